@@ -1,4 +1,24 @@
+use std::{env, fs, path::PathBuf};
+
+/// Flash pages at the end of the flash reserved for the storage, see STORAGE_PAGES
+const STORAGE_KB: u32 = 2;
+
 fn main() {
+    let flash_kb = if env::var_os("CARGO_FEATURE_STM32F103CB").is_some() {
+        128
+    } else {
+        64
+    };
+
+    // Put memory.x where the linker can find it, without the storage pages
+    let memory = format!(
+        "MEMORY\n{{\n    FLASH : ORIGIN = 0x08000000, LENGTH = {}K\n    RAM   : ORIGIN = 0x20000000, LENGTH = 20K\n}}\n",
+        flash_kb - STORAGE_KB
+    );
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("memory.x"), memory).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
@@ -0,0 +1,152 @@
+//! Tournament director's end of a shared RS-485 bus: polls the clocks one address at a time and
+//! sends commands to all of them at once.
+
+use std::io::{self, BufRead, Write};
+
+use chessclock_protocol::{self as protocol, Address, Command, Status, Telemetry};
+
+use crate::clock::ClockError;
+
+pub struct Bus<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> Bus<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Bus { reader, writer }
+    }
+
+    /// Sends the command to every clock on the bus, none of them answers
+    pub fn broadcast(&mut self, command: &Command) -> Result<(), ClockError> {
+        self.write(Address::All, command)
+    }
+
+    /// State of the clock at the address, `None` when nothing answered before the timeout
+    pub fn poll(&mut self, address: u8) -> Result<Option<Status>, ClockError> {
+        self.write(Address::Clock(address), &Command::State)?;
+        loop {
+            match self.receive(address) {
+                Ok(Telemetry::State(status)) => return Ok(Some(status)),
+                Ok(Telemetry::Error(err)) => return Err(ClockError::Rejected(err)),
+                Ok(_) => {}
+                Err(ClockError::Io(err)) if err.kind() == io::ErrorKind::TimedOut => {
+                    return Ok(None)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Next line from the clock at the address, late answers of other clocks are skipped
+    fn receive(&mut self, address: u8) -> Result<Telemetry, ClockError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(ClockError::Disconnected);
+            }
+            let Some((Address::Clock(from), line)) = protocol::split_address(line.trim_end())
+            else {
+                continue;
+            };
+            if from != address {
+                continue;
+            }
+            if let Ok(telemetry) = protocol::decode_telemetry(line) {
+                return Ok(telemetry);
+            }
+        }
+    }
+
+    fn write(&mut self, address: Address, command: &Command) -> Result<(), ClockError> {
+        let line = protocol::with_address(address, &protocol::encode_command(command));
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor};
+
+    use chessclock_protocol::{Config, DecodeError, IncrementKind, PageKind, Side};
+
+    use super::*;
+    use crate::simulated::SimulatedBus;
+
+    fn simulated(clocks: u8) -> Bus<BufReader<SimulatedBus>, SimulatedBus> {
+        let bus = SimulatedBus::new(clocks);
+        Bus::new(BufReader::new(bus.clone()), bus)
+    }
+
+    #[test]
+    fn every_clock_answers_at_its_own_address() {
+        let mut bus = simulated(3);
+        for address in 1..=3 {
+            let status = bus.poll(address).unwrap().unwrap();
+            assert_eq!(status.page, PageKind::Welcome);
+        }
+        // Nobody is there, the poll times out
+        assert!(bus.poll(4).unwrap().is_none());
+        assert!(bus.poll(32).unwrap().is_none());
+    }
+
+    #[test]
+    fn broadcasts_reach_every_clock_without_answers() {
+        let mut bus = simulated(2);
+        let config = Config {
+            left_secs: 600,
+            right_secs: 600,
+            left_kind: IncrementKind::Increment,
+            right_kind: IncrementKind::Increment,
+            left_increment_secs: 5,
+            right_increment_secs: 5,
+        };
+        bus.broadcast(&Command::Config(config)).unwrap();
+        bus.broadcast(&Command::Start(Side::Right)).unwrap();
+        for address in 1..=2 {
+            let status = bus.poll(address).unwrap().unwrap();
+            assert_eq!(status.page, PageKind::Game);
+            assert_eq!(status.turn, Side::Right);
+            assert_eq!(status.left_ms, 600_000);
+        }
+        bus.broadcast(&Command::Pause).unwrap();
+        assert!(bus.poll(2).unwrap().unwrap().paused);
+    }
+
+    #[test]
+    fn commands_go_out_with_the_address() {
+        let mut written = Vec::new();
+        let mut bus = Bus::new(Cursor::new(Vec::new()), &mut written);
+        bus.broadcast(&Command::Pause).unwrap();
+        assert!(matches!(bus.poll(5), Err(ClockError::Disconnected)));
+        assert_eq!(written, b"@* PAUSE\n@5 STATE\n");
+    }
+
+    #[test]
+    fn lines_of_other_clocks_are_skipped() {
+        let answers = concat!(
+            "@2 STATE,GAME,L,0,1000,2000,0\n",
+            "STATE,GAME,L,0,3000,4000,0\n",
+            "@3 OK\n",
+            "@3 STATE,MENU,R,0,5000,6000,0\n",
+        );
+        let mut bus = Bus::new(Cursor::new(answers), Vec::new());
+        let status = bus.poll(3).unwrap().unwrap();
+        assert_eq!(status.page, PageKind::Menu);
+        assert_eq!((status.left_ms, status.right_ms), (5000, 6000));
+    }
+
+    #[test]
+    fn errors_of_the_clock_are_returned() {
+        let error = Telemetry::Error(DecodeError::TooLong);
+        let answer = protocol::with_address(Address::Clock(1), &protocol::encode(&error));
+        let mut bus = Bus::new(Cursor::new(answer.as_bytes()), Vec::new());
+        assert!(matches!(
+            bus.poll(1),
+            Err(ClockError::Rejected(DecodeError::TooLong))
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! Conversation with the clock over the serial protocol, on any pair of byte streams.
+
+use std::io::{self, BufRead, Write};
+
+use chessclock_protocol::{
+    self as protocol, Command, Config, DecodeError, GameRecord, Name, Status, Telemetry,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClockError {
+    #[error("serial port error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("the clock rejected the command: {0:?}")]
+    Rejected(DecodeError),
+
+    #[error("the clock closed the connection")]
+    Disconnected,
+}
+
+pub struct Clock<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> Clock<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Clock { reader, writer }
+    }
+
+    /// Sends the command and waits until the clock accepted it
+    pub fn send(&mut self, command: &Command) -> Result<(), ClockError> {
+        self.write(command)?;
+        loop {
+            match self.receive()? {
+                Telemetry::Ok => return Ok(()),
+                Telemetry::Error(err) => return Err(ClockError::Rejected(err)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Next line from the clock, lines that don't decode are skipped
+    pub fn receive(&mut self) -> Result<Telemetry, ClockError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(ClockError::Disconnected);
+            }
+            if let Ok(telemetry) = protocol::decode_telemetry(line.trim_end()) {
+                return Ok(telemetry);
+            }
+        }
+    }
+
+    /// Next line from the clock, however long it takes
+    pub fn wait(&mut self) -> Result<Telemetry, ClockError> {
+        loop {
+            match self.receive() {
+                // Nothing happens on the clock while it sits on a page
+                Err(ClockError::Io(err)) if err.kind() == io::ErrorKind::TimedOut => continue,
+                result => return result,
+            }
+        }
+    }
+
+    pub fn state(&mut self) -> Result<Status, ClockError> {
+        self.write(&Command::State)?;
+        loop {
+            match self.receive()? {
+                Telemetry::State(status) => return Ok(status),
+                Telemetry::Error(err) => return Err(ClockError::Rejected(err)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Saved presets with their numbers
+    pub fn presets(&mut self) -> Result<Vec<(u8, Name, Config)>, ClockError> {
+        // The list has no end marker, the state asked for right after it ends it
+        self.write(&Command::ListPresets)?;
+        self.write(&Command::State)?;
+        let mut presets = Vec::new();
+        loop {
+            match self.receive()? {
+                Telemetry::Preset(number, name, config) => presets.push((number, name, config)),
+                Telemetry::State(_) => return Ok(presets),
+                Telemetry::Error(err) => return Err(ClockError::Rejected(err)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Finished games kept by the clock, the oldest first
+    pub fn games(&mut self) -> Result<Vec<GameRecord>, ClockError> {
+        // Ended by the state asked for after it, like the list of presets
+        self.write(&Command::ListGames)?;
+        self.write(&Command::State)?;
+        let mut games = Vec::new();
+        loop {
+            match self.receive()? {
+                Telemetry::Game(_, game) => games.push(game),
+                Telemetry::State(_) => return Ok(games),
+                Telemetry::Error(err) => return Err(ClockError::Rejected(err)),
+                _ => {}
+            }
+        }
+    }
+
+    fn write(&mut self, command: &Command) -> Result<(), ClockError> {
+        let line = protocol::encode_command(command);
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,396 @@
+//! Games recorded from the clock telemetry and written as CSV rows or PGN. The clock has no room
+//! to keep the moves of its games, so the tool records them while it follows the clock: every move
+//! with the times after it, and how the game ended. The games the clock keeps without their moves
+//! are written the same way.
+
+use std::fmt::Write;
+
+use chessclock_protocol::{GameRecord, PageKind, Side, Status, Telemetry};
+
+use crate::side_name;
+
+/// First line of CSV files
+pub const CSV_HEADER: &str = "game,move,side,left_ms,right_ms";
+/// First line of CSV files of the games kept by the clock
+pub const KEPT_CSV_HEADER: &str = "game,first,winner,moves,left_ms,right_ms";
+
+pub struct Recorder {
+    page: PageKind,
+    game: Option<Game>,
+}
+
+pub struct Game {
+    /// The player who moved first
+    pub white: Side,
+    pub start: Status,
+    pub moves: Vec<Move>,
+    /// `None` for games left before a flag fell
+    pub winner: Option<Side>,
+}
+
+pub struct Move {
+    pub side: Side,
+    pub left_ms: u32,
+    pub right_ms: u32,
+}
+
+impl Recorder {
+    /// A game already running when the recording starts is left out, its first moves are missing
+    pub fn new(status: Status) -> Self {
+        Recorder {
+            page: status.page,
+            game: None,
+        }
+    }
+
+    /// Follows the telemetry, returns the game it ended
+    pub fn apply(&mut self, telemetry: &Telemetry) -> Option<Game> {
+        match *telemetry {
+            Telemetry::State(status) => {
+                let previous = core::mem::replace(&mut self.page, status.page);
+                match status.page {
+                    PageKind::Game if previous != PageKind::Game => {
+                        self.game = Some(Game {
+                            white: status.turn,
+                            start: status,
+                            moves: Vec::new(),
+                            winner: None,
+                        });
+                    }
+                    // The result follows the game over page
+                    PageKind::Game | PageKind::GameOver => {}
+                    PageKind::Welcome | PageKind::Menu => return self.game.take(),
+                }
+            }
+            Telemetry::Turn(status) => {
+                if let Some(game) = &mut self.game {
+                    game.moves.push(Move {
+                        side: status.turn.other(),
+                        left_ms: status.left_ms,
+                        right_ms: status.right_ms,
+                    });
+                }
+            }
+            Telemetry::Result(winner) => {
+                let mut game = self.game.take()?;
+                game.winner = Some(winner);
+                return Some(game);
+            }
+            Telemetry::Tick(_)
+            | Telemetry::Flag(_)
+            | Telemetry::Ok
+            | Telemetry::Error(_)
+            | Telemetry::Preset(..)
+            | Telemetry::Game(..) => {}
+        }
+        None
+    }
+}
+
+impl Game {
+    /// A row for every move, then one with the winner, or `none`, in place of the move number
+    pub fn csv(&self, number: u32) -> String {
+        let mut rows = String::new();
+        for (index, mv) in self.moves.iter().enumerate() {
+            let _ = writeln!(
+                rows,
+                "{},{},{},{},{}",
+                number,
+                index / 2 + 1,
+                side_name(mv.side),
+                mv.left_ms,
+                mv.right_ms
+            );
+        }
+        let (left_ms, right_ms) = self.final_times();
+        let winner = self.winner.map_or("none", side_name);
+        let _ = writeln!(
+            rows,
+            "{},result,{},{},{}",
+            number, winner, left_ms, right_ms
+        );
+        rows
+    }
+
+    /// The clock only knows the times, so every move is a null move `--` with the clock of its
+    /// player in a `%clk` comment, for filling in the moves later
+    pub fn pgn(&self) -> String {
+        let result = match self.winner {
+            Some(winner) if winner == self.white => "1-0",
+            Some(_) => "0-1",
+            None => "*",
+        };
+        let mut pgn = pgn_tags(self.white, result);
+        let _ = writeln!(pgn);
+        let mut line = String::new();
+        for (index, mv) in self.moves.iter().enumerate() {
+            let clock = match mv.side {
+                Side::Left => mv.left_ms,
+                Side::Right => mv.right_ms,
+            };
+            let mut token = String::new();
+            if index % 2 == 0 {
+                let _ = write!(token, "{}. ", index / 2 + 1);
+            }
+            let _ = write!(token, "-- {{[%clk {}]}}", clk(clock));
+            push_token(&mut pgn, &mut line, &token);
+        }
+        push_token(&mut pgn, &mut line, result);
+        pgn.push_str(&line);
+        pgn.push_str("\n\n");
+        pgn
+    }
+
+    /// Times after the last move, the flagged player's is down to zero
+    fn final_times(&self) -> (u32, u32) {
+        let (left_ms, right_ms) = self
+            .moves
+            .last()
+            .map_or((self.start.left_ms, self.start.right_ms), |mv| {
+                (mv.left_ms, mv.right_ms)
+            });
+        match self.winner {
+            Some(Side::Left) => (left_ms, 0),
+            Some(Side::Right) => (0, right_ms),
+            None => (left_ms, right_ms),
+        }
+    }
+}
+
+/// Row of a game kept by the clock, the winner is `draw` for a draw
+pub fn kept_csv(number: u32, game: &GameRecord) -> String {
+    format!(
+        "{},{},{},{},{},{}\n",
+        number,
+        side_name(game.first),
+        game.winner.map_or("draw", side_name),
+        game.moves,
+        game.left_ms,
+        game.right_ms
+    )
+}
+
+/// A game kept by the clock, with a null move `--` for every move and the times it ended with in
+/// a comment
+pub fn kept_pgn(game: &GameRecord) -> String {
+    let result = match game.winner {
+        Some(winner) if winner == game.first => "1-0",
+        Some(_) => "0-1",
+        None => "1/2-1/2",
+    };
+    let mut pgn = pgn_tags(game.first, result);
+    let _ = writeln!(pgn, "[PlyCount \"{}\"]", game.moves);
+    let _ = writeln!(pgn);
+    let mut line = String::new();
+    for index in 0..game.moves as usize {
+        let token = match index % 2 {
+            0 => format!("{}. --", index / 2 + 1),
+            _ => "--".to_string(),
+        };
+        push_token(&mut pgn, &mut line, &token);
+    }
+    let times = format!(
+        "{{Left {}, right {}}}",
+        clk(game.left_ms),
+        clk(game.right_ms)
+    );
+    push_token(&mut pgn, &mut line, &times);
+    push_token(&mut pgn, &mut line, result);
+    pgn.push_str(&line);
+    pgn.push_str("\n\n");
+    pgn
+}
+
+/// The seven tag roster, `white` being the player who moved first
+fn pgn_tags(white: Side, result: &str) -> String {
+    let mut pgn = String::new();
+    let _ = writeln!(pgn, "[Event \"Chess clock game\"]");
+    let _ = writeln!(pgn, "[Site \"?\"]");
+    let _ = writeln!(pgn, "[Date \"????.??.??\"]");
+    let _ = writeln!(pgn, "[Round \"-\"]");
+    let _ = writeln!(pgn, "[White \"{} player\"]", capitalized(white));
+    let _ = writeln!(pgn, "[Black \"{} player\"]", capitalized(white.other()));
+    let _ = writeln!(pgn, "[Result \"{}\"]", result);
+    pgn
+}
+
+/// Adds the token to the movetext line, starting another past the 80 columns of export format
+fn push_token(pgn: &mut String, line: &mut String, token: &str) {
+    if !line.is_empty() && line.len() + 1 + token.len() > 80 {
+        pgn.push_str(line);
+        pgn.push('\n');
+        line.clear();
+    }
+    if !line.is_empty() {
+        line.push(' ');
+    }
+    line.push_str(token);
+}
+
+fn capitalized(side: Side) -> &'static str {
+    match side {
+        Side::Left => "Left",
+        Side::Right => "Right",
+    }
+}
+
+/// Hours, minutes and seconds of the `%clk` command
+fn clk(millis: u32) -> String {
+    let secs = millis / 1000;
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(page: PageKind, turn: Side, left_ms: u32, right_ms: u32) -> Status {
+        Status {
+            page,
+            turn,
+            paused: false,
+            left_ms,
+            right_ms,
+            delay_ms: 0,
+        }
+    }
+
+    /// Right moves first and flags on their second move
+    fn played() -> Vec<Telemetry> {
+        vec![
+            Telemetry::State(status(PageKind::Game, Side::Right, 300_000, 300_000)),
+            Telemetry::Tick(status(PageKind::Game, Side::Right, 300_000, 299_000)),
+            Telemetry::Turn(status(PageKind::Game, Side::Left, 300_000, 297_500)),
+            Telemetry::Turn(status(PageKind::Game, Side::Right, 295_000, 297_500)),
+            Telemetry::State(status(PageKind::GameOver, Side::Right, 295_000, 0)),
+            Telemetry::Flag(Side::Right),
+            Telemetry::Result(Side::Left),
+        ]
+    }
+
+    fn record(recorder: &mut Recorder, telemetry: &[Telemetry]) -> Vec<Game> {
+        telemetry.iter().filter_map(|t| recorder.apply(t)).collect()
+    }
+
+    #[test]
+    fn games_are_recorded_from_start_to_result() {
+        let mut recorder = Recorder::new(status(PageKind::Welcome, Side::Left, 300_000, 300_000));
+        let games = record(&mut recorder, &played());
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.white, Side::Right);
+        assert_eq!(game.winner, Some(Side::Left));
+        assert_eq!(game.moves.len(), 2);
+        assert_eq!(game.moves[0].side, Side::Right);
+        assert_eq!(game.moves[1].side, Side::Left);
+        assert_eq!(game.moves[1].left_ms, 295_000);
+    }
+
+    #[test]
+    fn games_left_halfway_have_no_winner() {
+        let mut recorder = Recorder::new(status(PageKind::Menu, Side::Left, 300_000, 300_000));
+        let mut telemetry = played();
+        telemetry.truncate(3);
+        telemetry.push(Telemetry::State(status(
+            PageKind::Welcome,
+            Side::Left,
+            300_000,
+            300_000,
+        )));
+        let games = record(&mut recorder, &telemetry);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].winner, None);
+        assert_eq!(games[0].moves.len(), 1);
+    }
+
+    #[test]
+    fn a_game_running_before_the_recording_is_left_out() {
+        let mut recorder = Recorder::new(status(PageKind::Game, Side::Right, 300_000, 300_000));
+        assert!(record(&mut recorder, &played()[1..]).is_empty());
+        assert_eq!(record(&mut recorder, &played()).len(), 1);
+    }
+
+    #[test]
+    fn pausing_stays_in_the_same_game() {
+        let mut recorder = Recorder::new(status(PageKind::Welcome, Side::Left, 300_000, 300_000));
+        let mut telemetry = played();
+        let mut paused = status(PageKind::Game, Side::Left, 300_000, 297_500);
+        paused.paused = true;
+        telemetry.insert(3, Telemetry::State(paused));
+        telemetry.insert(
+            4,
+            Telemetry::State(Status {
+                paused: false,
+                ..paused
+            }),
+        );
+        let games = record(&mut recorder, &telemetry);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].moves.len(), 2);
+    }
+
+    #[test]
+    fn csv_has_a_row_for_every_move_and_the_result() {
+        let mut recorder = Recorder::new(status(PageKind::Welcome, Side::Left, 300_000, 300_000));
+        let game = record(&mut recorder, &played()).remove(0);
+        assert_eq!(
+            game.csv(3),
+            "3,1,right,300000,297500\n3,1,left,295000,297500\n3,result,left,295000,0\n"
+        );
+    }
+
+    #[test]
+    fn pgn_has_null_moves_with_the_clock() {
+        let mut recorder = Recorder::new(status(PageKind::Welcome, Side::Left, 300_000, 300_000));
+        let game = record(&mut recorder, &played()).remove(0);
+        let pgn = game.pgn();
+        assert!(pgn.contains("[White \"Right player\"]\n[Black \"Left player\"]\n"));
+        assert!(pgn.contains("[Result \"0-1\"]\n"));
+        assert!(pgn.ends_with("\n1. -- {[%clk 0:04:57]} -- {[%clk 0:04:55]} 0-1\n\n"));
+    }
+
+    #[test]
+    fn long_games_wrap_at_80_columns() {
+        let moves = (0..60)
+            .map(|i| Move {
+                side: if i % 2 == 0 { Side::Left } else { Side::Right },
+                left_ms: 3_600_000,
+                right_ms: 3_600_000,
+            })
+            .collect();
+        let game = Game {
+            white: Side::Left,
+            start: status(PageKind::Game, Side::Left, 3_600_000, 3_600_000),
+            moves,
+            winner: None,
+        };
+        let pgn = game.pgn();
+        let movetext = pgn.split("\n\n").nth(1).unwrap();
+        assert!(movetext.lines().count() > 1);
+        assert!(movetext.lines().all(|line| line.len() <= 80));
+        assert_eq!(movetext.matches("-- {[%clk 1:00:00]}").count(), 60);
+        assert!(movetext.contains("30. --") && movetext.ends_with(" *"));
+    }
+    #[test]
+    fn kept_games_have_a_null_move_for_every_move() {
+        let game = GameRecord {
+            first: Side::Right,
+            winner: Some(Side::Right),
+            moves: 3,
+            left_ms: 0,
+            right_ms: 61_000,
+        };
+        assert_eq!(kept_csv(4, &game), "4,right,right,3,0,61000\n");
+        let pgn = kept_pgn(&game);
+        assert!(pgn.contains("[White \"Right player\"]\n[Black \"Left player\"]\n"));
+        assert!(pgn.contains("[Result \"1-0\"]\n[PlyCount \"3\"]\n"));
+        assert!(pgn.ends_with("\n1. -- -- 2. -- {Left 0:00:00, right 0:01:01} 1-0\n\n"));
+
+        let drawn = GameRecord {
+            winner: None,
+            ..game
+        };
+        assert!(kept_pgn(&drawn).ends_with(" 1/2-1/2\n\n"));
+        assert_eq!(kept_csv(5, &drawn), "5,right,draw,3,0,61000\n");
+    }
+}
@@ -0,0 +1,614 @@
+//! Configures the chess clock and follows its games from the computer, over the USB or UART
+//! serial port.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::TcpListener,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
+
+use chessclock_protocol::{Command, Name, PageKind, Side, Status, Telemetry, MAX_BUS_ADDRESS};
+
+use crate::{
+    bus::Bus,
+    clock::{Clock, ClockError},
+    game_log::Recorder,
+    overlay::{Feed, Overlay},
+    simulated::SimulatedBus,
+};
+
+mod bus;
+mod clock;
+mod game_log;
+mod overlay;
+mod simulated;
+mod time_control;
+
+const BAUD_RATE: u32 = 115_200;
+/// Longest wait for an answer, watching the clock waits forever
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// Wait for a clock on the bus, the ones that are off shouldn't hold up the poll for long
+const BUS_TIMEOUT: Duration = Duration::from_millis(200);
+const OVERLAY_ADDRESS: &str = "127.0.0.1:8080";
+const OVERLAY_FILE: &str = "chessclock.json";
+
+const USAGE: &str = "\
+usage: chessclock-cli <port> <command>
+       chessclock-cli sim:<clocks> bus <bus command>
+
+commands:
+  state                                  shows what the clock is doing
+  watch                                  follows the clock until interrupted
+  record <file>                          records the games into a CSV or PGN file until
+                                         interrupted
+  games <file>                           downloads the games kept by the clock into a CSV or
+                                         PGN file
+  config <time control> [<right>]        sets the time control of the next game
+  presets                                lists the saved presets
+  presets add <name> <time control> [<right>]
+  presets delete <number>
+  overlay [--listen <address>] [--json <file>]
+                                         serves the live game to streaming overlays
+  bus poll [<address>[-<address>]]       shows the state of the clocks on an RS-485 bus
+  bus config <time control> [<right>]    sets the time control on every clock of the bus
+  bus start <L|R>                        starts a game on every clock, the side's time running
+  bus pause / bus resume
+
+Time controls use the PGN notation <seconds>[+<increment>], <seconds>d<delay> for a simple
+delay or <seconds>b<delay> for Bronstein. The right player gets the left one's time control
+unless given their own, which can have another kind of increment, as in 600b15 300+5.
+
+Record follows the games from the computer and adds every game that ends to the file: PGN for
+.pgn files, with the clock of each move on a null move for entering the moves later, and CSV
+with the times after every move otherwise. Clocks built with the history feature keep their
+last 8 games without the times of the moves, games writes them to the file the same way.
+
+The overlay serves a page for browser sources at http://127.0.0.1:8080/, the state as JSON at
+/state.json and its updates on the /ws WebSocket, and keeps the state in chessclock.json.
+
+Bus commands talk to clocks built with the bus feature, polling every address from 1 to 32
+unless given some. The port sim:<clocks> is a bus of that many simulated clocks.";
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error("{0}")]
+    Usage(&'static str),
+
+    #[error(transparent)]
+    TimeControl(#[from] time_control::TimeControlError),
+
+    #[error("can't open the serial port: {0}")]
+    Port(#[from] serialport::Error),
+
+    #[error(transparent)]
+    Clock(#[from] ClockError),
+
+    #[error("overlay: {0}")]
+    Overlay(#[from] io::Error),
+
+    #[error("can't write {}: {err}", .path.display())]
+    File { path: PathBuf, err: io::Error },
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Usage(message)) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[&str]) -> Result<(), Error> {
+    let [port, command @ ..] = args else {
+        return Err(Error::Usage("missing serial port"));
+    };
+    // Checked before opening the port so mistakes don't wait for the clock
+    let command = parse_command(command)?;
+
+    if let Some(clocks) = port.strip_prefix("sim:") {
+        let clocks = clocks
+            .parse()
+            .ok()
+            .filter(|clocks| (1..=MAX_BUS_ADDRESS).contains(clocks))
+            .ok_or(Error::Usage("simulated buses have 1 to 32 clocks"))?;
+        let bus = SimulatedBus::new(clocks);
+        return execute(command, BufReader::new(bus.clone()), bus);
+    }
+
+    let timeout = match command {
+        CliCommand::Bus(_) => BUS_TIMEOUT,
+        _ => TIMEOUT,
+    };
+    let mut port = serialport::new(*port, BAUD_RATE).timeout(timeout).open()?;
+    // The USB port only sends to a terminal that set DTR, UARTs and ptys don't have the line
+    let _ = port.write_data_terminal_ready(true);
+    let reader = BufReader::new(port.try_clone()?);
+    execute(command, reader, port)
+}
+
+fn execute<R: BufRead, W: Write>(command: CliCommand, reader: R, writer: W) -> Result<(), Error> {
+    if let CliCommand::Bus(command) = command {
+        let mut bus = Bus::new(reader, writer);
+        match command {
+            BusCommand::Poll(addresses) => {
+                for address in addresses {
+                    match bus.poll(address)? {
+                        Some(status) => println!("{:>2}  {}", address, describe(&status)),
+                        None => println!("{:>2}  no answer", address),
+                    }
+                }
+            }
+            BusCommand::Broadcast(command) => bus.broadcast(&command)?,
+        }
+        return Ok(());
+    }
+
+    let mut clock = Clock::new(reader, writer);
+    match command {
+        CliCommand::State => print_status(&clock.state()?),
+        CliCommand::Watch => watch(&mut clock)?,
+        CliCommand::Record(file) => record(&mut clock, &file)?,
+        CliCommand::Games(file) => download_games(&mut clock, &file)?,
+        CliCommand::Overlay { listen, json } => {
+            let listener = TcpListener::bind(&listen)?;
+            println!("overlay on http://{}/, state in {}", listen, json.display());
+            serve_overlay(&mut clock, listener, &json)?
+        }
+        CliCommand::Send(command) => clock.send(&command)?,
+        CliCommand::ListPresets => {
+            for (number, name, config) in clock.presets()? {
+                println!(
+                    "{:>2}  {:<12}  {}",
+                    number,
+                    name.as_str(),
+                    time_control::format(&config)
+                );
+            }
+        }
+        CliCommand::Bus(_) => unreachable!("handled above"),
+    }
+    Ok(())
+}
+
+enum CliCommand {
+    State,
+    Watch,
+    Record(PathBuf),
+    Games(PathBuf),
+    ListPresets,
+    Overlay {
+        listen: String,
+        json: PathBuf,
+    },
+    /// Commands only answered with OK
+    Send(Command),
+    Bus(BusCommand),
+}
+
+enum BusCommand {
+    Poll(RangeInclusive<u8>),
+    /// Commands for every clock, which don't answer them
+    Broadcast(Command),
+}
+
+fn parse_command(args: &[&str]) -> Result<CliCommand, Error> {
+    let command = match args {
+        ["state"] => CliCommand::State,
+        ["watch"] => CliCommand::Watch,
+        ["record", file] => CliCommand::Record(PathBuf::from(file)),
+        ["games", file] => CliCommand::Games(PathBuf::from(file)),
+        ["config", left, right @ ..] if right.len() <= 1 => {
+            let config = time_control::parse(left, right.first().copied())?;
+            CliCommand::Send(Command::Config(config))
+        }
+        ["presets"] => CliCommand::ListPresets,
+        ["presets", "add", name, left, right @ ..] if right.len() <= 1 => {
+            let name = Name::new(name).ok_or(Error::Usage(
+                "preset names are up to 12 characters, without commas",
+            ))?;
+            let config = time_control::parse(left, right.first().copied())?;
+            CliCommand::Send(Command::AddPreset(name, config))
+        }
+        ["presets", "delete", number] => {
+            let number = number
+                .parse()
+                .map_err(|_| Error::Usage("preset numbers are the ones listed by presets"))?;
+            CliCommand::Send(Command::DeletePreset(number))
+        }
+        ["overlay", options @ ..] => {
+            let mut listen = OVERLAY_ADDRESS.to_string();
+            let mut json = PathBuf::from(OVERLAY_FILE);
+            for option in options.chunks(2) {
+                match option {
+                    ["--listen", address] => listen = address.to_string(),
+                    ["--json", file] => json = PathBuf::from(file),
+                    _ => return Err(Error::Usage("the overlay takes --listen and --json")),
+                }
+            }
+            CliCommand::Overlay { listen, json }
+        }
+        ["bus", command @ ..] => CliCommand::Bus(parse_bus_command(command)?),
+        [] => return Err(Error::Usage("missing command")),
+        _ => return Err(Error::Usage("unknown command or wrong arguments")),
+    };
+    Ok(command)
+}
+
+fn parse_bus_command(args: &[&str]) -> Result<BusCommand, Error> {
+    let command = match args {
+        ["poll"] => BusCommand::Poll(1..=MAX_BUS_ADDRESS),
+        ["poll", addresses] => {
+            let invalid = || Error::Usage("bus addresses go from 1 to 32");
+            let address = |text: &str| {
+                text.parse()
+                    .ok()
+                    .filter(|address| (1..=MAX_BUS_ADDRESS).contains(address))
+                    .ok_or_else(invalid)
+            };
+            let (first, last) = match addresses.split_once('-') {
+                Some((first, last)) => (address(first)?, address(last)?),
+                None => (address(addresses)?, address(addresses)?),
+            };
+            if first > last {
+                return Err(invalid());
+            }
+            BusCommand::Poll(first..=last)
+        }
+        ["config", left, right @ ..] if right.len() <= 1 => {
+            let config = time_control::parse(left, right.first().copied())?;
+            BusCommand::Broadcast(Command::Config(config))
+        }
+        ["start", side] => {
+            let side = match *side {
+                "L" | "l" => Side::Left,
+                "R" | "r" => Side::Right,
+                _ => return Err(Error::Usage("the side is L or R")),
+            };
+            BusCommand::Broadcast(Command::Start(side))
+        }
+        ["pause"] => BusCommand::Broadcast(Command::Pause),
+        ["resume"] => BusCommand::Broadcast(Command::Resume),
+        _ => return Err(Error::Usage("unknown bus command or wrong arguments")),
+    };
+    Ok(command)
+}
+
+/// Prints everything the clock reports until the connection is lost
+fn watch<R: io::BufRead, W: io::Write>(clock: &mut Clock<R, W>) -> Result<(), ClockError> {
+    print_status(&clock.state()?);
+    loop {
+        match clock.wait()? {
+            Telemetry::State(status) => print_status(&status),
+            Telemetry::Turn(status) => println!(
+                "{} to move  {}  {}",
+                side_name(status.turn),
+                format_time(status.left_ms),
+                format_time(status.right_ms)
+            ),
+            Telemetry::Tick(status) => println!(
+                "           {}  {}",
+                format_time(status.left_ms),
+                format_time(status.right_ms)
+            ),
+            Telemetry::Flag(side) => println!("{} flagged", side_name(side)),
+            Telemetry::Result(winner) => println!("{} wins on time", side_name(winner)),
+            Telemetry::Ok | Telemetry::Error(_) | Telemetry::Preset(..) | Telemetry::Game(..) => {}
+        }
+    }
+}
+
+/// Adds every game that ends to the file until the connection is lost, PGN for `.pgn` files
+/// and CSV otherwise
+fn record<R: io::BufRead, W: io::Write>(clock: &mut Clock<R, W>, path: &Path) -> Result<(), Error> {
+    let pgn = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pgn"));
+    let file_error = |err: io::Error| Error::File {
+        path: path.to_path_buf(),
+        err,
+    };
+    // CSV games are numbered on from the ones already in the file
+    let mut number = fs::read_to_string(path)
+        .map(|text| {
+            text.lines()
+                .filter(|row| row.split(',').nth(1) == Some("result"))
+                .count() as u32
+        })
+        .unwrap_or(0);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(file_error)?;
+    if !pgn && file.metadata().map_err(file_error)?.len() == 0 {
+        writeln!(file, "{}", game_log::CSV_HEADER).map_err(file_error)?;
+    }
+
+    let mut recorder = Recorder::new(clock.state()?);
+    loop {
+        if let Some(game) = recorder.apply(&clock.wait()?) {
+            number += 1;
+            let text = if pgn { game.pgn() } else { game.csv(number) };
+            file.write_all(text.as_bytes()).map_err(file_error)?;
+            println!("game {} recorded", number);
+        }
+    }
+}
+
+/// Writes the games kept by the clock to the file, PGN for `.pgn` files and CSV otherwise
+fn download_games<R: io::BufRead, W: io::Write>(
+    clock: &mut Clock<R, W>,
+    path: &Path,
+) -> Result<(), Error> {
+    let pgn = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pgn"));
+    let games = clock.games()?;
+    let mut text = if pgn {
+        String::new()
+    } else {
+        format!("{}\n", game_log::KEPT_CSV_HEADER)
+    };
+    for (number, game) in games.iter().enumerate() {
+        let game_text = if pgn {
+            game_log::kept_pgn(game)
+        } else {
+            game_log::kept_csv(number as u32 + 1, game)
+        };
+        text.push_str(&game_text);
+    }
+    fs::write(path, text).map_err(|err| Error::File {
+        path: path.to_path_buf(),
+        err,
+    })?;
+    println!("{} games downloaded", games.len());
+    Ok(())
+}
+
+/// Follows the clock into the overlay until the connection is lost
+fn serve_overlay<R: io::BufRead, W: io::Write>(
+    clock: &mut Clock<R, W>,
+    listener: TcpListener,
+    json_file: &Path,
+) -> Result<(), Error> {
+    let mut overlay = Overlay::new(clock.state()?);
+    let feed = Arc::new(Feed::default());
+    let json = overlay.json();
+    overlay::write_file(json_file, &json)?;
+    feed.publish(json);
+    overlay::serve(listener, feed.clone());
+    loop {
+        if overlay.apply(&clock.wait()?) {
+            let json = overlay.json();
+            overlay::write_file(json_file, &json)?;
+            feed.publish(json);
+        }
+    }
+}
+
+fn print_status(status: &Status) {
+    println!("{}", describe(status));
+}
+
+/// The page and times, or the result once the game is over
+fn describe(status: &Status) -> String {
+    let page = match status.page {
+        PageKind::Welcome => "welcome page",
+        PageKind::Menu => "menu",
+        PageKind::Game if status.paused => "game paused",
+        PageKind::Game => "game running",
+        PageKind::GameOver => {
+            return format!("game over, {} won on time", side_name(status.turn.other()))
+        }
+    };
+    format!(
+        "{}, {} to move  {}  {}",
+        page,
+        side_name(status.turn),
+        format_time(status.left_ms),
+        format_time(status.right_ms)
+    )
+}
+
+pub(crate) fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Left => "left",
+        Side::Right => "right",
+    }
+}
+
+/// Minutes and seconds like the clock shows them, tenths under ten seconds
+pub(crate) fn format_time(millis: u32) -> String {
+    if millis < 10_000 {
+        format!("{:>2}.{}", millis / 1000, millis / 100 % 10)
+    } else {
+        let secs = millis.div_ceil(1000);
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        thread::{self, JoinHandle},
+        time::Instant,
+    };
+
+    use chessclock_protocol::{self as protocol, Config, IncrementKind, Status};
+    use serialport::{SerialPort, TTYPort};
+
+    use super::*;
+    use crate::simulated::SimulatedClock;
+
+    /// Simulated clock at the master end of a pseudo-terminal, answering like the firmware over
+    /// USB. After the first state it sends the telemetry of `played`, and it goes away once `until`
+    /// holds or the terminal is closed.
+    struct FakeClock {
+        path: String,
+        /// Kept open so the terminal stays up between the commands
+        slave: TTYPort,
+        thread: JoinHandle<Vec<String>>,
+    }
+
+    impl FakeClock {
+        fn start(played: Vec<Telemetry>, until: impl Fn() -> bool + Send + 'static) -> Self {
+            let (master, slave) = TTYPort::pair().unwrap();
+            let path = slave.name().unwrap();
+            let thread = thread::spawn(move || {
+                let mut writer = master.try_clone_native().unwrap();
+                let mut reader = BufReader::new(master);
+                let mut clock = SimulatedClock::new();
+                let mut played = Some(played);
+                let mut received = Vec::new();
+                let started = Instant::now();
+                let mut line = String::new();
+                while started.elapsed() < Duration::from_secs(10) {
+                    if played.is_none() && until() {
+                        break;
+                    }
+                    match reader.read_line(&mut line) {
+                        Ok(_) if line.ends_with('\n') => {}
+                        Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+                        // The terminal hung up once every end of the cli was closed
+                        _ => break,
+                    }
+                    let command = protocol::decode(line.trim_end());
+                    received.push(line.trim_end().to_string());
+                    line.clear();
+                    let mut answers = clock.answer(command);
+                    if command == Ok(Command::State) {
+                        answers.extend(played.take().into_iter().flatten());
+                    }
+                    for answer in answers {
+                        writer
+                            .write_all(protocol::encode(&answer).as_bytes())
+                            .unwrap();
+                    }
+                }
+                received
+            });
+            FakeClock {
+                path,
+                slave,
+                thread,
+            }
+        }
+
+        /// Commands the clock received
+        fn stop(self) -> Vec<String> {
+            drop(self.slave);
+            self.thread.join().unwrap()
+        }
+    }
+
+    fn status(page: PageKind, turn: Side, left_ms: u32, right_ms: u32) -> Status {
+        Status {
+            page,
+            turn,
+            paused: false,
+            left_ms,
+            right_ms,
+            delay_ms: 0,
+        }
+    }
+
+    #[test]
+    fn commands_reach_the_clock_over_a_terminal() {
+        let clock = FakeClock::start(Vec::new(), || false);
+        run(&[&clock.path, "config", "600b15", "300+5"]).unwrap();
+        run(&[&clock.path, "state"]).unwrap();
+        let received = clock.stop();
+        let config = Config {
+            left_secs: 600,
+            right_secs: 300,
+            left_kind: IncrementKind::Bronstein,
+            right_kind: IncrementKind::Increment,
+            left_increment_secs: 15,
+            right_increment_secs: 5,
+        };
+        assert_eq!(
+            received,
+            [
+                protocol::encode_command(&Command::Config(config)).trim_end(),
+                "STATE"
+            ]
+        );
+    }
+
+    #[test]
+    fn presets_end_with_the_state_asked_after_them() {
+        let clock = FakeClock::start(Vec::new(), || false);
+        run(&[&clock.path, "presets"]).unwrap();
+        assert_eq!(
+            clock.stop(),
+            [
+                protocol::encode_command(&Command::ListPresets).trim_end(),
+                "STATE"
+            ]
+        );
+    }
+
+    #[test]
+    fn games_are_recorded_over_a_terminal() {
+        let dir = std::env::temp_dir().join(format!("chessclock-record-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("games.csv");
+        let _ = fs::remove_file(&csv);
+        let played = vec![
+            Telemetry::State(status(PageKind::Game, Side::Left, 300_000, 300_000)),
+            Telemetry::Turn(status(PageKind::Game, Side::Right, 298_000, 300_000)),
+            Telemetry::Turn(status(PageKind::Game, Side::Left, 298_000, 296_000)),
+            Telemetry::State(status(PageKind::GameOver, Side::Left, 0, 296_000)),
+            Telemetry::Flag(Side::Left),
+            Telemetry::Result(Side::Right),
+        ];
+        let written = csv.clone();
+        let clock = FakeClock::start(played, move || {
+            fs::read_to_string(&written).is_ok_and(|text| text.contains(",result,"))
+        });
+        // Recording goes on until the clock goes away
+        assert!(run(&[&clock.path, "record", csv.to_str().unwrap()]).is_err());
+        assert_eq!(clock.stop(), ["STATE"]);
+        assert_eq!(
+            fs::read_to_string(&csv).unwrap(),
+            "game,move,side,left_ms,right_ms\n\
+             1,1,left,298000,300000\n\
+             1,1,right,298000,296000\n\
+             1,result,right,0,296000\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn games_kept_by_the_clock_are_downloaded() {
+        let dir = std::env::temp_dir().join(format!("chessclock-games-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("kept.csv");
+        let answers = "OK\nGAME,1,L,R,41,0,12000\nGAME,2,R,D,80,5000,7000\nOK\n\
+                       STATE,WELCOME,L,0,300000,300000,0\n";
+        let mut written = Vec::new();
+        let mut clock = Clock::new(answers.as_bytes(), &mut written);
+        download_games(&mut clock, &csv).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), "GAMES\nSTATE\n");
+        assert_eq!(
+            fs::read_to_string(&csv).unwrap(),
+            "game,first,winner,moves,left_ms,right_ms\n\
+             1,left,right,41,0,12000\n\
+             2,right,draw,80,5000,7000\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+//! Live state of the game for broadcast overlays, followed from the clock telemetry and served
+//! as JSON over HTTP and a WebSocket, and written to a file.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+
+use chessclock_protocol::{PageKind, Side, Status, Telemetry};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+use crate::{format_time, side_name};
+
+/// Page of the browser source, it renders the WebSocket feed
+const PAGE: &str = include_str!("overlay.html");
+
+pub struct Overlay {
+    status: Status,
+}
+
+impl Overlay {
+    pub fn new(status: Status) -> Self {
+        Overlay { status }
+    }
+
+    /// Follows the telemetry, returns whether the overlay changed
+    pub fn apply(&mut self, telemetry: &Telemetry) -> bool {
+        let previous = self.status;
+        match *telemetry {
+            Telemetry::State(status) => self.status = status,
+            Telemetry::Turn(status) => {
+                self.status = Status {
+                    page: PageKind::Game,
+                    paused: false,
+                    ..status
+                }
+            }
+            // Ticks only carry the times, the turn and pause come from the other lines
+            Telemetry::Tick(status) => {
+                self.status.left_ms = status.left_ms;
+                self.status.right_ms = status.right_ms;
+                self.status.delay_ms = status.delay_ms;
+            }
+            Telemetry::Flag(side) => {
+                self.status.page = PageKind::GameOver;
+                self.status.turn = side;
+                self.status.delay_ms = 0;
+                match side {
+                    Side::Left => self.status.left_ms = 0,
+                    Side::Right => self.status.right_ms = 0,
+                }
+            }
+            Telemetry::Ok
+            | Telemetry::Error(_)
+            | Telemetry::Result(_)
+            | Telemetry::Preset(..)
+            | Telemetry::Game(..) => {}
+        }
+        self.status != previous
+    }
+
+    /// The state as a JSON object, the turn is the player who flagged once the game is over
+    pub fn json(&self) -> String {
+        let status = &self.status;
+        let stage = match status.page {
+            PageKind::Welcome => "welcome",
+            PageKind::Menu => "menu",
+            PageKind::Game => "game",
+            PageKind::GameOver => "game_over",
+        };
+        format!(
+            concat!(
+                "{{\"stage\":\"{}\",\"turn\":\"{}\",\"paused\":{},",
+                "\"left_ms\":{},\"right_ms\":{},\"delay_ms\":{},",
+                "\"left\":\"{}\",\"right\":\"{}\"}}"
+            ),
+            stage,
+            side_name(status.turn),
+            status.paused,
+            status.left_ms,
+            status.right_ms,
+            status.delay_ms,
+            format_time(status.left_ms).trim_start(),
+            format_time(status.right_ms).trim_start(),
+        )
+    }
+}
+
+/// Latest JSON and the WebSockets waiting for the next one, shared with the server threads
+#[derive(Default)]
+pub struct Feed {
+    json: Mutex<String>,
+    sockets: Mutex<Vec<WebSocket<TcpStream>>>,
+}
+
+impl Feed {
+    /// Sends the JSON to every WebSocket, dropping the closed ones
+    pub fn publish(&self, json: String) {
+        lock(&self.sockets).retain_mut(|socket| socket.send(Message::text(json.as_str())).is_ok());
+        *lock(&self.json) = json;
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A thread that panicked can't leave the JSON or the socket list half written
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Serves `/` with the overlay page, `/state.json` and the `/ws` WebSocket in the background
+pub fn serve(listener: TcpListener, feed: Arc<Feed>) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let feed = feed.clone();
+            thread::spawn(move || {
+                if let Err(err) = handle(stream, &feed) {
+                    eprintln!("overlay client: {}", err);
+                }
+            });
+        }
+    });
+}
+
+fn handle(mut stream: TcpStream, feed: &Feed) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+    let mut key = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    match (path.as_str(), key) {
+        ("/ws", Some(key)) => {
+            write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                derive_accept_key(key.as_bytes())
+            )?;
+            let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+            // Holding the list while sending keeps the first message ahead of the next publish
+            let mut sockets = lock(&feed.sockets);
+            let json = lock(&feed.json).clone();
+            socket.send(Message::text(json)).map_err(io::Error::other)?;
+            sockets.push(socket);
+            Ok(())
+        }
+        ("/state.json", _) => {
+            let json = lock(&feed.json).clone();
+            respond(&mut stream, "200 OK", "application/json", &json)
+        }
+        ("/", _) => respond(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Replaces the file at once, so programs reading it never see half a JSON object
+pub fn write_file(path: &Path, json: &str) -> io::Result<()> {
+    let mut temporary = PathBuf::from(path);
+    temporary.as_mut_os_string().push(".tmp");
+    fs::write(&temporary, json)?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::SocketAddr};
+
+    use chessclock_protocol as protocol;
+
+    use super::*;
+
+    fn status(page: PageKind, turn: Side, paused: bool, left_ms: u32, right_ms: u32) -> Status {
+        Status {
+            page,
+            turn,
+            paused,
+            left_ms,
+            right_ms,
+            delay_ms: 0,
+        }
+    }
+
+    /// Telemetry of a short game as the firmware sends it, from the changes between its states
+    fn simulated_game() -> Vec<Telemetry> {
+        let states = [
+            status(PageKind::Welcome, Side::Left, false, 300_000, 300_000),
+            status(PageKind::Game, Side::Left, false, 300_000, 300_000),
+            status(PageKind::Game, Side::Left, false, 299_000, 300_000),
+            status(PageKind::Game, Side::Right, false, 298_500, 300_000),
+            status(PageKind::Game, Side::Right, true, 298_500, 295_000),
+            status(PageKind::Game, Side::Right, false, 298_500, 295_000),
+            status(PageKind::Game, Side::Right, false, 298_500, 9_400),
+            status(PageKind::GameOver, Side::Right, false, 298_500, 0),
+        ];
+        states
+            .windows(2)
+            .flat_map(|pair| protocol::changes(&pair[0], &pair[1]).collect::<Vec<_>>())
+            .collect()
+    }
+
+    /// JSON after every line of the telemetry that changed the overlay
+    fn followed(telemetry: &[Telemetry]) -> Vec<String> {
+        let mut overlay = Overlay::new(status(PageKind::Welcome, Side::Left, false, 0, 0));
+        let mut json = Vec::new();
+        for line in telemetry {
+            if overlay.apply(line) {
+                json.push(overlay.json());
+            }
+        }
+        json
+    }
+
+    #[test]
+    fn the_overlay_follows_a_game() {
+        let json = followed(&simulated_game());
+        assert_eq!(
+            json[0],
+            concat!(
+                "{\"stage\":\"game\",\"turn\":\"left\",\"paused\":false,",
+                "\"left_ms\":300000,\"right_ms\":300000,\"delay_ms\":0,",
+                "\"left\":\"5:00\",\"right\":\"5:00\"}"
+            )
+        );
+        assert!(json[2].contains("\"turn\":\"right\""));
+        assert!(json[3].contains("\"paused\":true"));
+        assert!(json[5].contains("\"right\":\"9.4\""));
+        assert_eq!(
+            json.last().unwrap(),
+            concat!(
+                "{\"stage\":\"game_over\",\"turn\":\"right\",\"paused\":false,",
+                "\"left_ms\":298500,\"right_ms\":0,\"delay_ms\":0,",
+                "\"left\":\"4:59\",\"right\":\"0.0\"}"
+            )
+        );
+    }
+
+    #[test]
+    fn lines_without_news_change_nothing() {
+        let mut overlay = Overlay::new(status(PageKind::Game, Side::Left, false, 1000, 2000));
+        assert!(!overlay.apply(&Telemetry::Ok));
+        assert!(!overlay.apply(&Telemetry::Result(Side::Left)));
+        assert!(!overlay.apply(&Telemetry::Tick(status(
+            PageKind::Game,
+            Side::Left,
+            false,
+            1000,
+            2000
+        ))));
+    }
+
+    fn serving(json: &str) -> (SocketAddr, Arc<Feed>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let feed = Arc::new(Feed::default());
+        feed.publish(json.to_string());
+        serve(listener, feed.clone());
+        (address, feed)
+    }
+
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: overlay\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn the_state_is_served_over_http() {
+        let (address, _feed) = serving("{\"stage\":\"menu\"}");
+        let response = get(address, "/state.json");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"stage\":\"menu\"}"));
+        assert!(get(address, "/").contains("<html"));
+        assert!(get(address, "/favicon.ico").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn websockets_get_the_state_and_every_change() {
+        let json = followed(&simulated_game());
+        let (address, feed) = serving(&json[0]);
+        let stream = TcpStream::connect(address).unwrap();
+        let (mut socket, _) = tungstenite::client(format!("ws://{}/ws", address), stream).unwrap();
+        assert_eq!(socket.read().unwrap(), Message::text(json[0].as_str()));
+        for json in &json[1..] {
+            feed.publish(json.clone());
+            assert_eq!(socket.read().unwrap(), Message::text(json.as_str()));
+        }
+    }
+
+    #[test]
+    fn the_file_is_replaced_whole() {
+        let path =
+            std::env::temp_dir().join(format!("chessclock-overlay-{}.json", std::process::id()));
+        write_file(&path, "{\"stage\":\"welcome\"}").unwrap();
+        write_file(&path, "{\"stage\":\"menu\"}").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"stage\":\"menu\"}");
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        assert!(!Path::new(&temporary).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+//! Bus of simulated clocks, for trying the bus commands and the tools built on them without the
+//! hardware. The clocks answer like the firmware does on a bus, and run a game for as long as
+//! they are asked: their times don't go down. A single clock also stands in for the device at the
+//! other end of a serial port in the tests.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{self, Read, Write},
+    rc::Rc,
+};
+
+use chessclock_protocol::{
+    self as protocol, Address, Command, Config, DecodeError, IncrementKind, PageKind, Side, Status,
+    Telemetry,
+};
+
+/// Both ends of the bus, clones share the clocks
+#[derive(Clone)]
+pub struct SimulatedBus {
+    inner: Rc<RefCell<Inner>>,
+}
+
+struct Inner {
+    /// The clock at address `n` is at `n - 1`
+    clocks: Vec<SimulatedClock>,
+    /// Start of a line not complete yet
+    received: Vec<u8>,
+    answers: VecDeque<u8>,
+}
+
+pub struct SimulatedClock {
+    config: Config,
+    status: Status,
+}
+
+impl SimulatedBus {
+    /// Clocks at the addresses from 1 to `clocks`, on the welcome page with a five minute game
+    pub fn new(clocks: u8) -> Self {
+        let clocks = (1..=clocks).map(|_| SimulatedClock::new()).collect();
+        SimulatedBus {
+            inner: Rc::new(RefCell::new(Inner {
+                clocks,
+                received: Vec::new(),
+                answers: VecDeque::new(),
+            })),
+        }
+    }
+}
+
+impl Read for SimulatedBus {
+    /// Times out like a serial port once every answer was read
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.answers.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let len = buf.len().min(inner.answers.len());
+        for (byte, answer) in buf.iter_mut().zip(inner.answers.drain(..len)) {
+            *byte = answer;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for SimulatedBus {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        for &byte in buf {
+            if byte == b'\n' {
+                let line = String::from_utf8_lossy(&inner.received).into_owned();
+                inner.received.clear();
+                inner.handle_line(line.trim_end());
+            } else {
+                inner.received.push(byte);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Inner {
+    fn handle_line(&mut self, line: &str) {
+        let Some((address, command)) = protocol::split_address(line) else {
+            return;
+        };
+        let command = protocol::decode(command);
+        for (clock, own) in self.clocks.iter_mut().zip(1..) {
+            let answers = match (address, command) {
+                (Address::All, Ok(command)) => {
+                    clock.handle(command);
+                    continue;
+                }
+                (Address::All, Err(_)) => continue,
+                (Address::Clock(to), _) if to != own => continue,
+                (_, command) => clock.answer(command),
+            };
+            for answer in answers {
+                let answer =
+                    protocol::with_address(Address::Clock(own), &protocol::encode(&answer));
+                self.answers.extend(answer.as_bytes());
+            }
+        }
+    }
+}
+
+impl SimulatedClock {
+    /// On the welcome page with a five minute game
+    pub fn new() -> Self {
+        let config = Config {
+            left_secs: 300,
+            right_secs: 300,
+            left_kind: IncrementKind::SuddenDeath,
+            right_kind: IncrementKind::SuddenDeath,
+            left_increment_secs: 0,
+            right_increment_secs: 0,
+        };
+        SimulatedClock {
+            config,
+            status: Status {
+                page: PageKind::Welcome,
+                turn: Side::Left,
+                paused: false,
+                left_ms: config.left_secs * 1000,
+                right_ms: config.right_secs * 1000,
+                delay_ms: 0,
+            },
+        }
+    }
+
+    /// Answers to a command sent to this clock alone, in the order the firmware sends them
+    pub fn answer(&mut self, command: Result<Command, DecodeError>) -> Vec<Telemetry> {
+        match command {
+            Ok(command) => match self.handle(command) {
+                Some(answer) => vec![Telemetry::Ok, answer],
+                None => vec![Telemetry::Ok],
+            },
+            Err(err) => vec![Telemetry::Error(err)],
+        }
+    }
+
+    /// Carries the command out, returns what the clock reports besides OK
+    fn handle(&mut self, command: Command) -> Option<Telemetry> {
+        let status = &mut self.status;
+        match command {
+            Command::Config(config) => {
+                self.config = config;
+                if status.page != PageKind::Game {
+                    status.left_ms = config.left_secs * 1000;
+                    status.right_ms = config.right_secs * 1000;
+                }
+            }
+            Command::Start(side) => {
+                status.page = PageKind::Game;
+                status.turn = side;
+                status.paused = false;
+                status.left_ms = self.config.left_secs * 1000;
+                status.right_ms = self.config.right_secs * 1000;
+            }
+            Command::Pause | Command::Resume if status.page == PageKind::Game => {
+                status.paused = command == Command::Pause;
+            }
+            Command::State => return Some(Telemetry::State(*status)),
+            _ => {}
+        }
+        None
+    }
+}
@@ -0,0 +1,98 @@
+//! Time controls in the notation of the PGN TimeControl tag: `<seconds>` for sudden death and
+//! `<seconds>+<increment>` for a Fischer increment.
+//!
+//! PGN has no notation for the delay modes, `<seconds>d<delay>` is a simple delay and
+//! `<seconds>b<delay>` Bronstein. Move based periods and sandclock aren't supported by the clock.
+
+use chessclock_protocol::{Config, IncrementKind};
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum TimeControlError {
+    #[error("invalid time control {0:?}, expected <seconds>[+<increment>], <seconds>d<delay> or <seconds>b<delay>")]
+    Invalid(String),
+
+    #[error(
+        "time control {0:?} has move based periods or sandclock, the clock only has one period"
+    )]
+    Unsupported(String),
+}
+
+#[derive(Clone, Copy)]
+struct Period {
+    secs: u32,
+    kind: IncrementKind,
+    increment_secs: u32,
+}
+
+/// Parses the time control of both players, the right player gets the left one's without their own
+pub fn parse(left: &str, right: Option<&str>) -> Result<Config, TimeControlError> {
+    let left = parse_period(left)?;
+    let right = match right {
+        Some(right) => parse_period(right)?,
+        None => left,
+    };
+    Ok(Config {
+        left_secs: left.secs,
+        right_secs: right.secs,
+        left_kind: left.kind,
+        right_kind: right.kind,
+        left_increment_secs: left.increment_secs,
+        right_increment_secs: right.increment_secs,
+    })
+}
+
+fn parse_period(text: &str) -> Result<Period, TimeControlError> {
+    if text.contains(['/', ':', '*']) {
+        return Err(TimeControlError::Unsupported(text.to_string()));
+    }
+    let invalid = || TimeControlError::Invalid(text.to_string());
+    let (secs, kind, increment) = match text.find(['+', 'd', 'b']) {
+        Some(i) => {
+            let kind = match text.as_bytes()[i] {
+                b'+' => IncrementKind::Increment,
+                b'd' => IncrementKind::Delay,
+                _ => IncrementKind::Bronstein,
+            };
+            (&text[..i], kind, &text[i + 1..])
+        }
+        None => (text, IncrementKind::SuddenDeath, "0"),
+    };
+    let secs: u32 = secs.parse().map_err(|_| invalid())?;
+    let increment_secs = increment.parse().map_err(|_| invalid())?;
+    if secs == 0 {
+        return Err(invalid());
+    }
+    Ok(Period {
+        secs,
+        kind,
+        increment_secs,
+    })
+}
+
+/// Formats the time control of both players, the right one only when it differs
+pub fn format(config: &Config) -> String {
+    let left = format_period(
+        config.left_secs,
+        config.left_kind,
+        config.left_increment_secs,
+    );
+    let right = format_period(
+        config.right_secs,
+        config.right_kind,
+        config.right_increment_secs,
+    );
+    if left == right {
+        left
+    } else {
+        format!("{} {}", left, right)
+    }
+}
+
+fn format_period(secs: u32, kind: IncrementKind, increment_secs: u32) -> String {
+    match kind {
+        IncrementKind::SuddenDeath => secs.to_string(),
+        IncrementKind::Increment => format!("{}+{}", secs, increment_secs),
+        IncrementKind::Delay => format!("{}d{}", secs, increment_secs),
+        IncrementKind::Bronstein => format!("{}b{}", secs, increment_secs),
+    }
+}
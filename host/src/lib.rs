@@ -0,0 +1,73 @@
+//! The firmware modules that run the same on the computer, for the tests.
+//!
+//! The hardware is left out: the entry point and the peripherals in `main.rs`, the STOP mode, the
+//! USB port and the RS-485 transceiver. Time runs on the mock driver of embassy-time, which only
+//! moves on when a test advances it.
+
+// Whatever only main.rs uses is dead here
+#![allow(dead_code)]
+
+use chessclock_protocol as protocol;
+
+#[path = "../../src/app.rs"]
+mod app;
+#[cfg(feature = "armageddon")]
+#[path = "../../src/armageddon.rs"]
+mod armageddon;
+#[path = "../../src/aux.rs"]
+mod aux;
+#[path = "../../src/battery.rs"]
+mod battery;
+#[cfg(feature = "dgt")]
+#[path = "../../src/dgt.rs"]
+mod dgt;
+#[path = "../../src/display.rs"]
+mod display;
+#[path = "../../src/effect.rs"]
+mod effect;
+#[path = "../../src/error.rs"]
+mod error;
+#[path = "../../src/game.rs"]
+mod game;
+#[path = "../../src/handicap.rs"]
+mod handicap;
+#[cfg(feature = "history")]
+#[path = "../../src/history.rs"]
+mod history;
+#[path = "../../src/keyboard.rs"]
+mod keyboard;
+#[path = "../../src/lcd.rs"]
+mod lcd;
+#[cfg(feature = "link")]
+#[path = "../../src/link.rs"]
+mod link;
+#[cfg(feature = "match")]
+#[path = "../../src/match_play.rs"]
+mod match_play;
+#[path = "../../src/menu.rs"]
+mod menu;
+#[cfg(feature = "odds")]
+#[path = "../../src/odds.rs"]
+mod odds;
+#[path = "../../src/presets.rs"]
+mod presets;
+#[path = "../../src/result.rs"]
+mod result;
+#[path = "../../src/resume.rs"]
+mod resume;
+#[path = "../../src/settings.rs"]
+mod settings;
+#[path = "../../src/storage.rs"]
+mod storage;
+#[cfg(feature = "table")]
+#[path = "../../src/table.rs"]
+mod table;
+#[path = "../../src/tasks.rs"]
+mod tasks;
+#[cfg(test)]
+mod testing;
+
+#[cfg(all(feature = "dgt", feature = "link"))]
+compile_error!("The DGT emulation and the bughouse link both need USART1, enable only one");
+#[cfg(all(feature = "bus", any(feature = "dgt", feature = "link")))]
+compile_error!("The RS-485 bus needs USART1 for itself, it can't go with dgt or link");
@@ -0,0 +1,400 @@
+//! Fakes of the hardware and helpers to drive the tasks in the tests.
+
+use core::{
+    cell::{Cell, RefCell},
+    convert::Infallible,
+    future::Future,
+    task::Poll,
+};
+use std::{
+    collections::VecDeque,
+    rc::Rc,
+    sync::{Mutex, MutexGuard},
+};
+
+use embassy_futures::{
+    block_on,
+    select::{select, Either},
+    yield_now,
+};
+use embassy_time::{Duration, MockDriver};
+use embedded_hal::digital::ErrorType;
+use embedded_hal_async::digital::Wait;
+use embedded_storage::nor_flash::{NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+#[cfg(feature = "history")]
+use crate::history::History;
+use crate::{
+    app::{AppState, Page},
+    menu::GameConfig,
+    presets::UserPresets,
+    settings::Settings,
+    storage::PAGE_SIZE,
+};
+
+/// Step of the simulated time, short enough for the debounce times
+const STEP: Duration = Duration::from_millis(10);
+
+/// The mock driver is shared by the whole test binary, the tests using it take turns
+static TIME: Mutex<()> = Mutex::new(());
+
+/// Takes the mock driver for the test, starting again from zero
+pub fn lock_time() -> MutexGuard<'static, ()> {
+    let guard = TIME.lock().unwrap_or_else(|err| err.into_inner());
+    MockDriver::get().reset();
+    guard
+}
+
+/// Lets the time pass in small steps, with the other futures running in between
+pub async fn advance(duration: Duration) {
+    let mut left = duration;
+    while left.as_ticks() != 0 {
+        let step = left.min(STEP);
+        MockDriver::get().advance(step);
+        left -= step;
+        settle().await;
+    }
+}
+
+/// Gives the other futures a chance to run until they are waiting
+pub async fn settle() {
+    for _ in 0..10 {
+        yield_now().await;
+    }
+}
+
+/// Runs a task, which never returns, until the script driving it is done
+pub fn run<T>(task: impl Future, script: impl Future<Output = T>) -> T {
+    match block_on(select(script, task)) {
+        Either::First(result) => result,
+        Either::Second(_) => panic!("The task returned"),
+    }
+}
+
+/// Input pin of a button, high when released like the pulled up pins of the clock
+pub struct FakePin<'a>(pub &'a Cell<bool>);
+
+impl FakePin<'_> {
+    async fn wait_for(&self, high: bool) {
+        core::future::poll_fn(|cx| {
+            if self.0.get() == high {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl ErrorType for FakePin<'_> {
+    type Error = Infallible;
+}
+
+impl Wait for FakePin<'_> {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_for(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_for(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(false).await;
+        self.wait_for(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(true).await;
+        self.wait_for(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        let level = self.0.get();
+        self.wait_for(!level).await;
+        Ok(())
+    }
+}
+
+/// State of a clock fresh from the factory, on the welcome page
+pub fn app_state() -> AppState {
+    AppState {
+        game_config: GameConfig::default(),
+        user_presets: UserPresets::default(),
+        settings: Settings::default(),
+        #[cfg(feature = "history")]
+        history: History::new(),
+        page: Page::Welcome,
+        deferred_config: None,
+        resumable: None,
+        #[cfg(feature = "table")]
+        resumable_table: None,
+        battery: None,
+        #[cfg(feature = "link")]
+        partner: None,
+        #[cfg(feature = "match")]
+        match_state: None,
+        #[cfg(feature = "armageddon")]
+        armageddon: None,
+    }
+}
+
+/// What the LCD controller received, decoded from the transfers to the backpack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LcdOp {
+    /// Backlight bit written on its own
+    Backlight(bool),
+    /// Upper half of an instruction, only sent while switching to 4 bit mode
+    Nibble(u8),
+    Command(u8),
+    Data(u8),
+}
+
+/// HD44780 behind a PCF8574 backpack, keeping what it was sent.
+///
+/// The clones share the controller, so the test keeps one to look at what the driver sent.
+#[derive(Clone, Default)]
+pub struct FakeLcd {
+    ops: Rc<RefCell<Vec<LcdOp>>>,
+    failures: Rc<Cell<usize>>,
+    /// Bits clocked on the bus, with the address and the acknowledge bits
+    bits: Rc<Cell<u64>>,
+}
+
+impl FakeLcd {
+    /// Returns the operations received so far, clearing them
+    pub fn take(&self) -> Vec<LcdOp> {
+        self.ops.take()
+    }
+
+    /// Fails the next transfers, like a disconnected display
+    pub fn fail(&self, transfers: usize) {
+        self.failures.set(transfers);
+    }
+
+    /// Returns the time the transfers so far took on the 100 kHz bus, clearing it
+    pub fn take_bus_time(&self) -> Duration {
+        Duration::from_micros(self.bits.take() * 10)
+    }
+}
+
+impl embedded_hal::i2c::ErrorType for FakeLcd {
+    type Error = embedded_hal::i2c::ErrorKind;
+}
+
+impl embedded_hal_async::i2c::I2c for FakeLcd {
+    async fn transaction(
+        &mut self,
+        _address: u8,
+        operations: &mut [embedded_hal::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if self.failures.get() > 0 {
+            self.failures.set(self.failures.get() - 1);
+            return Err(embedded_hal::i2c::ErrorKind::NoAcknowledge(
+                embedded_hal::i2c::NoAcknowledgeSource::Address,
+            ));
+        }
+        for operation in operations {
+            let embedded_hal::i2c::Operation::Write(bytes) = operation else {
+                continue;
+            };
+            // Start and stop conditions, the address and the data, 9 bits to a byte
+            self.bits
+                .set(self.bits.get() + 2 + (1 + bytes.len() as u64) * 9);
+            const ENABLE: u8 = 0x04;
+            let op = match **bytes {
+                [byte] => LcdOp::Backlight(byte & 0x08 != 0),
+                [high, _] => LcdOp::Nibble(high & 0xf0),
+                [high, _, low, _] => {
+                    assert_eq!(high & ENABLE, ENABLE, "Missing enable pulse");
+                    let byte = (high & 0xf0) | (low >> 4);
+                    if high & 0x01 != 0 {
+                        LcdOp::Data(byte)
+                    } else {
+                        LcdOp::Command(byte)
+                    }
+                }
+                _ => panic!("Unexpected transfer {:?}", bytes),
+            };
+            self.ops.borrow_mut().push(op);
+        }
+        Ok(())
+    }
+}
+
+/// Delay that returns at once, the LCD tests don't look at the timing
+pub struct NoDelay;
+
+impl embedded_hal_async::delay::DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// NOR flash in memory, where a write can only clear bits and the power can fail.
+///
+/// The clones share the memory, so the test can open it again after a power failure.
+#[derive(Clone)]
+pub struct FakeFlash {
+    bytes: Rc<RefCell<Vec<u8>>>,
+    /// Writes and erases left before the power fails, the failing write only does half its bytes
+    budget: Rc<Cell<Option<usize>>>,
+}
+
+impl FakeFlash {
+    pub fn new(pages: u32) -> Self {
+        Self {
+            bytes: Rc::new(RefCell::new(vec![0xff; (pages * PAGE_SIZE) as usize])),
+            budget: Rc::new(Cell::new(None)),
+        }
+    }
+
+    /// Cuts the power after the next `operations` writes and erases
+    pub fn fail_after(&self, operations: usize) {
+        self.budget.set(Some(operations));
+    }
+
+    /// Brings the power back
+    pub fn restore(&self) {
+        self.budget.set(None);
+    }
+
+    /// Flips the bits of a byte, like a worn out cell
+    pub fn corrupt(&self, offset: u32) {
+        self.bytes.borrow_mut()[offset as usize] ^= 0xff;
+    }
+
+    /// Takes one operation from the budget, returns whether there was power for it
+    fn spend(&self) -> bool {
+        match self.budget.get() {
+            Some(0) => false,
+            Some(left) => {
+                self.budget.set(Some(left - 1));
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+impl embedded_storage::nor_flash::ErrorType for FakeFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for FakeFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), NorFlashErrorKind> {
+        let start = offset as usize;
+        let memory = self.bytes.borrow();
+        let source = memory
+            .get(start..start + bytes.len())
+            .ok_or(NorFlashErrorKind::OutOfBounds)?;
+        bytes.copy_from_slice(source);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.bytes.borrow().len()
+    }
+}
+
+impl NorFlash for FakeFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = PAGE_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), NorFlashErrorKind> {
+        if !from.is_multiple_of(PAGE_SIZE) || !to.is_multiple_of(PAGE_SIZE) {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        if !self.spend() {
+            return Err(NorFlashErrorKind::Other);
+        }
+        let mut memory = self.bytes.borrow_mut();
+        memory
+            .get_mut(from as usize..to as usize)
+            .ok_or(NorFlashErrorKind::OutOfBounds)?
+            .fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), NorFlashErrorKind> {
+        if !offset.is_multiple_of(4) || !bytes.len().is_multiple_of(4) {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        let powered = self.spend();
+        let bytes = if powered {
+            bytes
+        } else {
+            &bytes[..bytes.len() / 2]
+        };
+        let start = offset as usize;
+        let mut memory = self.bytes.borrow_mut();
+        let target = memory
+            .get_mut(start..start + bytes.len())
+            .ok_or(NorFlashErrorKind::OutOfBounds)?;
+        for (cell, byte) in target.iter_mut().zip(bytes) {
+            assert_eq!(*cell, 0xff, "Writing over written flash");
+            *cell = *byte;
+        }
+        if powered {
+            Ok(())
+        } else {
+            Err(NorFlashErrorKind::Other)
+        }
+    }
+}
+
+/// Serial port, with what the computer sends and what the clock wrote.
+///
+/// The clones share the line, so the test keeps one to talk to the task.
+#[derive(Clone, Default)]
+pub struct FakePort {
+    incoming: Rc<RefCell<VecDeque<u8>>>,
+    outgoing: Rc<RefCell<Vec<u8>>>,
+}
+
+impl FakePort {
+    /// Sends bytes to the clock
+    pub fn send(&self, bytes: &[u8]) {
+        self.incoming.borrow_mut().extend(bytes);
+    }
+
+    /// Returns what the clock wrote so far, clearing it
+    pub fn take(&self) -> String {
+        String::from_utf8(self.outgoing.take()).unwrap()
+    }
+}
+
+impl embedded_io_async::ErrorType for FakePort {
+    type Error = embedded_io_async::ErrorKind;
+}
+
+impl embedded_io_async::Read for FakePort {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        core::future::poll_fn(|cx| {
+            let mut incoming = self.incoming.borrow_mut();
+            if incoming.is_empty() {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = buf.len().min(incoming.len());
+            for (byte, received) in buf.iter_mut().zip(incoming.drain(..len)) {
+                *byte = received;
+            }
+            Poll::Ready(Ok(len))
+        })
+        .await
+    }
+}
+
+impl embedded_io_async::Write for FakePort {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.outgoing.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,1240 @@
+//! Line based serial protocol between the clock and a computer, shared by the firmware and the
+//! host tools so both sides speak the same version of it.
+//!
+//! Commands are sent to the clock as words separated by spaces, one command per line:
+//!
+//! ```text
+//! CONFIG <left s> <right s> <kind>[/<right kind>] [<left s> <right s>]
+//! START <L|R>        new game with the given player to move
+//! PAUSE / RESUME
+//! SWITCH             ends the turn of the player to move
+//! ADJUST <L|R> <s>   adds the signed number of seconds to the player's time
+//! SET <left s> <right s> [L|R]
+//!                    sets both times, running the given player's clock or paused without one
+//! BEEP <ms>
+//! STATE              asks for a STATE line
+//! PRESETS            asks for a PRESET line per saved preset
+//! PRESET ADD <left s> <right s> <kind>[/<right kind>] [<left s> <right s>] <name>
+//! PRESET DEL <n>     deletes the preset numbered n in the PRESET lines
+//! GAMES              asks for a GAME line per finished game kept by the clock
+//! ```
+//!
+//! A `CONFIG` sent during a game is kept for the next one, the game goes on with its own.
+//!
+//! The kind of increment is one of `SD`, `INC`, `DELAY` and `BRONSTEIN`, for both players unless
+//! the right one has their own after a slash, as in `BRONSTEIN/INC`. The increments or delays
+//! follow unless both players play sudden death.
+//!
+//! Times and adjustments go up to 5999 seconds, the 99:59 the clock can show, increments and
+//! delays up to 59 seconds and beeps up to 5 seconds. Anything longer is an invalid argument.
+//!
+//! The clock answers every line with `OK` or `ERR,<reason>` and reports what happens as comma
+//! separated lines, times in milliseconds:
+//!
+//! ```text
+//! STATE,<page>,<L|R>,<paused 0|1>,<left>,<right>,<delay>
+//! TURN,<L|R>,<left>,<right>,<delay>
+//! TICK,<left>,<right>,<delay>
+//! FLAG,<L|R>
+//! RESULT,<winner L|R>
+//! PRESET,<n>,<name>,<left s>,<right s>,<kind>[/<right kind>],<left s>,<right s>
+//! GAME,<n>,<first L|R>,<winner L|R|D>,<moves>,<left>,<right>
+//! ```
+//!
+//! The clock keeps the last games that ended in a flag or a result entered by hand, numbered from
+//! the oldest, with the player who moved first, the winner or `D` for a draw, the number of moves
+//! of both players and the times they ended with.
+//!
+//! Many clocks can share an RS-485 bus, each with its own bus address. Lines on the bus start
+//! with `@<n> ` for the clock at address n, or `@* ` for all of them. Only the addressed clock
+//! answers, with its address in front of every line, and only to `STATE`, `PRESETS` and `GAMES`:
+//! nothing is reported unasked, and commands to all clocks aren't answered at all.
+
+#![no_std]
+
+use core::{fmt::Write, str::SplitAsciiWhitespace};
+
+use heapless::String;
+
+/// Longest line sent or accepted, including the newline
+pub const MAX_LINE: usize = 80;
+/// Longest preset name
+pub const NAME_LEN: usize = 12;
+/// Highest bus address, addresses start at 1
+pub const MAX_BUS_ADDRESS: u8 = 32;
+/// Longest time in seconds, the clock shows 99:59 at most
+pub const MAX_SECS: u32 = 5999;
+/// Longest increment or delay in seconds, as in the menu of the clock
+pub const MAX_INCREMENT_SECS: u32 = 59;
+/// Longest beep in milliseconds
+pub const MAX_BEEP_MS: u32 = 5000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum IncrementKind {
+    SuddenDeath,
+    Increment,
+    Delay,
+    Bronstein,
+}
+
+/// Time control in whole seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub left_secs: u32,
+    pub right_secs: u32,
+    pub left_kind: IncrementKind,
+    pub right_kind: IncrementKind,
+    pub left_increment_secs: u32,
+    pub right_increment_secs: u32,
+}
+
+/// Preset name, printable ASCII without commas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Name {
+    bytes: [u8; NAME_LEN],
+    len: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    Config(Config),
+    Start(Side),
+    Pause,
+    Resume,
+    Switch,
+    Adjust(Side, i32),
+    Set {
+        left_secs: u32,
+        right_secs: u32,
+        running: Option<Side>,
+    },
+    Beep(u32),
+    State,
+    ListPresets,
+    AddPreset(Name, Config),
+    /// Number of the preset in the PRESET lines, counting from 1
+    DeletePreset(u8),
+    ListGames,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodeError {
+    UnknownCommand,
+    InvalidArgument,
+    TooLong,
+}
+
+/// Recipient of a line on the bus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Address {
+    Clock(u8),
+    All,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PageKind {
+    Welcome,
+    Menu,
+    Game,
+    GameOver,
+}
+
+/// What the telemetry reports about the clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    pub page: PageKind,
+    /// Player to move, on the game over page the player who ran out of time
+    pub turn: Side,
+    pub paused: bool,
+    pub left_ms: u32,
+    pub right_ms: u32,
+    /// Delay left before the time of the player to move runs, in the delay modes
+    pub delay_ms: u32,
+}
+
+/// Finished game kept by the clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GameRecord {
+    /// The player who moved first
+    pub first: Side,
+    /// `None` for a draw
+    pub winner: Option<Side>,
+    /// Moves of both players
+    pub moves: u16,
+    pub left_ms: u32,
+    pub right_ms: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Telemetry {
+    Ok,
+    Error(DecodeError),
+    State(Status),
+    Turn(Status),
+    Tick(Status),
+    Flag(Side),
+    Result(Side),
+    /// Saved preset with its number, counting from 1
+    Preset(u8, Name, Config),
+    /// Finished game with its number, counting from 1 for the oldest
+    Game(u8, GameRecord),
+}
+
+impl Side {
+    fn parse(word: &str) -> Option<Side> {
+        match word {
+            "L" | "l" => Some(Side::Left),
+            "R" | "r" => Some(Side::Right),
+            _ => None,
+        }
+    }
+
+    pub fn other(self) -> Side {
+        match self {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        }
+    }
+
+    fn code(self) -> char {
+        match self {
+            Side::Left => 'L',
+            Side::Right => 'R',
+        }
+    }
+}
+
+impl IncrementKind {
+    fn parse(word: &str) -> Option<IncrementKind> {
+        [
+            IncrementKind::SuddenDeath,
+            IncrementKind::Increment,
+            IncrementKind::Delay,
+            IncrementKind::Bronstein,
+        ]
+        .into_iter()
+        .find(|kind| word.eq_ignore_ascii_case(kind.keyword()))
+    }
+
+    fn keyword(self) -> &'static str {
+        match self {
+            IncrementKind::SuddenDeath => "SD",
+            IncrementKind::Increment => "INC",
+            IncrementKind::Delay => "DELAY",
+            IncrementKind::Bronstein => "BRONSTEIN",
+        }
+    }
+}
+
+/// Kinds of both players, the right one after a slash when it differs from the left
+fn parse_kinds(word: &str) -> Option<(IncrementKind, IncrementKind)> {
+    match word.split_once('/') {
+        Some((left, right)) => Some((IncrementKind::parse(left)?, IncrementKind::parse(right)?)),
+        None => IncrementKind::parse(word).map(|kind| (kind, kind)),
+    }
+}
+
+fn write_kinds(line: &mut String<MAX_LINE>, config: &Config) -> core::fmt::Result {
+    write!(line, "{}", config.left_kind.keyword())?;
+    if config.right_kind != config.left_kind {
+        write!(line, "/{}", config.right_kind.keyword())?;
+    }
+    Ok(())
+}
+
+impl Name {
+    /// `None` if the name is empty, too long or would break the lines it is sent in
+    pub fn new(name: &str) -> Option<Name> {
+        let name = name.trim();
+        let valid = |c: char| (c.is_ascii_graphic() || c == ' ') && c != ',';
+        if name.is_empty() || name.len() > NAME_LEN || !name.chars().all(valid) {
+            return None;
+        }
+        let mut bytes = [0; NAME_LEN];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Some(Name {
+            bytes,
+            len: name.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only built from ASCII
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or("")
+    }
+}
+
+/// Parses one line, without the line ending
+pub fn decode(line: &str) -> Result<Command, DecodeError> {
+    let mut words = line.split_ascii_whitespace();
+    let keyword = words.next().ok_or(DecodeError::UnknownCommand)?;
+
+    let command = if keyword.eq_ignore_ascii_case("CONFIG") {
+        Command::Config(decode_config(&mut words)?)
+    } else if keyword.eq_ignore_ascii_case("START") {
+        Command::Start(side(arg(&mut words)?)?)
+    } else if keyword.eq_ignore_ascii_case("PAUSE") {
+        Command::Pause
+    } else if keyword.eq_ignore_ascii_case("RESUME") {
+        Command::Resume
+    } else if keyword.eq_ignore_ascii_case("SWITCH") {
+        Command::Switch
+    } else if keyword.eq_ignore_ascii_case("ADJUST") {
+        let side = side(arg(&mut words)?)?;
+        let secs: i32 = number(arg(&mut words)?)?;
+        if secs.unsigned_abs() > MAX_SECS {
+            return Err(DecodeError::InvalidArgument);
+        }
+        Command::Adjust(side, secs)
+    } else if keyword.eq_ignore_ascii_case("SET") {
+        let left_secs = bounded(arg(&mut words)?, MAX_SECS)?;
+        let right_secs = bounded(arg(&mut words)?, MAX_SECS)?;
+        let running = words.next().map(side).transpose()?;
+        Command::Set {
+            left_secs,
+            right_secs,
+            running,
+        }
+    } else if keyword.eq_ignore_ascii_case("BEEP") {
+        Command::Beep(bounded(arg(&mut words)?, MAX_BEEP_MS)?)
+    } else if keyword.eq_ignore_ascii_case("STATE") {
+        Command::State
+    } else if keyword.eq_ignore_ascii_case("PRESETS") {
+        Command::ListPresets
+    } else if keyword.eq_ignore_ascii_case("GAMES") {
+        Command::ListGames
+    } else if keyword.eq_ignore_ascii_case("PRESET") {
+        let action = arg(&mut words)?;
+        if action.eq_ignore_ascii_case("ADD") {
+            let config = decode_config(&mut words)?;
+            // The rest of the line, spaces between the words kept as one
+            let mut name: String<NAME_LEN> = String::new();
+            for word in words.by_ref() {
+                if !name.is_empty() {
+                    name.push(' ').map_err(|_| DecodeError::InvalidArgument)?;
+                }
+                name.push_str(word)
+                    .map_err(|_| DecodeError::InvalidArgument)?;
+            }
+            let name = Name::new(&name).ok_or(DecodeError::InvalidArgument)?;
+            Command::AddPreset(name, config)
+        } else if action.eq_ignore_ascii_case("DEL") {
+            Command::DeletePreset(number(arg(&mut words)?)?)
+        } else {
+            return Err(DecodeError::InvalidArgument);
+        }
+    } else {
+        return Err(DecodeError::UnknownCommand);
+    };
+
+    if words.next().is_some() {
+        return Err(DecodeError::InvalidArgument);
+    }
+    Ok(command)
+}
+
+fn decode_config(words: &mut SplitAsciiWhitespace<'_>) -> Result<Config, DecodeError> {
+    let left_secs = bounded(arg(words)?, MAX_SECS)?;
+    let right_secs = bounded(arg(words)?, MAX_SECS)?;
+    if left_secs == 0 || right_secs == 0 {
+        return Err(DecodeError::InvalidArgument);
+    }
+    let (left_kind, right_kind) = parse_kinds(arg(words)?).ok_or(DecodeError::InvalidArgument)?;
+    let (left_increment_secs, right_increment_secs) = match (left_kind, right_kind) {
+        (IncrementKind::SuddenDeath, IncrementKind::SuddenDeath) => (0, 0),
+        _ => (
+            bounded(arg(words)?, MAX_INCREMENT_SECS)?,
+            bounded(arg(words)?, MAX_INCREMENT_SECS)?,
+        ),
+    };
+    Ok(Config {
+        left_secs,
+        right_secs,
+        left_kind,
+        right_kind,
+        left_increment_secs,
+        right_increment_secs,
+    })
+}
+
+fn arg<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, DecodeError> {
+    words.next().ok_or(DecodeError::InvalidArgument)
+}
+
+fn side(word: &str) -> Result<Side, DecodeError> {
+    Side::parse(word).ok_or(DecodeError::InvalidArgument)
+}
+
+fn number<T: core::str::FromStr>(word: &str) -> Result<T, DecodeError> {
+    word.parse().map_err(|_| DecodeError::InvalidArgument)
+}
+
+fn bounded(word: &str, max: u32) -> Result<u32, DecodeError> {
+    Some(number(word)?)
+        .filter(|value| *value <= max)
+        .ok_or(DecodeError::InvalidArgument)
+}
+
+/// Formats the command as a line for the clock, including the newline
+pub fn encode_command(command: &Command) -> String<MAX_LINE> {
+    let mut line = String::new();
+    // The longest line fits, formatting can't fail
+    let _ = match command {
+        Command::Config(config) => {
+            write!(line, "CONFIG").and_then(|_| write_config(&mut line, config))
+        }
+        Command::Start(side) => write!(line, "START {}", side.code()),
+        Command::Pause => write!(line, "PAUSE"),
+        Command::Resume => write!(line, "RESUME"),
+        Command::Switch => write!(line, "SWITCH"),
+        Command::Adjust(side, secs) => write!(line, "ADJUST {} {}", side.code(), secs),
+        Command::Set {
+            left_secs,
+            right_secs,
+            running,
+        } => write!(line, "SET {} {}", left_secs, right_secs).and_then(|_| match running {
+            Some(side) => write!(line, " {}", side.code()),
+            None => Ok(()),
+        }),
+        Command::Beep(millis) => write!(line, "BEEP {}", millis),
+        Command::State => write!(line, "STATE"),
+        Command::ListPresets => write!(line, "PRESETS"),
+        Command::AddPreset(name, config) => write!(line, "PRESET ADD")
+            .and_then(|_| write_config(&mut line, config))
+            .and_then(|_| write!(line, " {}", name.as_str())),
+        Command::DeletePreset(number) => write!(line, "PRESET DEL {}", number),
+        Command::ListGames => write!(line, "GAMES"),
+    };
+    let _ = line.push('\n');
+    line
+}
+
+/// Appends the arguments of the time control, each after a space
+fn write_config(line: &mut String<MAX_LINE>, config: &Config) -> core::fmt::Result {
+    write!(line, " {} {} ", config.left_secs, config.right_secs)?;
+    write_kinds(line, config)?;
+    if (config.left_kind, config.right_kind)
+        != (IncrementKind::SuddenDeath, IncrementKind::SuddenDeath)
+    {
+        write!(
+            line,
+            " {} {}",
+            config.left_increment_secs, config.right_increment_secs
+        )?;
+    }
+    Ok(())
+}
+
+/// Formats the telemetry as a line, including the newline
+pub fn encode(telemetry: &Telemetry) -> String<MAX_LINE> {
+    let mut line = String::new();
+    // The longest line fits, formatting can't fail
+    let _ = match telemetry {
+        Telemetry::Ok => write!(line, "OK"),
+        Telemetry::Error(err) => write!(line, "ERR,{}", err.reason()),
+        Telemetry::State(status) => write!(
+            line,
+            "STATE,{},{},{},{},{},{}",
+            status.page.keyword(),
+            status.turn.code(),
+            status.paused as u8,
+            status.left_ms,
+            status.right_ms,
+            status.delay_ms
+        ),
+        Telemetry::Turn(status) => write!(
+            line,
+            "TURN,{},{},{},{}",
+            status.turn.code(),
+            status.left_ms,
+            status.right_ms,
+            status.delay_ms
+        ),
+        Telemetry::Tick(status) => write!(
+            line,
+            "TICK,{},{},{}",
+            status.left_ms, status.right_ms, status.delay_ms
+        ),
+        Telemetry::Flag(side) => write!(line, "FLAG,{}", side.code()),
+        Telemetry::Result(winner) => write!(line, "RESULT,{}", winner.code()),
+        Telemetry::Preset(number, name, config) => write!(
+            line,
+            "PRESET,{},{},{},{},",
+            number,
+            name.as_str(),
+            config.left_secs,
+            config.right_secs
+        )
+        .and_then(|_| write_kinds(&mut line, config))
+        .and_then(|_| {
+            write!(
+                line,
+                ",{},{}",
+                config.left_increment_secs, config.right_increment_secs
+            )
+        }),
+        Telemetry::Game(number, game) => write!(
+            line,
+            "GAME,{},{},{},{},{},{}",
+            number,
+            game.first.code(),
+            game.winner.map_or('D', Side::code),
+            game.moves,
+            game.left_ms,
+            game.right_ms
+        ),
+    };
+    let _ = line.push('\n');
+    line
+}
+
+/// Parses a line from the clock, without the line ending.
+///
+/// A TICK line only carries the times, it decodes as a running game with the left player to move.
+pub fn decode_telemetry(line: &str) -> Result<Telemetry, DecodeError> {
+    let mut fields = line.split(',');
+    let keyword = fields.next().ok_or(DecodeError::UnknownCommand)?;
+    let status = |page, turn, paused, left_ms, right_ms, delay_ms| Status {
+        page,
+        turn,
+        paused,
+        left_ms,
+        right_ms,
+        delay_ms,
+    };
+
+    let telemetry = match keyword {
+        "OK" => Telemetry::Ok,
+        "ERR" => {
+            let reason = arg(&mut fields)?;
+            [
+                DecodeError::UnknownCommand,
+                DecodeError::InvalidArgument,
+                DecodeError::TooLong,
+            ]
+            .into_iter()
+            .find(|err| err.reason() == reason)
+            .map(Telemetry::Error)
+            .ok_or(DecodeError::InvalidArgument)?
+        }
+        "STATE" => {
+            let page = PageKind::parse(arg(&mut fields)?)?;
+            let turn = side(arg(&mut fields)?)?;
+            let paused = number::<u8>(arg(&mut fields)?)? != 0;
+            let left_ms = number(arg(&mut fields)?)?;
+            let right_ms = number(arg(&mut fields)?)?;
+            let delay_ms = number(arg(&mut fields)?)?;
+            Telemetry::State(status(page, turn, paused, left_ms, right_ms, delay_ms))
+        }
+        "TURN" => {
+            let turn = side(arg(&mut fields)?)?;
+            let left_ms = number(arg(&mut fields)?)?;
+            let right_ms = number(arg(&mut fields)?)?;
+            let delay_ms = number(arg(&mut fields)?)?;
+            Telemetry::Turn(status(
+                PageKind::Game,
+                turn,
+                false,
+                left_ms,
+                right_ms,
+                delay_ms,
+            ))
+        }
+        "TICK" => {
+            let left_ms = number(arg(&mut fields)?)?;
+            let right_ms = number(arg(&mut fields)?)?;
+            let delay_ms = number(arg(&mut fields)?)?;
+            let status = status(
+                PageKind::Game,
+                Side::Left,
+                false,
+                left_ms,
+                right_ms,
+                delay_ms,
+            );
+            Telemetry::Tick(status)
+        }
+        "FLAG" => Telemetry::Flag(side(arg(&mut fields)?)?),
+        "RESULT" => Telemetry::Result(side(arg(&mut fields)?)?),
+        "PRESET" => {
+            let number_field = number(arg(&mut fields)?)?;
+            let name = Name::new(arg(&mut fields)?).ok_or(DecodeError::InvalidArgument)?;
+            let left_secs = number(arg(&mut fields)?)?;
+            let right_secs = number(arg(&mut fields)?)?;
+            let (left_kind, right_kind) =
+                parse_kinds(arg(&mut fields)?).ok_or(DecodeError::InvalidArgument)?;
+            let config = Config {
+                left_secs,
+                right_secs,
+                left_kind,
+                right_kind,
+                left_increment_secs: number(arg(&mut fields)?)?,
+                right_increment_secs: number(arg(&mut fields)?)?,
+            };
+            Telemetry::Preset(number_field, name, config)
+        }
+        "GAME" => {
+            let number_field = number(arg(&mut fields)?)?;
+            let first = side(arg(&mut fields)?)?;
+            let winner = match arg(&mut fields)? {
+                "D" => None,
+                word => Some(side(word)?),
+            };
+            let game = GameRecord {
+                first,
+                winner,
+                moves: number(arg(&mut fields)?)?,
+                left_ms: number(arg(&mut fields)?)?,
+                right_ms: number(arg(&mut fields)?)?,
+            };
+            Telemetry::Game(number_field, game)
+        }
+        _ => return Err(DecodeError::UnknownCommand),
+    };
+
+    if fields.next().is_some() {
+        return Err(DecodeError::InvalidArgument);
+    }
+    Ok(telemetry)
+}
+
+impl DecodeError {
+    fn reason(self) -> &'static str {
+        match self {
+            DecodeError::UnknownCommand => "unknown command",
+            DecodeError::InvalidArgument => "invalid argument",
+            DecodeError::TooLong => "line too long",
+        }
+    }
+}
+
+impl PageKind {
+    fn parse(word: &str) -> Result<PageKind, DecodeError> {
+        [
+            PageKind::Welcome,
+            PageKind::Menu,
+            PageKind::Game,
+            PageKind::GameOver,
+        ]
+        .into_iter()
+        .find(|page| page.keyword() == word)
+        .ok_or(DecodeError::InvalidArgument)
+    }
+
+    fn keyword(self) -> &'static str {
+        match self {
+            PageKind::Welcome => "WELCOME",
+            PageKind::Menu => "MENU",
+            PageKind::Game => "GAME",
+            PageKind::GameOver => "GAMEOVER",
+        }
+    }
+}
+
+/// Telemetry describing the change from `prev` to `next`, in the order it should be sent
+pub fn changes(prev: &Status, next: &Status) -> impl Iterator<Item = Telemetry> {
+    let mut changes: [Option<Telemetry>; 3] = [None; 3];
+    if prev.page != next.page || prev.paused != next.paused {
+        changes[0] = Some(Telemetry::State(*next));
+    }
+    if next.page == PageKind::Game && prev.page == PageKind::Game {
+        if prev.turn != next.turn {
+            changes[1] = Some(Telemetry::Turn(*next));
+        } else if (prev.left_ms, prev.right_ms, prev.delay_ms)
+            != (next.left_ms, next.right_ms, next.delay_ms)
+        {
+            changes[1] = Some(Telemetry::Tick(*next));
+        }
+    }
+    if next.page == PageKind::GameOver && prev.page != PageKind::GameOver {
+        changes[1] = Some(Telemetry::Flag(next.turn));
+        changes[2] = Some(Telemetry::Result(next.turn.other()));
+    }
+    changes.into_iter().flatten()
+}
+
+/// Splits the address off a line on the bus, `None` for lines without a valid one
+pub fn split_address(line: &str) -> Option<(Address, &str)> {
+    let (address, rest) = line.strip_prefix('@')?.split_once(' ')?;
+    let address = match address {
+        "*" => Address::All,
+        _ => match address.parse() {
+            Ok(n @ 1..=MAX_BUS_ADDRESS) => Address::Clock(n),
+            _ => return None,
+        },
+    };
+    Some((address, rest))
+}
+
+/// Puts the address in front of an encoded line, for the bus
+pub fn with_address(address: Address, line: &str) -> String<MAX_LINE> {
+    let mut addressed = String::new();
+    // Lines are kept short enough for the address to fit
+    let _ = match address {
+        Address::Clock(n) => write!(addressed, "@{} {}", n, line),
+        Address::All => write!(addressed, "@* {}", line),
+    };
+    addressed
+}
+
+/// Collects bytes into lines
+pub struct LineBuffer {
+    line: String<MAX_LINE>,
+    /// Set when the line can't be decoded anymore, it is skipped up to the newline
+    error: Option<DecodeError>,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        LineBuffer {
+            line: String::new(),
+            error: None,
+        }
+    }
+
+    /// Adds a received byte, returns the decoded command once the line is complete.
+    ///
+    /// Empty lines are skipped, carriage returns ignored.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Command, DecodeError>> {
+        self.push_with(byte, |line, error| match error {
+            Some(err) => Some(Err(err)),
+            None if line.trim().is_empty() => None,
+            None => Some(decode(line)),
+        })
+    }
+
+    /// Adds a byte received on the bus, returns the address and the command once the line is
+    /// complete. Lines without an address are skipped.
+    pub fn push_addressed(&mut self, byte: u8) -> Option<(Address, Result<Command, DecodeError>)> {
+        self.push_with(byte, |line, error| {
+            let (address, command) = split_address(line)?;
+            Some((address, error.map_or_else(|| decode(command), Err)))
+        })
+    }
+
+    /// Calls `complete` with the line and its error once the newline comes in
+    fn push_with<T>(
+        &mut self,
+        byte: u8,
+        complete: impl FnOnce(&str, Option<DecodeError>) -> Option<T>,
+    ) -> Option<T> {
+        match byte {
+            b'\n' => {
+                let result = complete(&self.line, self.error.take());
+                self.line.clear();
+                result
+            }
+            b'\r' => None,
+            _ if !byte.is_ascii() => {
+                self.error.get_or_insert(DecodeError::InvalidArgument);
+                None
+            }
+            _ => {
+                if self.line.push(byte as char).is_err() {
+                    self.error.get_or_insert(DecodeError::TooLong);
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::Vec;
+
+    use super::*;
+
+    fn config(left_secs: u32, right_secs: u32, kind: IncrementKind, increment: u32) -> Config {
+        Config {
+            left_secs,
+            right_secs,
+            left_kind: kind,
+            right_kind: kind,
+            left_increment_secs: increment,
+            right_increment_secs: increment,
+        }
+    }
+
+    fn status(page: PageKind, turn: Side, paused: bool, left_ms: u32, right_ms: u32) -> Status {
+        Status {
+            page,
+            turn,
+            paused,
+            left_ms,
+            right_ms,
+            delay_ms: 0,
+        }
+    }
+
+    /// Feeds the bytes, returning what the last one completed
+    fn push_all(buffer: &mut LineBuffer, bytes: &[u8]) -> Option<Result<Command, DecodeError>> {
+        let (last, rest) = bytes.split_last()?;
+        for byte in rest {
+            assert_eq!(buffer.push(*byte), None);
+        }
+        buffer.push(*last)
+    }
+
+    #[test]
+    fn commands_are_decoded() {
+        assert_eq!(decode("START L"), Ok(Command::Start(Side::Left)));
+        assert_eq!(decode("start r"), Ok(Command::Start(Side::Right)));
+        assert_eq!(decode("PAUSE"), Ok(Command::Pause));
+        assert_eq!(decode("  RESUME  "), Ok(Command::Resume));
+        assert_eq!(decode("SWITCH"), Ok(Command::Switch));
+        assert_eq!(
+            decode("ADJUST R -30"),
+            Ok(Command::Adjust(Side::Right, -30))
+        );
+        assert_eq!(
+            decode("SET 300 240 L"),
+            Ok(Command::Set {
+                left_secs: 300,
+                right_secs: 240,
+                running: Some(Side::Left),
+            })
+        );
+        assert_eq!(
+            decode("SET 300 240"),
+            Ok(Command::Set {
+                left_secs: 300,
+                right_secs: 240,
+                running: None,
+            })
+        );
+        assert_eq!(decode("BEEP 200"), Ok(Command::Beep(200)));
+        assert_eq!(decode("STATE"), Ok(Command::State));
+        assert_eq!(decode("PRESETS"), Ok(Command::ListPresets));
+        assert_eq!(decode("PRESET DEL 2"), Ok(Command::DeletePreset(2)));
+        assert_eq!(decode("games"), Ok(Command::ListGames));
+    }
+
+    #[test]
+    fn configs_are_decoded() {
+        assert_eq!(
+            decode("CONFIG 600 600 SD"),
+            Ok(Command::Config(config(
+                600,
+                600,
+                IncrementKind::SuddenDeath,
+                0
+            )))
+        );
+        assert_eq!(
+            decode("CONFIG 180 180 inc 2 2"),
+            Ok(Command::Config(config(
+                180,
+                180,
+                IncrementKind::Increment,
+                2
+            )))
+        );
+        let odds = Config {
+            right_kind: IncrementKind::Delay,
+            right_increment_secs: 5,
+            ..config(300, 120, IncrementKind::Bronstein, 10)
+        };
+        assert_eq!(
+            decode("CONFIG 300 120 BRONSTEIN/DELAY 10 5"),
+            Ok(Command::Config(odds))
+        );
+        assert_eq!(
+            decode("PRESET ADD 300 300 INC 3 3 Club  blitz"),
+            Ok(Command::AddPreset(
+                Name::new("Club blitz").unwrap(),
+                config(300, 300, IncrementKind::Increment, 3)
+            ))
+        );
+    }
+
+    #[test]
+    fn bad_commands_are_rejected() {
+        assert_eq!(decode(""), Err(DecodeError::UnknownCommand));
+        assert_eq!(decode("JUMP"), Err(DecodeError::UnknownCommand));
+        assert_eq!(decode("START"), Err(DecodeError::InvalidArgument));
+        assert_eq!(decode("START X"), Err(DecodeError::InvalidArgument));
+        assert_eq!(decode("PAUSE NOW"), Err(DecodeError::InvalidArgument));
+        assert_eq!(decode("SET 300"), Err(DecodeError::InvalidArgument));
+        assert_eq!(decode("SET -1 300"), Err(DecodeError::InvalidArgument));
+        assert_eq!(decode("CONFIG 0 300 SD"), Err(DecodeError::InvalidArgument));
+        assert_eq!(
+            decode("CONFIG 300 300 INC 2"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode("CONFIG 300 300 FAST"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode("PRESET ADD 300 300 SD"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode("PRESET ADD 300 300 SD Much too long a name"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode("PRESET ADD 300 300 SD a,b"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(decode("PRESET MOVE 1"), Err(DecodeError::InvalidArgument));
+    }
+
+    #[test]
+    fn times_beyond_what_the_clock_shows_are_rejected() {
+        assert!(decode("SET 5999 5999").is_ok());
+        assert_eq!(decode("SET 6000 300"), Err(DecodeError::InvalidArgument));
+        assert_eq!(
+            decode("SET 300 4294967295"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert!(decode("CONFIG 5999 5999 DELAY 59 59").is_ok());
+        assert_eq!(
+            decode("CONFIG 6000 300 SD"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode("CONFIG 300 300 INC 60 2"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert!(decode("ADJUST L -5999").is_ok());
+        assert_eq!(decode("ADJUST L 6000"), Err(DecodeError::InvalidArgument));
+        assert_eq!(decode("ADJUST L -6000"), Err(DecodeError::InvalidArgument));
+        assert_eq!(
+            decode("ADJUST L -2147483648"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert!(decode("BEEP 5000").is_ok());
+        assert_eq!(decode("BEEP 5001"), Err(DecodeError::InvalidArgument));
+    }
+
+    #[test]
+    fn encoded_commands_decode_to_the_same() {
+        let commands = [
+            Command::Config(config(600, 600, IncrementKind::SuddenDeath, 0)),
+            Command::Config(Config {
+                right_kind: IncrementKind::Increment,
+                ..config(300, 60, IncrementKind::Delay, 5)
+            }),
+            Command::Start(Side::Right),
+            Command::Pause,
+            Command::Resume,
+            Command::Switch,
+            Command::Adjust(Side::Left, -10),
+            Command::Set {
+                left_secs: 60,
+                right_secs: 70,
+                running: Some(Side::Right),
+            },
+            Command::Set {
+                left_secs: 60,
+                right_secs: 70,
+                running: None,
+            },
+            Command::Beep(100),
+            Command::State,
+            Command::ListPresets,
+            Command::AddPreset(
+                Name::new("Rapid 15+10").unwrap(),
+                config(900, 900, IncrementKind::Increment, 10),
+            ),
+            Command::DeletePreset(3),
+            Command::ListGames,
+        ];
+        for command in commands {
+            let line = encode_command(&command);
+            assert!(line.ends_with('\n'));
+            assert_eq!(decode(line.trim_end()), Ok(command), "{}", line);
+        }
+    }
+
+    #[test]
+    fn telemetry_is_encoded_as_documented() {
+        let state = status(PageKind::Game, Side::Left, true, 300_000, 299_500);
+        assert_eq!(encode(&Telemetry::Ok), "OK\n");
+        assert_eq!(
+            encode(&Telemetry::Error(DecodeError::TooLong)),
+            "ERR,line too long\n"
+        );
+        assert_eq!(
+            encode(&Telemetry::State(state)),
+            "STATE,GAME,L,1,300000,299500,0\n"
+        );
+        assert_eq!(encode(&Telemetry::Turn(state)), "TURN,L,300000,299500,0\n");
+        assert_eq!(encode(&Telemetry::Tick(state)), "TICK,300000,299500,0\n");
+        assert_eq!(encode(&Telemetry::Flag(Side::Right)), "FLAG,R\n");
+        assert_eq!(encode(&Telemetry::Result(Side::Left)), "RESULT,L\n");
+        let preset = Config {
+            right_kind: IncrementKind::SuddenDeath,
+            right_increment_secs: 0,
+            ..config(180, 60, IncrementKind::Increment, 2)
+        };
+        assert_eq!(
+            encode(&Telemetry::Preset(1, Name::new("Odds").unwrap(), preset)),
+            "PRESET,1,Odds,180,60,INC/SD,2,0\n"
+        );
+        let game = GameRecord {
+            first: Side::Right,
+            winner: None,
+            moves: 81,
+            left_ms: 12_300,
+            right_ms: 4_500,
+        };
+        assert_eq!(
+            encode(&Telemetry::Game(2, game)),
+            "GAME,2,R,D,81,12300,4500\n"
+        );
+    }
+
+    #[test]
+    fn encoded_telemetry_decodes_to_the_same() {
+        let telemetry = [
+            Telemetry::Ok,
+            Telemetry::Error(DecodeError::UnknownCommand),
+            Telemetry::Error(DecodeError::InvalidArgument),
+            Telemetry::Error(DecodeError::TooLong),
+            Telemetry::State(status(PageKind::Welcome, Side::Right, false, 1, 2)),
+            Telemetry::State(status(PageKind::Menu, Side::Left, true, 0, 0)),
+            Telemetry::State(status(PageKind::GameOver, Side::Left, true, 0, 5)),
+            Telemetry::Turn(status(PageKind::Game, Side::Right, false, 10, 20)),
+            // What a TICK line leaves out decodes as the left player running
+            Telemetry::Tick(status(PageKind::Game, Side::Left, false, 30, 40)),
+            Telemetry::Flag(Side::Left),
+            Telemetry::Result(Side::Right),
+            Telemetry::Preset(
+                12,
+                Name::new("Blitz").unwrap(),
+                config(300, 300, IncrementKind::Bronstein, 3),
+            ),
+            Telemetry::Game(
+                8,
+                GameRecord {
+                    first: Side::Left,
+                    winner: Some(Side::Right),
+                    moves: 40,
+                    left_ms: 0,
+                    right_ms: 61_000,
+                },
+            ),
+        ];
+        for telemetry in telemetry {
+            let line = encode(&telemetry);
+            assert_eq!(decode_telemetry(line.trim_end()), Ok(telemetry), "{}", line);
+        }
+    }
+
+    #[test]
+    fn bad_telemetry_is_rejected() {
+        assert_eq!(decode_telemetry("HELLO"), Err(DecodeError::UnknownCommand));
+        assert_eq!(
+            decode_telemetry("ERR,tired"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode_telemetry("TICK,1,2"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode_telemetry("TICK,1,2,3,4"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode_telemetry("STATE,PLAYING,L,0,1,2,3"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode_telemetry("FLAG,X"),
+            Err(DecodeError::InvalidArgument)
+        );
+        assert_eq!(
+            decode_telemetry("GAME,1,L,X,40,0,100"),
+            Err(DecodeError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn changes_report_pages_turns_and_ticks() {
+        let welcome = status(PageKind::Welcome, Side::Left, false, 600_000, 600_000);
+        let game = Status {
+            page: PageKind::Game,
+            ..welcome
+        };
+        let started: Vec<Telemetry, 3> = changes(&welcome, &game).collect();
+        assert_eq!(started, [Telemetry::State(game)]);
+
+        let ticked = Status {
+            left_ms: 599_000,
+            ..game
+        };
+        let ticks: Vec<Telemetry, 3> = changes(&game, &ticked).collect();
+        assert_eq!(ticks, [Telemetry::Tick(ticked)]);
+
+        let switched = Status {
+            turn: Side::Right,
+            ..ticked
+        };
+        let turns: Vec<Telemetry, 3> = changes(&ticked, &switched).collect();
+        assert_eq!(turns, [Telemetry::Turn(switched)]);
+
+        let paused = Status {
+            paused: true,
+            ..switched
+        };
+        let pauses: Vec<Telemetry, 3> = changes(&switched, &paused).collect();
+        assert_eq!(pauses, [Telemetry::State(paused)]);
+
+        assert_eq!(changes(&paused, &paused).count(), 0);
+    }
+
+    #[test]
+    fn changes_report_the_flag_and_the_result() {
+        let game = status(PageKind::Game, Side::Right, false, 5_000, 0);
+        let over = Status {
+            page: PageKind::GameOver,
+            ..game
+        };
+        let flagged: Vec<Telemetry, 3> = changes(&game, &over).collect();
+        assert_eq!(
+            flagged,
+            [
+                Telemetry::State(over),
+                Telemetry::Flag(Side::Right),
+                Telemetry::Result(Side::Left)
+            ]
+        );
+        assert_eq!(changes(&over, &over).count(), 0);
+    }
+
+    #[test]
+    fn lines_are_collected_up_to_the_newline() {
+        let mut buffer = LineBuffer::new();
+        assert_eq!(
+            push_all(&mut buffer, b"PAUSE\r\n"),
+            Some(Ok(Command::Pause))
+        );
+        assert_eq!(push_all(&mut buffer, b"\r\n"), None);
+        assert_eq!(push_all(&mut buffer, b"   \n"), None);
+        assert_eq!(
+            push_all(&mut buffer, b"START R\n"),
+            Some(Ok(Command::Start(Side::Right)))
+        );
+        assert_eq!(
+            push_all(&mut buffer, b"NOPE\n"),
+            Some(Err(DecodeError::UnknownCommand))
+        );
+    }
+
+    #[test]
+    fn broken_lines_are_skipped_up_to_the_newline() {
+        let mut buffer = LineBuffer::new();
+        let long = [b'A'; MAX_LINE + 10];
+        assert_eq!(push_all(&mut buffer, &long), None);
+        assert_eq!(buffer.push(b'\n'), Some(Err(DecodeError::TooLong)));
+        assert_eq!(
+            push_all(&mut buffer, "PAUSE é\n".as_bytes()),
+            Some(Err(DecodeError::InvalidArgument))
+        );
+        // The next line starts over
+        assert_eq!(
+            push_all(&mut buffer, b"SWITCH\n"),
+            Some(Ok(Command::Switch))
+        );
+    }
+
+    #[test]
+    fn addressed_lines_are_collected_with_their_address() {
+        let mut buffer = LineBuffer::new();
+        let mut push_line = |bytes: &[u8]| {
+            let (last, rest) = bytes.split_last().unwrap();
+            for byte in rest {
+                assert_eq!(buffer.push_addressed(*byte), None);
+            }
+            buffer.push_addressed(*last)
+        };
+        assert_eq!(
+            push_line(b"@3 PAUSE\n"),
+            Some((Address::Clock(3), Ok(Command::Pause)))
+        );
+        assert_eq!(
+            push_line(b"@* STATE\n"),
+            Some((Address::All, Ok(Command::State)))
+        );
+        assert_eq!(push_line(b"PAUSE\n"), None);
+        assert_eq!(push_line(b"@33 PAUSE\n"), None);
+    }
+
+    #[test]
+    fn addresses_are_put_in_front_and_split_off() {
+        for address in (1..=MAX_BUS_ADDRESS)
+            .map(Address::Clock)
+            .chain([Address::All])
+        {
+            let line = with_address(address, "STATE");
+            assert_eq!(split_address(&line), Some((address, "STATE")));
+        }
+        assert_eq!(with_address(Address::Clock(7), "PAUSE\n"), "@7 PAUSE\n");
+        assert_eq!(with_address(Address::All, "PAUSE\n"), "@* PAUSE\n");
+        // The address ends at the first space, the rest is left to the decoder
+        assert_eq!(
+            split_address("@12 SET 60 60"),
+            Some((Address::Clock(12), "SET 60 60"))
+        );
+    }
+
+    #[test]
+    fn invalid_addresses_are_not_split() {
+        for line in [
+            "STATE",
+            "@ STATE",
+            "@0 STATE",
+            "@33 STATE",
+            "@-1 STATE",
+            "@1x STATE",
+            "@1STATE",
+            "@** STATE",
+            " @1 STATE",
+        ] {
+            assert_eq!(split_address(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn the_longest_lines_fit_with_an_address() {
+        let status = Status {
+            page: PageKind::GameOver,
+            turn: Side::Right,
+            paused: true,
+            left_ms: u32::MAX,
+            right_ms: u32::MAX,
+            delay_ms: u32::MAX,
+        };
+        let line = encode(&Telemetry::State(status));
+        let addressed = with_address(Address::Clock(MAX_BUS_ADDRESS), &line);
+        assert_eq!(addressed.len(), line.len() + 4);
+        assert!(addressed.ends_with('\n'));
+    }
+}
@@ -1,11 +1,33 @@
 use embassy_time::Duration;
+use heapless::String;
 
+#[cfg(feature = "armageddon")]
+use crate::armageddon::{Armageddon, ArmageddonState};
+#[cfg(feature = "history")]
+use crate::history::{self, GameLog, History};
+#[cfg(feature = "link")]
+use crate::link::{LinkMessage, Partner};
+#[cfg(feature = "match")]
+use crate::match_play::MatchState;
+#[cfg(feature = "armageddon")]
+use crate::menu::MenuItem;
+#[cfg(feature = "table")]
+use crate::table::{TableSnapshot, TableState};
 use crate::{
+    aux::{format_secs, CeilTime},
+    battery::{Battery, Level},
+    display::Frame,
     effect::Effects,
     error::Error,
     game::{GameState, Player},
+    handicap,
+    keyboard::KeyboardMode,
     menu::{GameConfig, MenuState},
-    Outputs,
+    presets::{UserPreset, UserPresets},
+    protocol::{Command, PageKind, Side, Status},
+    result::{Choice, ResultState},
+    resume::Snapshot,
+    settings::{self, Settings},
 };
 
 #[derive(Clone, Copy, defmt::Format, PartialEq, Eq, Hash)]
@@ -25,37 +47,80 @@ pub enum PressType {
 pub enum Event {
     ButtonPushed(Button, PressType),
     Clock(Duration),
+    Battery(Battery),
+    /// Received through the serial protocol
+    // With the link on USART1, only the USB port speaks it
+    #[cfg_attr(all(feature = "link", not(feature = "usb")), allow(dead_code))]
+    Command(Command),
+    /// Received from the partner clock of a bughouse match
+    #[cfg(feature = "link")]
+    Link(LinkMessage),
 }
 
 #[derive(Clone)]
 pub enum Page {
-    Init,
     Welcome,
     Menu(MenuState),
     Game(GameState),
     GameOver(Player),
-}
-
-impl Page {
-    pub fn is_changed(&self, other: &Page) -> bool {
-        match self {
-            Page::Init => !matches!(other, Page::Init),
-            Page::Welcome => !matches!(other, Page::Welcome),
-            Page::Menu(_) => !matches!(other, Page::Menu(_)),
-            Page::Game(_) => !matches!(other, Page::Game(_)),
-            Page::GameOver(_) => !matches!(other, Page::GameOver(_)),
-        }
-    }
+    /// Game of more than two players, over once one of them ran out of time
+    #[cfg(feature = "table")]
+    Table(TableState),
+    /// Result of a paused game other than a flag, entered by hand
+    Result(ResultState),
+    /// Colours and times of an Armageddon game being given out
+    #[cfg(feature = "armageddon")]
+    Armageddon(ArmageddonState),
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub game_config: GameConfig,
+    pub user_presets: UserPresets,
+    pub settings: Settings,
+    /// Latest finished games, kept in flash
+    #[cfg(feature = "history")]
+    pub history: History,
     pub page: Page,
+    /// Config sent over the serial protocol during a game, taken once the game is over
+    pub deferred_config: Option<GameConfig>,
+    /// Game interrupted by a reset or power loss, offered on the welcome page
+    pub resumable: Option<Snapshot>,
+    /// Table game interrupted by a power loss, offered the same way
+    #[cfg(feature = "table")]
+    pub resumable_table: Option<TableSnapshot>,
+    /// Latest battery reading, `None` when running without a battery
+    pub battery: Option<Battery>,
+    /// Latest report of the partner clock, `None` until it sent one
+    #[cfg(feature = "link")]
+    pub partner: Option<Partner>,
+    /// Match being played, kept once it is over to show the final score
+    #[cfg(feature = "match")]
+    pub match_state: Option<MatchState>,
+    /// Armageddon game being played, kept once it is over to show the result
+    #[cfg(feature = "armageddon")]
+    pub armageddon: Option<Armageddon>,
 }
 
 impl AppState {
-    pub fn handle_event(&mut self, effects: &mut Effects, event: Event) -> Result<(), Error> {
+    pub fn handle_event(&mut self, effects: &mut Effects, event: Event) {
+        if !self.plays_game() {
+            if let Some(game_config) = self.deferred_config.take() {
+                self.game_config = game_config;
+            }
+        }
+        // The player buttons type their key on the USB keyboard, except in the menu
+        if let Event::ButtonPushed(button, _) = event {
+            let keyboard = self.settings.keyboard;
+            if let Some(key) = self.settings.key(button) {
+                if keyboard != KeyboardMode::Off && !matches!(self.page, Page::Menu(_)) {
+                    effects.press_key(key);
+                    if keyboard == KeyboardMode::KeysOnly {
+                        return;
+                    }
+                }
+            }
+        }
         match event {
             Event::ButtonPushed(Button::Control, PressType::Long) => {
                 if matches!(self.page, Page::Menu(_)) {
@@ -65,91 +130,623 @@ impl AppState {
                     self.page = Page::Menu(MenuState::new())
                 }
             }
+            Event::Battery(battery) => {
+                let prev_level = self.battery.map(|battery| battery.level());
+                if battery.level() != Level::Good && prev_level < Some(battery.level()) {
+                    effects.buzz(880, Duration::from_millis(1000));
+                }
+                self.battery = Some(battery);
+            }
+            Event::Command(command) => self.handle_command(effects, command),
+            #[cfg(feature = "link")]
+            Event::Link(message) => self.handle_link(effects, message),
             _ => match self.page {
-                Page::Init => {}
                 Page::Welcome => match event {
-                    Event::ButtonPushed(Button::Left, _) => {
-                        self.page = Page::Game(GameState::new(&self.game_config, Player::Left));
-                    }
-                    Event::ButtonPushed(Button::Right, _) => {
-                        self.page = Page::Game(GameState::new(&self.game_config, Player::Right));
+                    Event::ButtonPushed(Button::Left, _) => self.new_game(effects, Player::Left),
+                    Event::ButtonPushed(Button::Right, _) => self.new_game(effects, Player::Right),
+                    Event::ButtonPushed(Button::Control, _) if self.offers_resume() => {
+                        self.resume(effects)
                     }
                     Event::ButtonPushed(Button::Control, _) => {
                         self.page = Page::Menu(MenuState::new());
                     }
-                    Event::Clock(_) => {}
+                    Event::Clock(_) | Event::Battery(_) | Event::Command(_) => {}
+                    #[cfg(feature = "link")]
+                    Event::Link(_) => {}
                 },
+                #[cfg(feature = "armageddon")]
+                Page::Menu(ref menu_state)
+                    if menu_state.item() == &MenuItem::Armageddon
+                        && matches!(
+                            event,
+                            Event::ButtonPushed(Button::Control, PressType::Single)
+                        ) =>
+                {
+                    self.page = Page::Armageddon(ArmageddonState::new());
+                }
+                #[cfg(feature = "armageddon")]
+                Page::Armageddon(ref mut armageddon_state) => {
+                    let armageddon =
+                        armageddon_state.handle_event(effects, &self.game_config, &event);
+                    if let Some(armageddon) = armageddon {
+                        let game_state = GameState::new(&armageddon.config, armageddon.white);
+                        if self.start_game(effects, Page::Game(game_state)) {
+                            self.armageddon = Some(armageddon);
+                        }
+                    }
+                }
                 Page::Menu(ref mut menu_state) => {
-                    menu_state.handle_event(&mut self.game_config, &event);
+                    menu_state.handle_event(
+                        &mut self.game_config,
+                        &mut self.settings,
+                        &mut self.user_presets,
+                        &event,
+                    );
                 }
-                Page::Game(ref mut game_state) => {
-                    game_state.handle_event(effects, &self.game_config, &event)
+                // Results other than a flag are entered on the result page
+                Page::Game(ref game_state)
+                    if game_state.paused
+                        && self.takes_results()
+                        && matches!(
+                            event,
+                            Event::ButtonPushed(Button::Left | Button::Right, PressType::Long)
+                        ) =>
+                {
+                    self.page = Page::Result(ResultState::new(game_state.clone()));
                 }
-                Page::GameOver(_) => match event {
-                    Event::ButtonPushed(Button::Left, _) => {
-                        self.page = Page::Game(GameState::new(&self.game_config, Player::Left))
-                    }
-                    Event::ButtonPushed(Button::Right, _) => {
-                        self.page = Page::Game(GameState::new(&self.game_config, Player::Right))
+                Page::Game(_) => self.handle_game_event(effects, &event),
+                Page::Result(ref mut result_state) => match result_state.handle_event(&event) {
+                    Some(Choice::Back) => self.page = Page::Game(result_state.game.clone()),
+                    Some(choice) => {
+                        let loser = match choice {
+                            Choice::Lost(loser) => Some(loser),
+                            _ => None,
+                        };
+                        #[cfg(feature = "history")]
+                        history::record(&mut self.history, GameLog::of(&result_state.game, loser));
+                        #[cfg(feature = "armageddon")]
+                        if let Some(ref mut armageddon) = self.armageddon {
+                            self.page = Page::GameOver(armageddon.record(loser));
+                            return;
+                        }
+                        #[cfg(feature = "match")]
+                        if let Some(ref mut match_state) = self.match_state {
+                            match_state.record(&self.settings, loser);
+                            self.page = Page::Welcome;
+                            return;
+                        }
+                        if let Some(loser) = loser {
+                            handicap::adapt(&self.settings, &mut self.game_config, loser);
+                        }
+                        self.page = Page::Welcome;
                     }
+                    None => {}
+                },
+                #[cfg(feature = "table")]
+                Page::Table(ref mut table_state) => {
+                    table_state.handle_event(effects, &self.game_config, &event)
+                }
+                Page::GameOver(_) => match event {
+                    Event::ButtonPushed(Button::Left, _) => self.new_game(effects, Player::Left),
+                    Event::ButtonPushed(Button::Right, _) => self.new_game(effects, Player::Right),
                     Event::ButtonPushed(Button::Control, _) => {
                         self.page = Page::Menu(MenuState::new())
                     }
-                    Event::Clock(_) => {}
+                    Event::Clock(_) | Event::Battery(_) | Event::Command(_) => {}
+                    #[cfg(feature = "link")]
+                    Event::Link(_) => {}
                 },
             },
         }
-        Ok(())
+        // The match, or else the adaptive handicap, follows every finished game
+        if let Some(Page::GameOver(loser)) = effects.page_change {
+            #[cfg(feature = "history")]
+            if let Page::Game(ref game_state) = self.page {
+                history::record(&mut self.history, GameLog::of(game_state, Some(loser)));
+            }
+            #[cfg(feature = "match")]
+            if let Some(ref mut match_state) = self.match_state {
+                match_state.record(&self.settings, Some(loser));
+                return;
+            }
+            // The times of an Armageddon game have nothing to do with the handicap
+            #[cfg(feature = "armageddon")]
+            if self.armageddon.is_some() {
+                return;
+            }
+            handicap::adapt(&self.settings, &mut self.game_config, loser);
+        }
+    }
+
+    fn handle_command(&mut self, effects: &mut Effects, command: Command) {
+        match command {
+            // The running game goes on with the controls it started with
+            Command::Config(config) if self.plays_game() => {
+                self.deferred_config = Some(config.into())
+            }
+            Command::Config(config) => self.game_config = config.into(),
+            Command::Start(side) => {
+                let mut game_state = GameState::new(&self.game_config, side.into());
+                game_state.paused = false;
+                if self.start_game(effects, Page::Game(game_state)) {
+                    effects.set_clock(true);
+                }
+            }
+            Command::Pause | Command::Resume => {
+                if let Page::Game(ref mut game_state) = self.page {
+                    game_state.paused = command == Command::Pause;
+                    effects.set_clock(!game_state.paused);
+                }
+            }
+            Command::Switch => {
+                if let Page::Game(ref game_state) = self.page {
+                    if !game_state.paused {
+                        let button = match game_state.turn {
+                            Player::Left => Button::Left,
+                            Player::Right => Button::Right,
+                        };
+                        let event = Event::ButtonPushed(button, PressType::Single);
+                        self.handle_game_event(effects, &event);
+                    }
+                }
+            }
+            Command::Adjust(side, secs) => {
+                if let Page::Game(ref mut game_state) = self.page {
+                    game_state.adjust_time(effects, side.into(), secs);
+                }
+            }
+            Command::Set {
+                left_secs,
+                right_secs,
+                running,
+            } => {
+                let game_state = match self.page {
+                    Page::Game(ref mut game_state) => game_state,
+                    _ => {
+                        let first = running.unwrap_or(Side::Left).into();
+                        let game_state = GameState::new(&self.game_config, first);
+                        if !self.start_game(effects, Page::Game(game_state)) {
+                            return;
+                        }
+                        let Page::Game(ref mut game_state) = self.page else {
+                            return;
+                        };
+                        game_state
+                    }
+                };
+                game_state.left_time = Duration::from_secs(left_secs as u64);
+                game_state.right_time = Duration::from_secs(right_secs as u64);
+                if let Some(side) = running {
+                    game_state.turn = side.into();
+                }
+                game_state.paused = running.is_none();
+                effects.set_clock(!game_state.paused);
+            }
+            Command::Beep(millis) => effects.buzz(880, Duration::from_millis(millis as u64)),
+            Command::State => effects.report_state(),
+            Command::ListPresets => effects.report_presets(),
+            #[cfg(feature = "history")]
+            Command::ListGames => effects.report_games(),
+            // Without the history there are no games to list
+            #[cfg(not(feature = "history"))]
+            Command::ListGames => {}
+            Command::AddPreset(name, config) => {
+                let preset = UserPreset {
+                    name: String::try_from(name.as_str()).unwrap_or_default(),
+                    config: config.into(),
+                };
+                // The presets are only checked by listing them, the command was already answered
+                if self.user_presets.push(preset).is_err() {
+                    effects.buzz(220, Duration::from_millis(500));
+                }
+                self.presets_changed();
+            }
+            Command::DeletePreset(number) => {
+                let index = number as usize;
+                if (1..=self.user_presets.len()).contains(&index) {
+                    self.user_presets.remove(index - 1);
+                }
+                self.presets_changed();
+            }
+        }
+    }
+
+    /// Mirrors the game of the partner clock on this one
+    #[cfg(feature = "link")]
+    fn handle_link(&mut self, effects: &mut Effects, message: LinkMessage) {
+        let partner = self.partner.get_or_insert(Partner {
+            left_ms: 0,
+            right_ms: 0,
+            flagged: None,
+        });
+        match message {
+            LinkMessage::Start(side) => {
+                partner.flagged = None;
+                match self.page {
+                    Page::Game(ref mut game_state) => {
+                        if game_state.paused {
+                            game_state.paused = false;
+                            effects.set_clock(true);
+                        }
+                    }
+                    // Left alone while someone sets the clock up, or uses it for another game
+                    Page::Menu(_) => {}
+                    #[cfg(feature = "table")]
+                    Page::Table(_) => {}
+                    Page::Result(_) => {}
+                    #[cfg(feature = "armageddon")]
+                    Page::Armageddon(_) => {}
+                    Page::Welcome | Page::GameOver(_) => {
+                        let mut game_state = GameState::new(&self.game_config, side.into());
+                        game_state.paused = false;
+                        if self.start_game(effects, Page::Game(game_state)) {
+                            effects.set_clock(true);
+                        }
+                    }
+                }
+            }
+            LinkMessage::Pause | LinkMessage::Flag(_) => {
+                if let LinkMessage::Flag(side) = message {
+                    partner.flagged = Some(side);
+                    effects.buzz(440, Duration::from_millis(500));
+                }
+                if let Page::Game(ref mut game_state) = self.page {
+                    game_state.paused = true;
+                    effects.set_clock(false);
+                }
+            }
+            LinkMessage::Times { left_ms, right_ms } => {
+                partner.left_ms = left_ms;
+                partner.right_ms = right_ms;
+            }
+        }
+    }
+
+    /// Starts a game of the configured number of players, `first` to move in a chess game
+    fn new_game(&mut self, effects: &mut Effects, first: Player) {
+        #[cfg(feature = "table")]
+        if self.game_config.players > 2 {
+            self.start_game(effects, Page::Table(TableState::new(&self.game_config)));
+            return;
+        }
+        // The next game of the match, or the first of a new one
+        #[cfg(feature = "match")]
+        if self.settings.match_games > 0 {
+            let mut match_state = match self.match_state {
+                Some(ref match_state) if !match_state.over => match_state.clone(),
+                _ => MatchState::new(&self.game_config),
+            };
+            match_state.start(&self.settings, &self.game_config, first);
+            let game_state = GameState::new(&match_state.config, first);
+            if self.start_game(effects, Page::Game(game_state)) {
+                self.match_state = Some(match_state);
+            }
+            return;
+        }
+        self.start_game(
+            effects,
+            Page::Game(GameState::new(&self.game_config, first)),
+        );
     }
 
-    pub async fn display_state(
-        &self,
-        prev_state: &AppState,
-        outputs: &mut Outputs<'_, '_>,
-    ) -> Result<(), Error> {
-        if self.page.is_changed(&prev_state.page) {
-            outputs.lcd.clear().await?;
-            outputs.lcd.cursor_blink(false).await?;
-            outputs.lcd.cursor_on(false).await?;
+    /// Shows the page of a new game unless the battery could die during it, returns whether it
+    /// started
+    fn start_game(&mut self, effects: &mut Effects, page: Page) -> bool {
+        if self.battery_level() == Level::Critical {
+            effects.buzz(220, Duration::from_millis(500));
+            return false;
         }
+        self.resumable = None;
+        #[cfg(feature = "table")]
+        {
+            self.resumable_table = None;
+        }
+        // Only the games started with the player buttons belong to a match
+        #[cfg(feature = "match")]
+        {
+            self.match_state = None;
+        }
+        #[cfg(feature = "armageddon")]
+        {
+            self.armageddon = None;
+        }
+        self.page = page;
+        true
+    }
+
+    /// Takes the menu off a saved preset, the list changed under it
+    fn presets_changed(&mut self) {
+        if let Page::Menu(ref mut menu_state) = self.page {
+            menu_state.presets_changed();
+        }
+    }
+
+    /// Whether a game is on the page, its controls taken from the config as it goes
+    fn plays_game(&self) -> bool {
+        match self.page {
+            Page::Game(_) | Page::Result(_) => true,
+            #[cfg(feature = "table")]
+            Page::Table(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether a game interrupted by a reset or power loss waits on the welcome page
+    fn offers_resume(&self) -> bool {
+        #[cfg(feature = "table")]
+        if self.resumable_table.is_some() {
+            return true;
+        }
+        self.resumable.is_some()
+    }
+
+    /// Starts the interrupted game again, paused
+    fn resume(&mut self, effects: &mut Effects) {
+        #[cfg(feature = "table")]
+        if let Some(snapshot) = self.resumable_table.clone() {
+            let page = Page::Table(snapshot.to_table_state());
+            if self.start_game(effects, page) {
+                snapshot.restore_control(&mut self.game_config);
+            }
+            return;
+        }
+        if let Some(snapshot) = self.resumable.clone() {
+            let page = Page::Game(snapshot.to_game_state());
+            if !self.start_game(effects, page) {
+                return;
+            }
+            // The sides of a match swap their controls, the menu keeps them as it set them
+            #[cfg(feature = "match")]
+            if let Some(progress) = snapshot.match_progress {
+                let mut config = self.game_config.clone();
+                snapshot.restore_increments(&mut config);
+                self.match_state = Some(MatchState::resumed(progress, config));
+                return;
+            }
+            snapshot.restore_increments(&mut self.game_config);
+        }
+    }
+
+    /// Time controls of the game being played, those of its Armageddon game or match if it is
+    /// part of one
+    fn config_in_play(&self) -> &GameConfig {
+        #[cfg(feature = "armageddon")]
+        if let Some(ref armageddon) = self.armageddon {
+            return &armageddon.config;
+        }
+        #[cfg(feature = "match")]
+        if let Some(ref match_state) = self.match_state {
+            return &match_state.config;
+        }
+        &self.game_config
+    }
+
+    /// Passes the event on to the chess game on the page, with the controls it is played with
+    fn handle_game_event(&mut self, effects: &mut Effects, event: &Event) {
+        // Copied, the game can't be changed while the controls are borrowed from the state
+        let game_config = self.config_in_play().clone();
+        if let Page::Game(ref mut game_state) = self.page {
+            game_state.handle_event(effects, &game_config, event);
+        }
+    }
+
+    /// Snapshot of the chess game on the page, to resume it after a reset or power loss
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        let game_state = match self.page {
+            Page::Game(ref game_state) => game_state,
+            // The paused game is still there to go back to
+            Page::Result(ref result_state) => &result_state.game,
+            _ => return None,
+        };
+        #[allow(unused_mut)]
+        let mut snapshot = Snapshot::of(game_state, self.config_in_play());
+        #[cfg(feature = "match")]
+        {
+            snapshot.match_progress = self.match_state.as_ref().map(MatchState::progress);
+        }
+        Some(snapshot)
+    }
+
+    /// Whether the paused game takes results other than a flag on the result page
+    fn takes_results(&self) -> bool {
+        let takes_results = self.settings.handicap_step.as_ticks() != 0;
+        #[cfg(feature = "match")]
+        let takes_results = takes_results || self.match_state.as_ref().is_some_and(|m| !m.over);
+        #[cfg(feature = "armageddon")]
+        let takes_results = takes_results || self.armageddon.is_some();
+        takes_results
+    }
+
+    fn battery_level(&self) -> Level {
+        self.battery
+            .map(|battery| battery.level())
+            .unwrap_or(Level::Good)
+    }
+
+    pub fn view(&self) -> Result<Frame, Error> {
         match self.page {
-            Page::Init => {}
             Page::Welcome => {
-                if !matches!(prev_state.page, Page::Welcome) {
-                    outputs.lcd.set_cursor(0, 3).await?;
-                    outputs.lcd.write_str("ChessClock").await?;
-                };
+                let mut frame = Frame::new();
+                if self.offers_resume() {
+                    frame.print(0, 2, "Resume game?");
+                } else {
+                    frame.print(0, 3, "ChessClock");
+                }
+                // The score of the match takes the place of the rest
+                #[cfg(feature = "match")]
+                if let (Some(ref match_state), false, Level::Good | Level::Low) = (
+                    &self.match_state,
+                    self.offers_resume(),
+                    self.battery_level(),
+                ) {
+                    match_state.view(&self.settings, &mut frame, 1)?;
+                    return Ok(frame);
+                }
+                if self.battery_level() == Level::Critical {
+                    frame.print(1, 0, "Replace battery");
+                } else if self.offers_resume() {
+                    frame.print(1, 0, "yes:Ctrl new:L/R");
+                } else if self.settings.handicap_step.as_ticks() != 0 {
+                    // The odds the adaptive handicap came to, for the next game
+                    let (left, right) = (self.game_config.left.time, self.game_config.right.time);
+                    frame.print(1, 0, format_secs(left.ceil_secs())?.as_str());
+                    frame.print(1, 11, format_secs(right.ceil_secs())?.as_str());
+                } else if let Some(battery) = self.battery {
+                    battery.view(&mut frame, 1)?;
+                }
+                Ok(frame)
             }
             Page::Menu(ref menu_state) => {
-                let prev_game_config = prev_state.game_config.clone();
-                let prev_state = if let Page::Menu(ref menu_state) = prev_state.page {
-                    Some(menu_state)
-                } else {
-                    None
-                };
-                menu_state
-                    .display_state(prev_state, &prev_game_config, &self.game_config, outputs)
-                    .await?
+                menu_state.view(&self.game_config, &self.settings, &self.user_presets)
             }
             Page::Game(ref game_state) => {
-                let prev_state = if let Page::Game(ref game_state) = prev_state.page {
-                    Some(game_state)
-                } else {
-                    None
-                };
-                game_state.display_state(prev_state, outputs).await?
+                let mut frame = game_state.view()?;
+                #[cfg(feature = "link")]
+                if let Some(ref partner) = self.partner {
+                    // The partner's times take the place of the battery
+                    partner.view(&mut frame, 1)?;
+                    return Ok(frame);
+                }
+                if let (true, Some(battery)) = (game_state.paused, self.battery) {
+                    battery.view(&mut frame, 1)?;
+                }
+                Ok(frame)
             }
+            #[cfg(feature = "table")]
+            Page::Table(ref table_state) => table_state.view(),
+            Page::Result(ref result_state) => Ok(result_state.view()),
+            #[cfg(feature = "armageddon")]
+            Page::Armageddon(ref armageddon_state) => armageddon_state.view(),
             Page::GameOver(ref loser) => {
-                outputs.lcd.set_cursor(0, 0).await?;
+                let mut frame = Frame::new();
+                #[cfg(feature = "armageddon")]
+                if let Some(ref armageddon) = self.armageddon {
+                    armageddon.view(&mut frame, *loser);
+                    return Ok(frame);
+                }
                 match loser {
-                    Player::Left => outputs.lcd.write_str("Left player").await,
-                    Player::Right => outputs.lcd.write_str("Right player").await,
-                }?;
-                outputs.lcd.set_cursor(1, 0).await?;
-                outputs.lcd.write_str("timeout :(").await?;
+                    Player::Left => frame.print(0, 0, "Left player"),
+                    Player::Right => frame.print(0, 0, "Right player"),
+                }
+                #[cfg(feature = "match")]
+                if let Some(ref match_state) = self.match_state {
+                    match_state.view(&self.settings, &mut frame, 1)?;
+                    return Ok(frame);
+                }
+                frame.print(1, 0, "timeout :(");
+                Ok(frame)
+            }
+        }
+    }
+
+    /// Summary of the state sent as telemetry
+    pub fn status(&self) -> Status {
+        let millis = |duration: Duration| duration.as_millis() as u32;
+        let (page, turn, paused, left_time, right_time) = match self.page {
+            Page::Game(ref game_state) => (
+                PageKind::Game,
+                game_state.turn.into(),
+                game_state.paused,
+                game_state.left_time,
+                game_state.right_time,
+            ),
+            // Still the paused game while its result is entered
+            Page::Result(ref result_state) => (
+                PageKind::Game,
+                result_state.game.turn.into(),
+                true,
+                result_state.game.left_time,
+                result_state.game.right_time,
+            ),
+            Page::GameOver(loser) => (
+                PageKind::GameOver,
+                loser.into(),
+                false,
+                Duration::from_ticks(0),
+                Duration::from_ticks(0),
+            ),
+            Page::Welcome => (
+                PageKind::Welcome,
+                Side::Left,
+                false,
+                self.game_config.left.time,
+                self.game_config.right.time,
+            ),
+            Page::Menu(_) => (
+                PageKind::Menu,
+                Side::Left,
+                false,
+                self.game_config.left.time,
+                self.game_config.right.time,
+            ),
+            // Still setting up, like the menu it is opened from
+            #[cfg(feature = "armageddon")]
+            Page::Armageddon(_) => (
+                PageKind::Menu,
+                Side::Left,
+                false,
+                self.game_config.left.time,
+                self.game_config.right.time,
+            ),
+            // Reported as the left player to move, with the time of the next one on the right
+            #[cfg(feature = "table")]
+            Page::Table(ref table_state) => (
+                match table_state.flagged {
+                    Some(_) => PageKind::GameOver,
+                    None => PageKind::Game,
+                },
+                Side::Left,
+                table_state.paused,
+                table_state.times[table_state.turn as usize],
+                table_state.times[table_state.next() as usize],
+            ),
+        };
+        Status {
+            page,
+            turn,
+            paused,
+            left_ms: millis(left_time),
+            right_ms: millis(right_time),
+            delay_ms: match self.page {
+                Page::Game(ref game_state) => millis(game_state.delay),
+                _ => 0,
+            },
+        }
+    }
+
+    /// Returns the inactivity after which the clock goes to sleep, `None` if it stays awake
+    pub fn sleep_timeout(&self) -> Option<Duration> {
+        match self.page {
+            Page::Game(_) if !self.settings.sleep_in_game => None,
+            #[cfg(feature = "table")]
+            Page::Table(_) if !self.settings.sleep_in_game => None,
+            Page::Game(GameState { paused: true, .. }) => {
+                settings::timeout(self.settings.paused_timeout)
             }
+            #[cfg(feature = "table")]
+            Page::Table(TableState { paused: true, .. }) => {
+                settings::timeout(self.settings.paused_timeout)
+            }
+            _ => settings::timeout(self.settings.idle_timeout),
         }
+    }
 
-        Ok(())
+    /// Returns the player whose LED should be lit
+    pub fn active_led(&self) -> Option<Player> {
+        match self.page {
+            Page::Game(ref game_state) => Some(game_state.turn),
+            // Alternating round the table, so every handover changes the LED
+            #[cfg(feature = "table")]
+            Page::Table(TableState {
+                turn,
+                flagged: None,
+                ..
+            }) => Some(if turn % 2 == 0 {
+                Player::Left
+            } else {
+                Player::Right
+            }),
+            _ => None,
+        }
     }
 }
@@ -0,0 +1,320 @@
+//! Armageddon games, where black gets less time than white and the draw odds: a drawn game counts
+//! as a win for black, so every game has a winner.
+//!
+//! White moves first with five minutes. The helper page gives out the colours, at random with a
+//! minute less for black, or by bidding: the bid for black counts down from white's time and the
+//! first player to press their button takes black with the time shown. The clock has no source of
+//! randomness, so the random draw stirs the noise in the lowest bits of the battery readings into a
+//! xorshift generator, together with the moment the control button is pressed.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    app::{Button, Event, PressType},
+    aux::format_duration,
+    display::Frame,
+    effect::Effects,
+    error::Error,
+    game::Player,
+    match_play::ARMAGEDDON_TIME,
+    menu::{GameConfig, IncrementType, TimeControl},
+};
+
+/// Lowest bid, where the count down stops
+const MIN_BID: Duration = Duration::from_secs(60);
+
+/// Seconds the bid goes down by every second
+const BID_SPEED: u32 = 5;
+
+/// State of the random draw, stirred by the battery monitor
+static SEED: AtomicU32 = AtomicU32::new(0);
+
+/// Mixes a reading of the ADC into the seed of the random draw
+pub fn stir(sample: u16) {
+    let seed = SEED.load(Ordering::Relaxed);
+    SEED.store(seed.rotate_left(5) ^ sample as u32, Ordering::Relaxed);
+}
+
+/// Player drawn at random, every draw moves the seed on
+fn draw() -> Player {
+    // Never zero, where xorshift would stay
+    let mut x = (SEED.load(Ordering::Relaxed) ^ Instant::now().as_ticks() as u32).max(1);
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    SEED.store(x, Ordering::Relaxed);
+    match x >> 31 {
+        0 => Player::Left,
+        _ => Player::Right,
+    }
+}
+
+/// How the game with draw odds was won
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Time,
+    /// Entered on the result page
+    Board,
+    /// Drawn and so won by black
+    DrawOdds,
+}
+
+/// Armageddon game being played, kept once it is over to show the result
+#[derive(Clone)]
+pub struct Armageddon {
+    pub config: GameConfig,
+    pub white: Player,
+    pub outcome: Outcome,
+}
+
+impl Armageddon {
+    fn new(game_config: &GameConfig, black: Player, black_time: Duration) -> Armageddon {
+        let white = match black {
+            Player::Left => Player::Right,
+            Player::Right => Player::Left,
+        };
+        let control = |time| TimeControl {
+            time,
+            increment_type: IncrementType::SuddenDeath,
+        };
+        let mut config = game_config.clone();
+        *config.control_mut(white) = control(ARMAGEDDON_TIME);
+        *config.control_mut(black) = control(black_time);
+        Armageddon {
+            config,
+            white,
+            outcome: Outcome::Time,
+        }
+    }
+
+    /// Takes the result entered on the result page, `None` for a draw, and returns the loser
+    pub fn record(&mut self, loser: Option<Player>) -> Player {
+        match loser {
+            Some(loser) => {
+                self.outcome = Outcome::Board;
+                loser
+            }
+            None => {
+                self.outcome = Outcome::DrawOdds;
+                self.white
+            }
+        }
+    }
+
+    /// Prints the colour of the winner and how they won
+    pub fn view(&self, frame: &mut Frame, loser: Player) {
+        if loser == self.white {
+            frame.print(0, 0, "Black wins");
+        } else {
+            frame.print(0, 0, "White wins");
+        }
+        let outcome = match self.outcome {
+            Outcome::Time => "on time",
+            Outcome::Board => "on the board",
+            Outcome::DrawOdds => "on draw odds",
+        };
+        frame.print(1, 0, outcome);
+    }
+}
+
+#[derive(Clone)]
+enum Stage {
+    /// Choosing between bidding and a random draw
+    Choose { bidding: bool },
+    /// Black's time on offer, counting down
+    Bidding(Duration),
+    /// Colours given out, waiting for the control button to start
+    Ready { black: Player, black_time: Duration },
+}
+
+/// Helper page giving out the colours and times of an Armageddon game
+#[derive(Clone)]
+pub struct ArmageddonState {
+    stage: Stage,
+}
+
+impl ArmageddonState {
+    pub fn new() -> ArmageddonState {
+        ArmageddonState {
+            stage: Stage::Choose { bidding: false },
+        }
+    }
+
+    /// Returns the game once the players start it with the control button
+    pub fn handle_event(
+        &mut self,
+        effects: &mut Effects,
+        game_config: &GameConfig,
+        event: &Event,
+    ) -> Option<Armageddon> {
+        match self.stage {
+            Stage::Choose { bidding } => match event {
+                Event::ButtonPushed(Button::Left | Button::Right, _) => {
+                    self.stage = Stage::Choose { bidding: !bidding };
+                }
+                Event::ButtonPushed(Button::Control, PressType::Single) if bidding => {
+                    self.stage = Stage::Bidding(ARMAGEDDON_TIME);
+                    effects.set_clock(true);
+                }
+                Event::ButtonPushed(Button::Control, PressType::Single) => {
+                    self.stage = Stage::Ready {
+                        black: draw(),
+                        black_time: ARMAGEDDON_TIME - Duration::from_secs(60),
+                    };
+                }
+                _ => {}
+            },
+            Stage::Bidding(bid) => match event {
+                Event::Clock(duration) => {
+                    let bid = bid.checked_sub(*duration * BID_SPEED).unwrap_or(MIN_BID);
+                    self.stage = Stage::Bidding(bid.max(MIN_BID));
+                }
+                Event::ButtonPushed(button @ (Button::Left | Button::Right), _) => {
+                    let black = match button {
+                        Button::Left => Player::Left,
+                        _ => Player::Right,
+                    };
+                    self.stage = Stage::Ready {
+                        black,
+                        black_time: bid,
+                    };
+                    effects.set_clock(false);
+                }
+                _ => {}
+            },
+            Stage::Ready { black, black_time } => {
+                if let Event::ButtonPushed(Button::Control, PressType::Single) = event {
+                    return Some(Armageddon::new(game_config, black, black_time));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn view(&self) -> Result<Frame, Error> {
+        let mut frame = Frame::new();
+        match self.stage {
+            Stage::Choose { bidding } => {
+                frame.print(0, 0, "Armageddon");
+                frame.print(1, 0, if bidding { "< Bidding >" } else { "< Random >" });
+            }
+            Stage::Bidding(bid) => {
+                frame.print(0, 0, "Bid for black");
+                frame.print(1, 0, &format_duration(bid)?);
+            }
+            Stage::Ready { black, black_time } => {
+                let (black_col, white_col) = match black {
+                    Player::Left => (0, 11),
+                    Player::Right => (11, 0),
+                };
+                frame.print(0, black_col, "Black");
+                frame.print(0, white_col, "White");
+                frame.print(1, black_col, &format_duration(black_time)?);
+                frame.print(1, white_col, &format_duration(ARMAGEDDON_TIME)?);
+            }
+        }
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app::Page, game::GameState, testing::app_state};
+
+    fn press(button: Button) -> Event {
+        Event::ButtonPushed(button, PressType::Single)
+    }
+
+    fn text(frame: &Frame, row: usize) -> std::string::String {
+        std::string::String::from_utf8_lossy(&frame.cells[row])
+            .trim_end()
+            .into()
+    }
+
+    #[test]
+    fn a_draw_counts_as_a_loss_for_white() {
+        let four_minutes = ARMAGEDDON_TIME - Duration::from_secs(60);
+        let mut armageddon = Armageddon::new(&GameConfig::default(), Player::Left, four_minutes);
+        assert!(armageddon.white == Player::Right);
+        assert_eq!(armageddon.config.left.time, four_minutes);
+        assert_eq!(armageddon.config.right.time, ARMAGEDDON_TIME);
+
+        assert!(armageddon.record(None) == Player::Right);
+        assert!(armageddon.outcome == Outcome::DrawOdds);
+        let mut frame = Frame::new();
+        armageddon.view(&mut frame, Player::Right);
+        assert_eq!(text(&frame, 0), "Black wins");
+        assert_eq!(text(&frame, 1), "on draw odds");
+
+        assert!(armageddon.record(Some(Player::Left)) == Player::Left);
+        assert!(armageddon.outcome == Outcome::Board);
+    }
+
+    #[test]
+    fn the_first_press_takes_black_with_the_time_shown() {
+        let game_config = GameConfig::default();
+        let mut state = ArmageddonState::new();
+        let mut effects = Effects::new();
+        assert!(state
+            .handle_event(&mut effects, &game_config, &press(Button::Right))
+            .is_none());
+        state.handle_event(&mut effects, &game_config, &press(Button::Control));
+        assert!(matches!(state.stage, Stage::Bidding(bid) if bid == ARMAGEDDON_TIME));
+        assert_eq!(effects.set_clock, Some(true));
+
+        // Five seconds of bid for every second
+        let tick = Event::Clock(Duration::from_secs(10));
+        state.handle_event(&mut Effects::new(), &game_config, &tick);
+        let shown = ARMAGEDDON_TIME - Duration::from_secs(50);
+        assert_eq!(text(&state.view().unwrap(), 1), "04:10");
+
+        let mut effects = Effects::new();
+        state.handle_event(&mut effects, &game_config, &press(Button::Left));
+        assert_eq!(effects.set_clock, Some(false));
+        // Later presses and ticks leave the bid taken
+        state.handle_event(&mut Effects::new(), &game_config, &press(Button::Right));
+        state.handle_event(&mut Effects::new(), &game_config, &tick);
+        let armageddon = state
+            .handle_event(&mut Effects::new(), &game_config, &press(Button::Control))
+            .unwrap();
+        assert!(armageddon.white == Player::Right);
+        assert_eq!(armageddon.config.left.time, shown);
+        assert_eq!(armageddon.config.right.time, ARMAGEDDON_TIME);
+    }
+
+    #[test]
+    fn the_bid_stops_at_a_minute() {
+        let game_config = GameConfig::default();
+        let mut state = ArmageddonState {
+            stage: Stage::Bidding(ARMAGEDDON_TIME),
+        };
+        let tick = Event::Clock(Duration::from_secs(100));
+        state.handle_event(&mut Effects::new(), &game_config, &tick);
+        assert!(matches!(state.stage, Stage::Bidding(bid) if bid == MIN_BID));
+    }
+
+    #[test]
+    fn a_flag_is_won_on_time() {
+        let mut state = app_state();
+        let armageddon = Armageddon::new(&state.game_config, Player::Left, MIN_BID);
+        let mut game_state = GameState::new(&armageddon.config, armageddon.white);
+        game_state.paused = false;
+        game_state.right_time = Duration::from_millis(100);
+        state.page = Page::Game(game_state);
+        state.armageddon = Some(armageddon);
+
+        let mut effects = Effects::new();
+        state.handle_event(&mut effects, Event::Clock(Duration::from_millis(100)));
+        assert!(matches!(
+            effects.page_change,
+            Some(Page::GameOver(Player::Right))
+        ));
+        state.page = effects.page_change.unwrap();
+        let frame = state.view().unwrap();
+        assert_eq!(text(&frame, 0), "Black wins");
+        assert_eq!(text(&frame, 1), "on time");
+    }
+}
@@ -0,0 +1,174 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::{display::Frame, error::Error, lcd::Glyph};
+
+/// Ratio of the voltage divider between the battery and the ADC pin
+const DIVIDER: u32 = 2;
+/// Internal reference voltage of the STM32F1, there is no factory calibration value
+const VREFINT_MV: u32 = 1200;
+/// Three fresh AA cells
+const FULL_MV: u16 = 4500;
+/// Three drained AA cells, the regulator drops out below this
+const EMPTY_MV: u16 = 3300;
+const LOW_MV: u16 = 3600;
+const CRITICAL_MV: u16 = 3400;
+/// Anything below is not a battery, the clock is powered through USB or the debug probe
+const NO_BATTERY_MV: u16 = 1000;
+/// Smallest change in charge reported again
+const REPORT_STEP: u8 = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Level {
+    Good,
+    Low,
+    Critical,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Battery {
+    pub millivolts: u16,
+}
+
+impl Battery {
+    /// Converts the raw readings of the battery pin and VREFINT, `None` if no battery is connected.
+    ///
+    /// VREFINT is measured against VDDA like the battery, so the supply voltage cancels out.
+    pub fn from_samples(raw: u16, vrefint_raw: u16) -> Option<Battery> {
+        if vrefint_raw == 0 {
+            return None;
+        }
+        let millivolts = raw as u32 * VREFINT_MV * DIVIDER / vrefint_raw as u32;
+        let millivolts = millivolts.min(u16::MAX as u32) as u16;
+        (millivolts >= NO_BATTERY_MV).then_some(Battery { millivolts })
+    }
+
+    pub fn percent(&self) -> u8 {
+        let charge = self.millivolts.clamp(EMPTY_MV, FULL_MV) - EMPTY_MV;
+        (charge as u32 * 100 / (FULL_MV - EMPTY_MV) as u32) as u8
+    }
+
+    pub fn level(&self) -> Level {
+        if self.millivolts <= CRITICAL_MV {
+            Level::Critical
+        } else if self.millivolts <= LOW_MV {
+            Level::Low
+        } else {
+            Level::Good
+        }
+    }
+
+    /// Whether the change from the last reported reading is worth an update
+    pub fn differs(&self, prev: Option<&Battery>) -> bool {
+        prev.is_none_or(|prev| {
+            prev.level() != self.level() || prev.percent().abs_diff(self.percent()) >= REPORT_STEP
+        })
+    }
+
+    /// Prints the icon and the charge at the right end of the row
+    pub fn view(&self, frame: &mut Frame, row: usize) -> Result<(), Error> {
+        let percent = self.percent();
+        frame.glyphs = &ICONS[(percent as usize + 10) / 20];
+        frame.cells[row][11] = 0;
+        let mut text: String<4> = String::new();
+        write!(&mut text, "{:>3}%", percent)?;
+        frame.print(row, 12, &text);
+        Ok(())
+    }
+}
+
+/// Battery outline, filled from the bottom in `fill` of five steps
+const fn icon(fill: usize) -> [Glyph; 1] {
+    let mut glyph = [
+        0b01110, 0b11111, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11111,
+    ];
+    let mut row = 7 - fill;
+    while row < 7 {
+        glyph[row] = 0b11111;
+        row += 1;
+    }
+    [glyph]
+}
+
+static ICONS: [[Glyph; 1]; 6] = [icon(0), icon(1), icon(2), icon(3), icon(4), icon(5)];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Raw reading of a voltage with VDDA at 3.3V
+    fn raw(millivolts: u32) -> u16 {
+        raw_at(millivolts, 3300)
+    }
+
+    fn raw_at(millivolts: u32, vdda_mv: u32) -> u16 {
+        (millivolts * 4095 / vdda_mv) as u16
+    }
+
+    fn battery(millivolts: u16) -> Battery {
+        Battery { millivolts }
+    }
+
+    #[test]
+    fn samples_are_scaled_by_vrefint_and_the_divider() {
+        let battery = Battery::from_samples(raw(2250), raw(1200)).unwrap();
+        assert!(battery.millivolts.abs_diff(4500) < 5);
+        // A lower supply raises both readings, the result stays
+        let battery = Battery::from_samples(raw_at(2250, 3000), raw_at(1200, 3000)).unwrap();
+        assert!(battery.millivolts.abs_diff(4500) < 5);
+    }
+
+    #[test]
+    fn without_a_battery_there_is_no_reading() {
+        assert!(Battery::from_samples(raw(400), raw(1200)).is_none());
+        assert!(Battery::from_samples(0, raw(1200)).is_none());
+        assert!(Battery::from_samples(raw(2000), 0).is_none());
+    }
+
+    #[test]
+    fn absurd_samples_saturate() {
+        let battery = Battery::from_samples(u16::MAX, 1).unwrap();
+        assert_eq!(battery.millivolts, u16::MAX);
+        assert_eq!(battery.percent(), 100);
+    }
+
+    #[test]
+    fn the_charge_goes_from_empty_to_full() {
+        assert_eq!(battery(3000).percent(), 0);
+        assert_eq!(battery(EMPTY_MV).percent(), 0);
+        assert_eq!(battery(3900).percent(), 50);
+        assert_eq!(battery(FULL_MV).percent(), 100);
+        assert_eq!(battery(5000).percent(), 100);
+    }
+
+    #[test]
+    fn levels_change_at_the_thresholds() {
+        assert!(battery(CRITICAL_MV).level() == Level::Critical);
+        assert!(battery(CRITICAL_MV + 1).level() == Level::Low);
+        assert!(battery(LOW_MV).level() == Level::Low);
+        assert!(battery(LOW_MV + 1).level() == Level::Good);
+    }
+
+    #[test]
+    fn small_changes_are_not_reported() {
+        assert!(battery(4000).differs(None));
+        // 5% is 60 mV
+        assert!(!battery(4000).differs(Some(&battery(4040))));
+        assert!(battery(4000).differs(Some(&battery(4060))));
+        // Crossing a threshold is reported however small
+        assert!(battery(LOW_MV).differs(Some(&battery(LOW_MV + 1))));
+    }
+
+    #[test]
+    fn the_view_shows_the_icon_and_the_charge() {
+        let mut frame = Frame::new();
+        battery(3900).view(&mut frame, 1).unwrap();
+        assert_eq!(&frame.cells[1][11..], &[0, b' ', b'5', b'0', b'%']);
+        assert_eq!(frame.glyphs, &ICONS[3]);
+
+        battery(3000).view(&mut frame, 1).unwrap();
+        assert_eq!(&frame.cells[1][12..], b"  0%");
+        assert_eq!(frame.glyphs, &ICONS[0]);
+    }
+}
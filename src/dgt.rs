@@ -0,0 +1,473 @@
+//! Emulation of a DGT e-board with a DGT3000 clock, as seen by chess software over the serial port.
+//!
+//! The board protocol consists of one byte commands from the host, and messages starting with a
+//! message id with the high bit set followed by a 14 bit big endian length, which includes the
+//! header. Clock commands are wrapped in `DGT_CLOCK_MESSAGE`, and the clock answers them and reports
+//! button presses with acks sent in the shape of a time message. There is no board, a request for
+//! the board gets an empty one.
+//!
+//! The order of the times in the time message follows picochess: the right clock comes first.
+
+use crate::protocol::{PageKind, Side, Status, MAX_BEEP_MS, MAX_SECS};
+
+// Commands from the host
+const DGT_SEND_RESET: u8 = 0x40;
+const DGT_SEND_CLK: u8 = 0x41;
+const DGT_SEND_BRD: u8 = 0x42;
+const DGT_SEND_UPDATE: u8 = 0x43;
+const DGT_SEND_UPDATE_BRD: u8 = 0x44;
+const DGT_RETURN_SERIALNR: u8 = 0x45;
+const DGT_SEND_UPDATE_NICE: u8 = 0x4b;
+const DGT_SEND_VERSION: u8 = 0x4d;
+const DGT_CLOCK_MESSAGE: u8 = 0x2b;
+
+// Clock commands inside DGT_CLOCK_MESSAGE
+const CLOCK_START_MESSAGE: u8 = 0x03;
+const CLOCK_END_MESSAGE: u8 = 0x00;
+const CLOCK_SETNRUN: u8 = 0x0a;
+const CLOCK_BEEP: u8 = 0x0b;
+const CLOCK_VERSION: u8 = 0x09;
+/// Longest clock message after the size byte, the ASCII display command
+const MAX_CLOCK_MESSAGE: usize = 12;
+
+// Messages to the host
+const MESSAGE_BIT: u8 = 0x80;
+const DGT_MSG_BOARD_DUMP: u8 = MESSAGE_BIT | 0x06;
+const DGT_MSG_BWTIME: u8 = MESSAGE_BIT | 0x0d;
+const DGT_MSG_SERIALNR: u8 = MESSAGE_BIT | 0x11;
+const DGT_MSG_VERSION: u8 = MESSAGE_BIT | 0x13;
+pub const BWTIME_SIZE: usize = 10;
+pub const BOARD_DUMP_SIZE: usize = 67;
+
+// Clock status bits of the time message
+const STATUS_RUNNING: u8 = 0x01;
+const STATUS_LEFT_HIGH: u8 = 0x02;
+const STATUS_RIGHT_TURN: u8 = 0x08;
+const STATUS_LEFT_TURN: u8 = 0x10;
+/// Set in the hours byte of the player whose flag fell
+const FLAG_FALLEN: u8 = 0x10;
+
+/// First byte of every clock ack
+const ACK_CLOCK: u8 = 0x10;
+/// Second byte of the acks reporting a button or the lever
+const ACK_BUTTON: u8 = 0x88;
+const BUTTON_PLAY_PAUSE: u8 = 0x33;
+const LEVER_RIGHT_DOWN: u8 = 0x08;
+const LEVER_LEFT_DOWN: u8 = 0x40;
+/// Reported clock version 2.2
+const CLOCK_VERSION_NUMBER: u8 = 0x22;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum HostCommand {
+    Reset,
+    SendClock,
+    SendBoard,
+    /// Any of the update modes, time messages are sent whenever the clock changes
+    SendUpdates,
+    SendSerialNumber,
+    SendVersion,
+    Clock(ClockCommand),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ClockCommand {
+    /// Sets both times, up to the 99:59 the clock shows, the running side is `None` while stopped
+    SetAndRun {
+        left_secs: u32,
+        right_secs: u32,
+        running: Option<Side>,
+    },
+    Beep {
+        millis: u32,
+    },
+    Version,
+    /// Display, icons, button requests and the others are only acknowledged
+    Other(u8),
+}
+
+impl ClockCommand {
+    pub fn code(&self) -> u8 {
+        match self {
+            ClockCommand::SetAndRun { .. } => CLOCK_SETNRUN,
+            ClockCommand::Beep { .. } => CLOCK_BEEP,
+            ClockCommand::Version => CLOCK_VERSION,
+            ClockCommand::Other(code) => *code,
+        }
+    }
+}
+
+enum DecoderState {
+    Idle,
+    ClockSize,
+    ClockMessage { size: usize },
+}
+
+/// Splits the bytes from the host into commands
+pub struct Decoder {
+    state: DecoderState,
+    buf: [u8; MAX_CLOCK_MESSAGE],
+    len: usize,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder {
+            state: DecoderState::Idle,
+            buf: [0; MAX_CLOCK_MESSAGE],
+            len: 0,
+        }
+    }
+
+    /// Adds a received byte, returns the command once it is complete. Unknown bytes are skipped.
+    pub fn push(&mut self, byte: u8) -> Option<HostCommand> {
+        match self.state {
+            DecoderState::Idle => match byte {
+                DGT_SEND_RESET => Some(HostCommand::Reset),
+                DGT_SEND_CLK => Some(HostCommand::SendClock),
+                DGT_SEND_BRD => Some(HostCommand::SendBoard),
+                DGT_SEND_UPDATE | DGT_SEND_UPDATE_BRD | DGT_SEND_UPDATE_NICE => {
+                    Some(HostCommand::SendUpdates)
+                }
+                DGT_RETURN_SERIALNR => Some(HostCommand::SendSerialNumber),
+                DGT_SEND_VERSION => Some(HostCommand::SendVersion),
+                DGT_CLOCK_MESSAGE => {
+                    self.state = DecoderState::ClockSize;
+                    None
+                }
+                _ => None,
+            },
+            DecoderState::ClockSize => {
+                let size = byte as usize;
+                self.state = if (3..=MAX_CLOCK_MESSAGE).contains(&size) {
+                    self.len = 0;
+                    DecoderState::ClockMessage { size }
+                } else {
+                    DecoderState::Idle
+                };
+                None
+            }
+            DecoderState::ClockMessage { size } => {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                if self.len < size {
+                    return None;
+                }
+                self.state = DecoderState::Idle;
+                decode_clock_message(&self.buf[..size]).map(HostCommand::Clock)
+            }
+        }
+    }
+}
+
+/// Decodes the clock message after the size byte, from the start to the end marker
+fn decode_clock_message(message: &[u8]) -> Option<ClockCommand> {
+    let [CLOCK_START_MESSAGE, code, data @ .., CLOCK_END_MESSAGE] = message else {
+        return None;
+    };
+    let command = match (*code, data) {
+        (CLOCK_SETNRUN, [lh, lm, ls, rh, rm, rs, side]) => ClockCommand::SetAndRun {
+            left_secs: hms_secs(*lh, *lm, *ls),
+            right_secs: hms_secs(*rh, *rm, *rs),
+            running: match side & 0x03 {
+                0x01 => Some(Side::Left),
+                0x02 => Some(Side::Right),
+                _ => None,
+            },
+        },
+        // The duration is counted in 62.5ms steps
+        (CLOCK_BEEP, [duration]) => ClockCommand::Beep {
+            millis: (*duration as u32 * 125 / 2).min(MAX_BEEP_MS),
+        },
+        (CLOCK_VERSION, []) => ClockCommand::Version,
+        // Missing or extra bytes, not acknowledged as if it had worked
+        (CLOCK_SETNRUN | CLOCK_BEEP | CLOCK_VERSION, _) => return None,
+        (code, _) => ClockCommand::Other(code),
+    };
+    Some(command)
+}
+
+/// Seconds of the time set on the DGT3000, which goes up to 9:59:59
+fn hms_secs(hours: u8, minutes: u8, seconds: u8) -> u32 {
+    (hours as u32 * 3600 + minutes as u32 * 60 + seconds as u32).min(MAX_SECS)
+}
+
+fn bcd(value: u32) -> u8 {
+    (((value / 10 % 10) << 4) | (value % 10)) as u8
+}
+
+/// Hours, minutes and seconds bytes of one clock, rounded up to whole seconds like the display
+fn time_bytes(millis: u32, flag_fallen: bool) -> [u8; 3] {
+    let secs = millis.div_ceil(1000);
+    let hours = (secs / 3600).min(9) as u8;
+    let flag = if flag_fallen { FLAG_FALLEN } else { 0 };
+    [hours | flag, bcd(secs / 60 % 60), bcd(secs % 60)]
+}
+
+/// Time message reporting both clocks
+pub fn time_message(status: &Status) -> [u8; BWTIME_SIZE] {
+    let game_over = status.page == PageKind::GameOver;
+    let left_flag = game_over && status.turn == Side::Left;
+    let right_flag = game_over && status.turn == Side::Right;
+    let right = time_bytes(status.right_ms, right_flag);
+    let left = time_bytes(status.left_ms, left_flag);
+
+    let mut clock_status = match status.turn {
+        Side::Left => STATUS_LEFT_TURN | STATUS_LEFT_HIGH,
+        Side::Right => STATUS_RIGHT_TURN,
+    };
+    if status.page == PageKind::Game && !status.paused {
+        clock_status |= STATUS_RUNNING;
+    }
+
+    [
+        DGT_MSG_BWTIME,
+        0,
+        BWTIME_SIZE as u8,
+        right[0],
+        right[1],
+        right[2],
+        left[0],
+        left[1],
+        left[2],
+        clock_status,
+    ]
+}
+
+/// Ack from the clock, in the shape of a time message with 0x0a in the hours nibbles
+fn ack_message(ack: [u8; 4]) -> [u8; BWTIME_SIZE] {
+    let high_bits = |a: u8, b: u8| 0x0a | ((a & 0x80) >> 3) | ((b & 0x80) >> 2);
+    [
+        DGT_MSG_BWTIME,
+        0,
+        BWTIME_SIZE as u8,
+        high_bits(ack[2], ack[3]),
+        ack[0] & 0x7f,
+        ack[1] & 0x7f,
+        high_bits(ack[0], ack[1]),
+        ack[2] & 0x7f,
+        ack[3] & 0x7f,
+        0,
+    ]
+}
+
+/// Acknowledges a clock command
+pub fn clock_ack(command: &ClockCommand) -> [u8; BWTIME_SIZE] {
+    let data = match command {
+        ClockCommand::Version => CLOCK_VERSION_NUMBER,
+        _ => 0,
+    };
+    ack_message([ACK_CLOCK, command.code(), data, 0])
+}
+
+/// Reports the lever moving down on the side of the player who pressed their button
+pub fn lever_ack(pressed: Side) -> [u8; BWTIME_SIZE] {
+    let lever = match pressed {
+        Side::Left => LEVER_LEFT_DOWN,
+        Side::Right => LEVER_RIGHT_DOWN,
+    };
+    ack_message([ACK_CLOCK, ACK_BUTTON, 0, lever])
+}
+
+pub fn play_pause_ack() -> [u8; BWTIME_SIZE] {
+    ack_message([ACK_CLOCK, ACK_BUTTON, 0, BUTTON_PLAY_PAUSE])
+}
+
+/// An empty board, there are no pieces to detect
+pub fn board_dump() -> [u8; BOARD_DUMP_SIZE] {
+    let mut message = [0; BOARD_DUMP_SIZE];
+    message[0] = DGT_MSG_BOARD_DUMP;
+    message[2] = BOARD_DUMP_SIZE as u8;
+    message
+}
+
+pub fn version_message() -> [u8; 5] {
+    [DGT_MSG_VERSION, 0, 5, 1, 0]
+}
+
+pub fn serial_number_message() -> [u8; 8] {
+    [DGT_MSG_SERIALNR, 0, 8, b'0', b'0', b'0', b'0', b'1']
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_all(decoder: &mut Decoder, bytes: &[u8]) -> Vec<HostCommand> {
+        bytes
+            .iter()
+            .filter_map(|byte| decoder.push(*byte))
+            .collect()
+    }
+
+    fn status(page: PageKind, turn: Side, paused: bool, left_ms: u32, right_ms: u32) -> Status {
+        Status {
+            page,
+            turn,
+            paused,
+            left_ms,
+            right_ms,
+            delay_ms: 0,
+        }
+    }
+
+    #[test]
+    fn board_commands_are_single_bytes() {
+        let mut decoder = Decoder::new();
+        let commands = push_all(
+            &mut decoder,
+            &[0x40, 0x41, 0x42, 0x43, 0x44, 0x4b, 0x45, 0x4d],
+        );
+        assert_eq!(
+            commands,
+            [
+                HostCommand::Reset,
+                HostCommand::SendClock,
+                HostCommand::SendBoard,
+                HostCommand::SendUpdates,
+                HostCommand::SendUpdates,
+                HostCommand::SendUpdates,
+                HostCommand::SendSerialNumber,
+                HostCommand::SendVersion,
+            ]
+        );
+        assert_eq!(push_all(&mut decoder, &[0x00, 0x7f, 0xff]), []);
+    }
+
+    #[test]
+    fn set_and_run_sets_both_times() {
+        let mut decoder = Decoder::new();
+        // 0:05:00 on the left, running, and 1:30:15 on the right
+        let message = [0x2b, 0x0a, 0x03, 0x0a, 0, 5, 0, 1, 30, 15, 0x01, 0x00];
+        assert_eq!(
+            push_all(&mut decoder, &message),
+            [HostCommand::Clock(ClockCommand::SetAndRun {
+                left_secs: 300,
+                right_secs: 5415,
+                running: Some(Side::Left),
+            })]
+        );
+        let stopped = [0x2b, 0x0a, 0x03, 0x0a, 0, 1, 0, 0, 2, 0, 0x04, 0x00];
+        assert_eq!(
+            push_all(&mut decoder, &stopped),
+            [HostCommand::Clock(ClockCommand::SetAndRun {
+                left_secs: 60,
+                right_secs: 120,
+                running: None,
+            })]
+        );
+    }
+
+    #[test]
+    fn set_and_run_stops_at_the_longest_time_shown() {
+        let mut decoder = Decoder::new();
+        let message = [0x2b, 0x0a, 0x03, 0x0a, 9, 59, 59, 255, 255, 255, 0x02, 0x00];
+        assert_eq!(
+            push_all(&mut decoder, &message),
+            [HostCommand::Clock(ClockCommand::SetAndRun {
+                left_secs: MAX_SECS,
+                right_secs: MAX_SECS,
+                running: Some(Side::Right),
+            })]
+        );
+    }
+
+    #[test]
+    fn beeps_and_the_version_request_are_decoded() {
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            push_all(&mut decoder, &[0x2b, 0x04, 0x03, 0x0b, 8, 0x00]),
+            [HostCommand::Clock(ClockCommand::Beep { millis: 500 })]
+        );
+        assert_eq!(
+            push_all(&mut decoder, &[0x2b, 0x04, 0x03, 0x0b, 255, 0x00]),
+            [HostCommand::Clock(ClockCommand::Beep {
+                millis: MAX_BEEP_MS
+            })]
+        );
+        assert_eq!(
+            push_all(&mut decoder, &[0x2b, 0x03, 0x03, 0x09, 0x00]),
+            [HostCommand::Clock(ClockCommand::Version)]
+        );
+        // The display text is only acknowledged
+        assert_eq!(
+            push_all(&mut decoder, &[0x2b, 0x05, 0x03, 0x0c, b'h', b'i', 0x00]),
+            [HostCommand::Clock(ClockCommand::Other(0x0c))]
+        );
+    }
+
+    #[test]
+    fn bad_sizes_are_skipped() {
+        let mut decoder = Decoder::new();
+        // Too short and too long, the next byte is a command again
+        assert_eq!(
+            push_all(&mut decoder, &[0x2b, 0x02, 0x41]),
+            [HostCommand::SendClock]
+        );
+        assert_eq!(
+            push_all(&mut decoder, &[0x2b, 13, 0x41]),
+            [HostCommand::SendClock]
+        );
+    }
+
+    #[test]
+    fn truncated_messages_are_dropped() {
+        let mut decoder = Decoder::new();
+        // SETNRUN with its side byte missing
+        let message = [0x2b, 0x09, 0x03, 0x0a, 0, 5, 0, 0, 5, 0, 0x00];
+        assert_eq!(push_all(&mut decoder, &message), []);
+        // The size said 9 bytes, the decoder is back to the commands
+        assert_eq!(push_all(&mut decoder, &[0x4d]), [HostCommand::SendVersion]);
+        // Without the start marker
+        assert_eq!(push_all(&mut decoder, &[0x2b, 0x03, 0x00, 0x09, 0x00]), []);
+        assert_eq!(push_all(&mut decoder, &[0x41]), [HostCommand::SendClock]);
+    }
+
+    #[test]
+    fn the_time_message_puts_the_right_clock_first() {
+        // 1:05:06 on the left, to move and running, 9:59 on the right
+        let running = status(PageKind::Game, Side::Left, false, 3_905_200, 599_000);
+        assert_eq!(
+            time_message(&running),
+            [0x8d, 0x00, 0x0a, 0x00, 0x09, 0x59, 0x01, 0x05, 0x06, 0x13]
+        );
+        let paused = status(PageKind::Game, Side::Right, true, 60_000, 45_000);
+        assert_eq!(
+            time_message(&paused),
+            [0x8d, 0x00, 0x0a, 0x00, 0x00, 0x45, 0x00, 0x01, 0x00, 0x08]
+        );
+    }
+
+    #[test]
+    fn the_time_message_shows_the_fallen_flag() {
+        let over = status(PageKind::GameOver, Side::Right, false, 12_000, 0);
+        assert_eq!(
+            time_message(&over),
+            [0x8d, 0x00, 0x0a, 0x10, 0x00, 0x00, 0x00, 0x00, 0x12, 0x08]
+        );
+    }
+
+    #[test]
+    fn acks_come_in_the_shape_of_time_messages() {
+        assert_eq!(
+            clock_ack(&ClockCommand::Version),
+            [0x8d, 0x00, 0x0a, 0x0a, 0x10, 0x09, 0x0a, 0x22, 0x00, 0x00]
+        );
+        assert_eq!(
+            clock_ack(&ClockCommand::Beep { millis: 500 }),
+            [0x8d, 0x00, 0x0a, 0x0a, 0x10, 0x0b, 0x0a, 0x00, 0x00, 0x00]
+        );
+        // 0x88 has its high bit moved into the hours nibble
+        assert_eq!(
+            lever_ack(Side::Left),
+            [0x8d, 0x00, 0x0a, 0x0a, 0x10, 0x08, 0x2a, 0x00, 0x40, 0x00]
+        );
+        assert_eq!(
+            lever_ack(Side::Right),
+            [0x8d, 0x00, 0x0a, 0x0a, 0x10, 0x08, 0x2a, 0x00, 0x08, 0x00]
+        );
+        assert_eq!(
+            play_pause_ack(),
+            [0x8d, 0x00, 0x0a, 0x0a, 0x10, 0x08, 0x2a, 0x00, 0x33, 0x00]
+        );
+    }
+}
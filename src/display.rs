@@ -0,0 +1,405 @@
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+use crate::{
+    error::Error,
+    lcd::{Glyph, Lcd},
+};
+
+pub const ROWS: usize = 2;
+pub const COLS: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CursorMode {
+    Hidden,
+    Underline { row: u8, col: u8 },
+    Blink { row: u8, col: u8 },
+}
+
+/// Content of the whole display, produced by the views of the pages
+#[derive(Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub cells: [[u8; COLS]; ROWS],
+    pub cursor: CursorMode,
+    /// Custom characters, the glyph at index `i` is shown for the character code `i`
+    pub glyphs: &'static [Glyph],
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame {
+            cells: [[b' '; COLS]; ROWS],
+            cursor: CursorMode::Hidden,
+            glyphs: &[],
+        }
+    }
+
+    /// Writes text starting from (row, col), characters not fitting on the row are dropped.
+    ///
+    /// The LCD only has the ASCII characters, anything else is shown as `?`.
+    pub fn print(&mut self, row: usize, col: usize, text: &str) {
+        self.cells[row]
+            .iter_mut()
+            .skip(col)
+            .zip(text.chars())
+            .for_each(|(cell, c)| *cell = if c.is_ascii() { c as u8 } else { b'?' });
+    }
+}
+
+/// Desired state of the display, the display task always shows the latest one
+#[derive(Clone)]
+pub enum Screen {
+    Off,
+    On(Frame),
+}
+
+/// Continuous run of changed cells on a row
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub row: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+/// Returns the runs of cells that differ between the two frames, or all of the rows if there is
+/// no previous frame
+pub fn diff<'a>(prev: Option<&'a Frame>, next: &'a Frame) -> impl Iterator<Item = Span> + 'a {
+    (0..ROWS).flat_map(move |row| {
+        let mut col = 0;
+        core::iter::from_fn(move || {
+            let is_changed = |col: usize| {
+                prev.map(|prev| prev.cells[row][col] != next.cells[row][col])
+                    .unwrap_or(true)
+            };
+            while col < COLS && !is_changed(col) {
+                col += 1;
+            }
+            if col == COLS {
+                return None;
+            }
+            let start = col;
+            while col < COLS && is_changed(col) {
+                col += 1;
+            }
+            Some(Span {
+                row,
+                col: start,
+                len: col - start,
+            })
+        })
+    })
+}
+
+/// Number of attempts to show a screen, with a reinitialisation of the LCD after each failure
+const ATTEMPTS: usize = 3;
+
+/// Keeps track of the frame on the display, and only sends the changed cells to the LCD
+pub struct Renderer<I, D> {
+    pub lcd: Lcd<I, D>,
+    last_frame: Option<Frame>,
+    is_on: bool,
+}
+
+impl<I, D> Renderer<I, D>
+where
+    I: I2c,
+    D: DelayNs,
+    Error: From<I::Error>,
+{
+    pub fn new(lcd: Lcd<I, D>) -> Self {
+        Self {
+            lcd,
+            last_frame: None,
+            is_on: true,
+        }
+    }
+
+    /// Runs the initialisation sequence of the LCD, after which the whole screen is redrawn
+    pub async fn reinit(&mut self) -> Result<(), Error> {
+        self.invalidate();
+        self.lcd.init().await?;
+        self.lcd.backlight(self.is_on).await?;
+        Ok(())
+    }
+
+    /// Shows the screen, retrying with a reinitialised LCD if the transfer fails
+    pub async fn show(&mut self, screen: &Screen) -> Result<(), Error> {
+        let mut result = Ok(());
+        for _ in 0..ATTEMPTS {
+            result = self.try_show(screen).await;
+            if result.is_ok() {
+                break;
+            }
+            // A failed reinit shows up in the next attempt
+            let _ = self.reinit().await;
+        }
+        result
+    }
+
+    async fn try_show(&mut self, screen: &Screen) -> Result<(), Error> {
+        match screen {
+            Screen::Off => {
+                if self.is_on {
+                    self.lcd.clear().await?;
+                    self.lcd.backlight(false).await?;
+                    self.invalidate();
+                    self.is_on = false;
+                }
+            }
+            Screen::On(frame) => {
+                if !self.is_on {
+                    // The MCU may have been stopped in between, start from a known state
+                    self.is_on = true;
+                    self.reinit().await?;
+                }
+                self.render(frame).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the last frame, so the next render redraws the whole display
+    pub fn invalidate(&mut self) {
+        self.last_frame = None;
+    }
+
+    pub async fn render(&mut self, frame: &Frame) -> Result<(), Error> {
+        let prev = self.last_frame.take();
+        // Invalidated until the whole frame is written out
+        let prev = prev.as_ref();
+
+        let glyphs_changed = prev.map(|prev| prev.glyphs != frame.glyphs).unwrap_or(true);
+        if glyphs_changed {
+            for (location, glyph) in frame.glyphs.iter().enumerate() {
+                self.lcd.create_char(location as u8, glyph).await?;
+            }
+        }
+
+        // Uploading glyphs moves the cursor as well
+        let mut cursor_moved = glyphs_changed;
+        for span in diff(prev, frame) {
+            self.lcd.set_cursor(span.row as u8, span.col as u8).await?;
+            self.lcd
+                .write_bytes(&frame.cells[span.row][span.col..span.col + span.len])
+                .await?;
+            cursor_moved = true;
+        }
+
+        let cursor_changed = prev.map(|prev| prev.cursor != frame.cursor).unwrap_or(true);
+        match frame.cursor {
+            CursorMode::Hidden => {
+                if cursor_changed {
+                    self.lcd.set_cursor_mode(false, false).await?;
+                }
+            }
+            CursorMode::Underline { row, col } | CursorMode::Blink { row, col } => {
+                if cursor_changed || cursor_moved {
+                    self.lcd.set_cursor(row, col).await?;
+                }
+                if cursor_changed {
+                    let blink = matches!(frame.cursor, CursorMode::Blink { .. });
+                    self.lcd.set_cursor_mode(!blink, blink).await?;
+                }
+            }
+        }
+
+        self.last_frame = Some(frame.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::testing::{FakeLcd, LcdOp::*, NoDelay};
+
+    fn frame(rows: [&str; ROWS]) -> Frame {
+        let mut frame = Frame::new();
+        for (row, text) in rows.iter().enumerate() {
+            frame.print(row, 0, text);
+        }
+        frame
+    }
+
+    fn spans(prev: Option<&Frame>, next: &Frame) -> Vec<(usize, usize, usize)> {
+        diff(prev, next)
+            .map(|span| (span.row, span.col, span.len))
+            .collect()
+    }
+
+    fn renderer() -> (Renderer<FakeLcd, NoDelay>, FakeLcd) {
+        let lcd = FakeLcd::default();
+        (Renderer::new(Lcd::new(lcd.clone(), NoDelay, 0x27)), lcd)
+    }
+
+    /// Characters written at each cursor position, leaving out the other instructions
+    fn writes(ops: &[crate::testing::LcdOp]) -> Vec<(u8, Vec<u8>)> {
+        let mut writes: Vec<(u8, Vec<u8>)> = Vec::new();
+        for op in ops {
+            match *op {
+                Command(command) if command & 0x80 != 0 => writes.push((command & 0x7f, vec![])),
+                Data(byte) => writes.last_mut().unwrap().1.push(byte),
+                _ => {}
+            }
+        }
+        writes
+    }
+
+    #[test]
+    fn print_drops_what_does_not_fit() {
+        let mut frame = Frame::new();
+        frame.print(0, 12, "12:34:56");
+        assert_eq!(&frame.cells[0][10..], b"  12:3");
+    }
+
+    #[test]
+    fn print_replaces_characters_the_lcd_does_not_have() {
+        let mut frame = Frame::new();
+        frame.print(1, 0, "Jürgen 5°");
+        assert_eq!(&frame.cells[1][..9], b"J?rgen 5?");
+    }
+
+    #[test]
+    fn without_a_previous_frame_every_row_is_changed() {
+        let next = frame(["ChessClock", ""]);
+        assert_eq!(spans(None, &next), [(0, 0, COLS), (1, 0, COLS)]);
+    }
+
+    #[test]
+    fn diff_finds_the_runs_of_changed_cells() {
+        let prev = frame(["05:00      05:00", "     paused"]);
+        let next = frame(["04:59      05:00", "           "]);
+        assert_eq!(spans(Some(&prev), &next), [(0, 1, 1), (0, 3, 2), (1, 5, 6)]);
+        assert_eq!(spans(Some(&next), &next), []);
+    }
+
+    #[test]
+    fn diff_reaches_the_last_column() {
+        let prev = frame(["", "              ab"]);
+        let next = frame(["", "              ac"]);
+        assert_eq!(spans(Some(&prev), &next), [(1, 15, 1)]);
+    }
+
+    #[test]
+    fn only_the_changed_cells_are_sent() {
+        let (mut renderer, lcd) = renderer();
+        block_on(renderer.render(&frame(["05:00      05:00", ""]))).unwrap();
+        assert_eq!(
+            writes(&lcd.take()),
+            [
+                (0x00, b"05:00      05:00".to_vec()),
+                (0x40, b"                ".to_vec()),
+            ]
+        );
+
+        block_on(renderer.render(&frame(["04:59      05:00", ""]))).unwrap();
+        assert_eq!(
+            lcd.take(),
+            [
+                Command(0x81),
+                Data(b'4'),
+                Command(0x83),
+                Data(b'5'),
+                Data(b'9')
+            ]
+        );
+
+        block_on(renderer.render(&frame(["04:59      05:00", ""]))).unwrap();
+        assert_eq!(lcd.take(), []);
+    }
+
+    #[test]
+    fn the_cursor_goes_back_after_the_text_moved_it() {
+        let (mut renderer, lcd) = renderer();
+        let mut menu = frame(["Left time", "05:00"]);
+        menu.cursor = CursorMode::Underline { row: 1, col: 1 };
+        block_on(renderer.render(&menu)).unwrap();
+        let ops = lcd.take();
+        assert_eq!(ops[ops.len() - 2..], [Command(0xc1), Command(0x0e)]);
+
+        menu.print(1, 0, "06:00");
+        block_on(renderer.render(&menu)).unwrap();
+        assert_eq!(lcd.take(), [Command(0xc1), Data(b'6'), Command(0xc1)]);
+
+        menu.cursor = CursorMode::Blink { row: 1, col: 4 };
+        block_on(renderer.render(&menu)).unwrap();
+        assert_eq!(lcd.take(), [Command(0xc4), Command(0x0d)]);
+
+        menu.cursor = CursorMode::Hidden;
+        block_on(renderer.render(&menu)).unwrap();
+        assert_eq!(lcd.take(), [Command(0x0c)]);
+    }
+
+    #[test]
+    fn glyphs_are_uploaded_when_they_change() {
+        static GLYPHS: [Glyph; 1] = [[0x1f; 8]];
+        let (mut renderer, lcd) = renderer();
+        let mut with_glyph = frame(["", ""]);
+        block_on(renderer.render(&with_glyph)).unwrap();
+        lcd.take();
+
+        with_glyph.glyphs = &GLYPHS;
+        block_on(renderer.render(&with_glyph)).unwrap();
+        let ops = lcd.take();
+        assert_eq!(ops[0], Command(0x40));
+        assert_eq!(ops[1..9], [Data(0x1f); 8]);
+        assert_eq!(ops.len(), 9);
+
+        block_on(renderer.render(&with_glyph)).unwrap();
+        assert_eq!(lcd.take(), []);
+    }
+
+    #[test]
+    fn a_failed_transfer_reinitialises_and_redraws() {
+        let (mut renderer, lcd) = renderer();
+        block_on(renderer.show(&Screen::On(frame(["ChessClock", ""])))).unwrap();
+        lcd.take();
+
+        lcd.fail(1);
+        block_on(renderer.show(&Screen::On(frame(["Resume game?", ""])))).unwrap();
+        let ops = lcd.take();
+        // The LCD may have lost anything, the whole screen goes out again
+        assert_eq!(ops[..2], [Backlight(true), Nibble(0x30)]);
+        assert_eq!(writes(&ops).len(), 2);
+    }
+
+    #[test]
+    fn a_lost_display_gives_up_after_the_attempts() {
+        let (mut renderer, lcd) = renderer();
+        lcd.fail(usize::MAX);
+        let screen = Screen::On(frame(["ChessClock", ""]));
+        assert!(block_on(renderer.show(&screen)).is_err());
+    }
+
+    #[test]
+    fn switching_off_clears_and_switching_on_starts_over() {
+        let (mut renderer, lcd) = renderer();
+        let screen = Screen::On(frame(["ChessClock", ""]));
+        block_on(renderer.show(&screen)).unwrap();
+        lcd.take();
+
+        block_on(renderer.show(&Screen::Off)).unwrap();
+        assert_eq!(lcd.take(), [Command(0x01), Backlight(false)]);
+        block_on(renderer.show(&Screen::Off)).unwrap();
+        assert_eq!(lcd.take(), []);
+
+        block_on(renderer.show(&screen)).unwrap();
+        let ops = lcd.take();
+        let backlight = ops.iter().rfind(|op| matches!(op, Backlight(_)));
+        assert_eq!(backlight, Some(&Backlight(true)));
+        assert_eq!(writes(&ops).len(), 2);
+    }
+
+    /// Modelled from the bits on the bus, not measured on the clock
+    #[test]
+    fn a_clock_tick_keeps_the_bus_busy_for_a_few_milliseconds() {
+        let (mut renderer, lcd) = renderer();
+        block_on(renderer.render(&frame(["05:00      05:00", ""]))).unwrap();
+        let full = lcd.take_bus_time();
+        block_on(renderer.render(&frame(["04:59      05:00", ""]))).unwrap();
+        let tick = lcd.take_bus_time();
+        assert_eq!(full.as_micros(), 16_450);
+        assert_eq!(tick.as_micros(), 2_350);
+    }
+}
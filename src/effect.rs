@@ -6,6 +6,15 @@ pub struct Effects {
     pub set_clock: Option<bool>,
     pub buzz: Option<Buzz>,
     pub page_change: Option<Page>,
+    /// Send the full state over the serial protocol
+    pub report_state: bool,
+    /// Send the saved presets over the serial protocol
+    pub report_presets: bool,
+    /// Send the finished games kept in flash over the serial protocol
+    #[cfg(feature = "history")]
+    pub report_games: bool,
+    /// Keystroke for the USB keyboard, an index into `KEYS`
+    pub key: Option<u8>,
 }
 
 pub struct Buzz {
@@ -19,6 +28,11 @@ impl Effects {
             set_clock: None,
             buzz: None,
             page_change: None,
+            report_state: false,
+            report_presets: false,
+            #[cfg(feature = "history")]
+            report_games: false,
+            key: None,
         }
     }
     pub fn set_clock(&mut self, clock: bool) {
@@ -45,4 +59,21 @@ impl Effects {
             self.page_change = Some(page);
         }
     }
+
+    pub fn report_state(&mut self) {
+        self.report_state = true;
+    }
+
+    pub fn report_presets(&mut self) {
+        self.report_presets = true;
+    }
+
+    #[cfg(feature = "history")]
+    pub fn report_games(&mut self) {
+        self.report_games = true;
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.key = Some(key);
+    }
 }
@@ -1,14 +1,63 @@
+use embedded_hal_async::i2c::ErrorKind;
+use embedded_storage::nor_flash::NorFlashErrorKind;
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("I2c error")]
-    I2cError(embassy_stm32::i2c::Error),
+    I2cError(ErrorKind),
 
     #[error("IO error: {0}")]
     FormattingError(#[from] core::fmt::Error),
+
+    #[error("Flash error")]
+    FlashError(NorFlashErrorKind),
+
+    #[error("Corrupt record in storage")]
+    CorruptRecord,
 }
 
+// Only the kinds are kept, so the drivers can be swapped for fakes in the tests
+impl From<ErrorKind> for Error {
+    fn from(value: ErrorKind) -> Self {
+        Error::I2cError(value)
+    }
+}
+
+impl From<NorFlashErrorKind> for Error {
+    fn from(value: NorFlashErrorKind) -> Self {
+        Error::FlashError(value)
+    }
+}
+
+#[cfg(target_os = "none")]
 impl From<embassy_stm32::i2c::Error> for Error {
     fn from(value: embassy_stm32::i2c::Error) -> Self {
-        Error::I2cError(value)
+        Error::I2cError(embedded_hal_async::i2c::Error::kind(&value))
+    }
+}
+
+#[cfg(target_os = "none")]
+impl From<embassy_stm32::flash::Error> for Error {
+    fn from(value: embassy_stm32::flash::Error) -> Self {
+        Error::FlashError(embedded_storage::nor_flash::NorFlashError::kind(&value))
+    }
+}
+
+impl defmt::Format for Error {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            Error::I2cError(kind) => defmt::write!(fmt, "I2c error: {}", kind),
+            Error::FormattingError(_) => defmt::write!(fmt, "Formatting error"),
+            Error::FlashError(kind) => {
+                let kind = match kind {
+                    NorFlashErrorKind::NotAligned => "not aligned",
+                    NorFlashErrorKind::OutOfBounds => "out of bounds",
+                    _ => "other",
+                };
+                defmt::write!(fmt, "Flash error: {}", kind)
+            }
+            Error::CorruptRecord => defmt::write!(fmt, "Corrupt record in storage"),
+        }
     }
 }
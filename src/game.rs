@@ -4,10 +4,11 @@ use embassy_time::Duration;
 use crate::{
     app::{Button, Event, Page, PressType},
     aux::{format_secs, CeilTime},
+    display::Frame,
     effect::Effects,
     error::Error,
     menu::{GameConfig, IncrementType},
-    Outputs,
+    protocol::{Side, MAX_SECS},
 };
 
 #[derive(Clone)]
@@ -17,6 +18,8 @@ pub struct GameState {
     pub right_time: Duration,
     pub paused: bool,
     pub delay: Duration,
+    /// Moves of both players so far
+    pub moves: u16,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -25,90 +28,51 @@ pub enum Player {
     Right,
 }
 
+impl From<Side> for Player {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Left => Player::Left,
+            Side::Right => Player::Right,
+        }
+    }
+}
+
+impl From<Player> for Side {
+    fn from(player: Player) -> Self {
+        match player {
+            Player::Left => Side::Left,
+            Player::Right => Side::Right,
+        }
+    }
+}
+
 impl GameState {
     pub fn new(game_config: &GameConfig, first_player: Player) -> GameState {
-        let delay = match game_config.increment_type {
-            IncrementType::Delay {
-                left_delay,
-                right_delay,
-            } => match first_player {
-                Player::Left => left_delay,
-                Player::Right => right_delay,
-            },
-            IncrementType::Bronstein {
-                left_delay,
-                right_delay,
-            } => match first_player {
-                Player::Left => left_delay,
-                Player::Right => right_delay,
-            },
-            _ => Duration::from_ticks(0),
-        };
         Self {
             turn: first_player,
-            left_time: game_config.left_time,
-            right_time: game_config.right_time,
+            left_time: game_config.left.time,
+            right_time: game_config.right.time,
             paused: true,
-            delay,
+            delay: game_config
+                .control(first_player)
+                .increment_type
+                .turn_delay(),
+            moves: 0,
         }
     }
 
     pub fn handle_event(&mut self, effects: &mut Effects, game_config: &GameConfig, event: &Event) {
         match event {
-            Event::ButtonPushed(Button::Left, _) => {
+            Event::ButtonPushed(button @ (Button::Left | Button::Right), _) => {
+                let player = match button {
+                    Button::Left => Player::Left,
+                    _ => Player::Right,
+                };
                 if self.paused {
                     self.paused = false;
                     effects.set_clock(true);
-                } else if self.turn == Player::Left {
-                    match game_config.increment_type {
-                        IncrementType::SuddenDeath { .. } => self.delay = Duration::from_ticks(0),
-                        IncrementType::Increment { left_increment, .. } => {
-                            self.left_time += left_increment;
-                            self.delay = Duration::from_ticks(0)
-                        }
-                        IncrementType::Delay { right_delay, .. } => {
-                            self.delay = right_delay;
-                        }
-                        IncrementType::Bronstein {
-                            left_delay,
-                            right_delay,
-                        } => {
-                            self.left_time += left_delay - self.delay;
-                            self.delay = right_delay;
-                        }
-                    }
-                    self.turn = Player::Right;
-                    effects.buzz(220, Duration::from_millis(50));
-                    info!("Right's turn")
-                }
-            }
-            Event::ButtonPushed(Button::Right, _) => {
-                if self.paused {
-                    self.paused = false;
-                    effects.set_clock(true);
-                } else if self.turn == Player::Right {
-                    match game_config.increment_type {
-                        IncrementType::SuddenDeath { .. } => self.delay = Duration::from_ticks(0),
-                        IncrementType::Increment {
-                            right_increment, ..
-                        } => {
-                            self.right_time += right_increment;
-                            self.delay = Duration::from_ticks(0)
-                        }
-                        IncrementType::Delay { left_delay, .. } => {
-                            self.delay = left_delay;
-                        }
-                        IncrementType::Bronstein {
-                            left_delay,
-                            right_delay,
-                        } => {
-                            self.right_time += right_delay - self.delay;
-                            self.delay = left_delay;
-                        }
-                    }
-                    self.turn = Player::Left;
-                    effects.buzz(220, Duration::from_millis(50));
-                    info!("Left's turn")
+                } else if self.turn == player {
+                    self.end_turn(effects, game_config);
                 }
             }
             Event::ButtonPushed(Button::Control, PressType::Single) => {
@@ -119,18 +83,25 @@ impl GameState {
                 info!("Pause: {}", self.paused);
             }
             Event::ButtonPushed(Button::Control, PressType::Long) => {}
+            Event::Battery(_) | Event::Command(_) => {}
+            #[cfg(feature = "link")]
+            Event::Link(_) => {}
             Event::Clock(duration) => {
                 if !self.paused {
                     if self.delay.as_ticks() != 0 {
-                        match game_config.increment_type {
-                            IncrementType::Delay { .. } => {
+                        match game_config.control(self.turn).increment_type {
+                            IncrementType::Delay(_) => {
                                 self.delay -= *duration;
                             }
-                            IncrementType::Bronstein { .. } => {
+                            IncrementType::Bronstein(_) => {
                                 self.delay -= *duration;
                                 self.decrement_time(effects, duration);
                             }
-                            _ => {}
+                            // No longer a delay of the config, so the time runs on
+                            _ => {
+                                self.delay = Duration::from_ticks(0);
+                                self.decrement_time(effects, duration);
+                            }
                         }
                     } else {
                         self.decrement_time(effects, duration);
@@ -140,6 +111,50 @@ impl GameState {
         }
     }
 
+    /// Applies the increment type of the player to move and hands over to the other one, with
+    /// the delay of their own increment type
+    fn end_turn(&mut self, effects: &mut Effects, game_config: &GameConfig) {
+        let time = match self.turn {
+            Player::Left => &mut self.left_time,
+            Player::Right => &mut self.right_time,
+        };
+        match game_config.control(self.turn).increment_type {
+            IncrementType::SuddenDeath | IncrementType::Delay(_) => {}
+            IncrementType::Increment(increment) => *time += increment,
+            IncrementType::Bronstein(delay) => *time += delay - self.delay,
+        }
+        self.turn = match self.turn {
+            Player::Left => Player::Right,
+            Player::Right => Player::Left,
+        };
+        self.delay = game_config.control(self.turn).increment_type.turn_delay();
+        self.moves = self.moves.saturating_add(1);
+        effects.buzz(220, Duration::from_millis(50));
+        match self.turn {
+            Player::Left => info!("Left's turn"),
+            Player::Right => info!("Right's turn"),
+        }
+    }
+
+    /// Adds the signed number of seconds to the player's time, up to the 99:59 the display shows.
+    /// Running out flags the player.
+    pub fn adjust_time(&mut self, effects: &mut Effects, player: Player, secs: i32) {
+        let time = match player {
+            Player::Left => &mut self.left_time,
+            Player::Right => &mut self.right_time,
+        };
+        let delta = Duration::from_secs(secs.unsigned_abs() as u64);
+        *time = if secs < 0 {
+            time.checked_sub(delta).unwrap_or(Duration::from_ticks(0))
+        } else {
+            (*time + delta).min(Duration::from_secs(MAX_SECS as u64))
+        };
+        if time.as_ticks() == 0 {
+            effects.page_change(Page::GameOver(player));
+            effects.set_clock(false);
+        }
+    }
+
     fn decrement_time(&mut self, effects: &mut Effects, duration: &Duration) {
         let prev_left_time = self.left_time;
         let prev_right_time = self.right_time;
@@ -180,58 +195,165 @@ impl GameState {
         }
     }
 
-    pub async fn display_state(
-        &self,
-        prev_state: Option<&GameState>,
-        outputs: &mut Outputs<'_, '_>,
-    ) -> Result<(), Error> {
-        if Some(self.turn) != prev_state.map(|s| s.turn) {
-            match self.turn {
-                Player::Left => {
-                    outputs.left_led.set_high();
-                    outputs.right_led.set_low();
-                }
-                Player::Right => {
-                    outputs.left_led.set_low();
-                    outputs.right_led.set_high();
-                }
-            }
+    pub fn view(&self) -> Result<Frame, Error> {
+        let mut frame = Frame::new();
+        frame.print(0, 0, format_secs(self.left_time.ceil_secs())?.as_str());
+        frame.print(0, 11, format_secs(self.right_time.ceil_secs())?.as_str());
+        if self.paused {
+            frame.print(1, 5, "paused");
         }
+        Ok(frame)
+    }
+}
 
-        let prev_left_secs = prev_state.map(|s| s.left_time.ceil_secs());
-        let left_secs = self.left_time.ceil_secs();
+fn time_passing(set_time: &Duration, prev_time: &Duration, time: &Duration) -> bool {
+    prev_time > set_time && set_time >= time
+}
 
-        if prev_left_secs.is_none() || prev_left_secs != Some(left_secs) {
-            outputs.lcd.set_cursor(0, 0).await?;
-            outputs
-                .lcd
-                .write_str(format_secs(left_secs)?.as_str())
-                .await?;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let prev_right_secs = prev_state.map(|s| s.right_time.ceil_secs());
-        let right_secs = self.right_time.ceil_secs();
+    fn game_state() -> GameState {
+        GameState::new(&GameConfig::default(), Player::Left)
+    }
 
-        if prev_right_secs.is_none() || prev_right_secs != Some(right_secs) {
-            outputs.lcd.set_cursor(0, 11).await?;
-            outputs
-                .lcd
-                .write_str(format_secs(right_secs)?.as_str())
-                .await?;
-        }
+    #[test]
+    fn adjusting_stops_at_the_longest_time_shown() {
+        let mut game_state = game_state();
+        let mut effects = Effects::new();
+        game_state.adjust_time(&mut effects, Player::Left, 5999);
+        assert_eq!(game_state.left_time, Duration::from_secs(5999));
+        assert!(format_secs(game_state.left_time.as_secs()).is_ok());
+        assert!(effects.page_change.is_none());
+    }
 
-        if prev_state.map(|s| s.paused) != Some(self.paused) {
-            outputs.lcd.set_cursor(1, 5).await?;
-            if self.paused {
-                outputs.lcd.write_str("paused").await?;
-            } else {
-                outputs.lcd.write_str("      ").await?;
-            }
-        }
-        Ok(())
+    #[test]
+    fn adjusting_to_zero_flags_the_player() {
+        let mut game_state = game_state();
+        let mut effects = Effects::new();
+        game_state.adjust_time(&mut effects, Player::Right, -5999);
+        assert_eq!(game_state.right_time.as_ticks(), 0);
+        assert!(matches!(
+            effects.page_change,
+            Some(Page::GameOver(Player::Right))
+        ));
+        assert_eq!(effects.set_clock, Some(false));
     }
-}
 
-fn time_passing(set_time: &Duration, prev_time: &Duration, time: &Duration) -> bool {
-    prev_time > set_time && set_time >= time
+    /// Left with a delay of five seconds and right with an increment of two
+    fn delay_and_increment() -> GameConfig {
+        let mut game_config = GameConfig::default();
+        game_config.left.increment_type = IncrementType::Delay(Duration::from_secs(5));
+        game_config.right.increment_type = IncrementType::Increment(Duration::from_secs(2));
+        game_config
+    }
+
+    #[test]
+    fn a_delay_the_config_no_longer_has_lets_the_time_run() {
+        let mut game_state = GameState::new(&delay_and_increment(), Player::Left);
+        game_state.paused = false;
+        let start = game_state.left_time;
+        let mut game_config = delay_and_increment();
+        game_config.left.increment_type = IncrementType::SuddenDeath;
+        let mut effects = Effects::new();
+        game_state.handle_event(
+            &mut effects,
+            &game_config,
+            &Event::Clock(Duration::from_secs(1)),
+        );
+        assert_eq!(game_state.delay.as_ticks(), 0);
+        assert_eq!(game_state.left_time, start - Duration::from_secs(1));
+    }
+
+    #[test]
+    fn a_config_sent_during_a_game_waits_for_it_to_end() {
+        use crate::{
+            protocol::{Command, Config, IncrementKind},
+            testing::app_state,
+        };
+
+        let mut state = app_state();
+        let first = state.game_config.clone();
+        state.page = Page::Game(GameState::new(&first, Player::Left));
+        let config = Config {
+            left_secs: 60,
+            right_secs: 60,
+            left_kind: IncrementKind::SuddenDeath,
+            right_kind: IncrementKind::SuddenDeath,
+            left_increment_secs: 0,
+            right_increment_secs: 0,
+        };
+        state.handle_event(&mut Effects::new(), Event::Command(Command::Config(config)));
+        assert!(state.game_config == first);
+
+        state.page = Page::GameOver(Player::Left);
+        state.handle_event(&mut Effects::new(), Event::Clock(Duration::from_secs(1)));
+        assert_eq!(state.game_config.left.time, Duration::from_secs(60));
+        assert!(state.deferred_config.is_none());
+    }
+
+    fn event(game_state: &mut GameState, game_config: &GameConfig, event: Event) {
+        game_state.handle_event(&mut Effects::new(), game_config, &event);
+    }
+
+    fn secs(secs: u64) -> Event {
+        Event::Clock(Duration::from_secs(secs))
+    }
+
+    fn press(button: Button) -> Event {
+        Event::ButtonPushed(button, PressType::Single)
+    }
+
+    #[test]
+    fn each_player_moves_with_their_own_increment() {
+        let mut game_config = GameConfig::default();
+        game_config.left.increment_type = IncrementType::Bronstein(Duration::from_secs(10));
+        game_config.right.increment_type = IncrementType::Increment(Duration::from_secs(5));
+        let mut game_state = GameState::new(&game_config, Player::Left);
+        game_state.paused = false;
+        let (start, right_start) = (game_state.left_time, game_state.right_time);
+        assert_eq!(game_state.delay, Duration::from_secs(10));
+
+        // Bronstein gives back the time used, up to the delay
+        event(&mut game_state, &game_config, secs(3));
+        assert_eq!(game_state.left_time, start - Duration::from_secs(3));
+        event(&mut game_state, &game_config, press(Button::Left));
+        assert_eq!(game_state.left_time, start);
+        assert_eq!(game_state.delay.as_ticks(), 0);
+
+        // Fischer adds the whole increment, whatever the time used
+        event(&mut game_state, &game_config, secs(4));
+        assert_eq!(game_state.right_time, right_start - Duration::from_secs(4));
+        event(&mut game_state, &game_config, press(Button::Right));
+        assert_eq!(game_state.right_time, right_start + Duration::from_secs(1));
+        assert!(game_state.turn == Player::Left);
+        assert_eq!(game_state.delay, Duration::from_secs(10));
+        assert_eq!(game_state.left_time, start);
+    }
+
+    #[test]
+    fn the_next_player_gets_the_delay_of_their_own_control() {
+        let mut game_config = GameConfig::default();
+        game_config.left.increment_type = IncrementType::Increment(Duration::from_secs(2));
+        game_config.right.increment_type = IncrementType::Delay(Duration::from_secs(5));
+        let mut game_state = GameState::new(&game_config, Player::Left);
+        game_state.paused = false;
+        let (left_start, start) = (game_state.left_time, game_state.right_time);
+        assert_eq!(game_state.delay.as_ticks(), 0);
+
+        event(&mut game_state, &game_config, press(Button::Left));
+        assert_eq!(game_state.delay, Duration::from_secs(5));
+        // The delay runs out before the time
+        event(&mut game_state, &game_config, secs(5));
+        assert_eq!(game_state.right_time, start);
+        event(&mut game_state, &game_config, secs(1));
+        assert_eq!(game_state.right_time, start - Duration::from_secs(1));
+
+        // A simple delay adds nothing, and the increment of the left player has none
+        event(&mut game_state, &game_config, press(Button::Right));
+        assert_eq!(game_state.right_time, start - Duration::from_secs(1));
+        assert_eq!(game_state.delay.as_ticks(), 0);
+        assert_eq!(game_state.left_time, left_start + Duration::from_secs(2));
+    }
 }
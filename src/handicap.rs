@@ -0,0 +1,63 @@
+//! Adaptive handicap, moving time from the winner to the loser after every game so the odds follow
+//! the strength of the players.
+//!
+//! The handicap belongs to the sides of the clock, not to the players, so they keep their seats
+//! from one game to the next.
+
+use embassy_time::Duration;
+
+use crate::{game::Player, menu::GameConfig, settings::Settings};
+
+/// Takes the step off the winner's time and gives it to the loser, short of the bounds of the
+/// settings and never below a second, does nothing while the step is zero. Only the step is held
+/// back at a bound, a time already past it is left as it is.
+pub fn adapt(settings: &Settings, game_config: &mut GameConfig, loser: Player) {
+    let step = settings.handicap_step;
+    if step.as_ticks() == 0 {
+        return;
+    }
+    let winner = match loser {
+        Player::Left => Player::Right,
+        Player::Right => Player::Left,
+    };
+    let zero = Duration::from_ticks(0);
+    let min = settings.handicap_min.max(Duration::from_secs(1));
+    let time = &mut game_config.control_mut(winner).time;
+    *time -= step.min(time.checked_sub(min).unwrap_or(zero));
+    let time = &mut game_config.control_mut(loser).time;
+    *time += step.min(settings.handicap_max.checked_sub(*time).unwrap_or(zero));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::app_state;
+
+    fn adapted(left: u64, right: u64, loser: Player) -> (u64, u64) {
+        let mut state = app_state();
+        state.settings.handicap_step = Duration::from_secs(30);
+        state.settings.handicap_min = Duration::from_secs(60);
+        state.settings.handicap_max = Duration::from_secs(600);
+        state.game_config.left.time = Duration::from_secs(left);
+        state.game_config.right.time = Duration::from_secs(right);
+        adapt(&state.settings, &mut state.game_config, loser);
+        let config = &state.game_config;
+        (config.left.time.as_secs(), config.right.time.as_secs())
+    }
+
+    #[test]
+    fn the_step_goes_from_the_winner_to_the_loser() {
+        assert_eq!(adapted(300, 300, Player::Left), (330, 270));
+        assert_eq!(adapted(300, 300, Player::Right), (270, 330));
+    }
+
+    #[test]
+    fn the_step_stops_at_the_bounds() {
+        assert_eq!(adapted(590, 70, Player::Left), (600, 60));
+    }
+
+    #[test]
+    fn times_past_the_bounds_are_left_alone() {
+        assert_eq!(adapted(900, 30, Player::Left), (900, 30));
+    }
+}
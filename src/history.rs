@@ -0,0 +1,150 @@
+//! Finished games kept in flash for the computer to download. There is no room for their moves,
+//! so only how they ended is kept.
+
+use embassy_time::Duration;
+use heapless::Vec;
+
+use crate::{
+    game::{GameState, Player},
+    protocol::GameRecord,
+};
+
+/// Games kept, the oldest makes room for the next one
+pub const MAX_GAMES: usize = 8;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct GameLog {
+    /// The player who moved first
+    pub first: Player,
+    /// `None` for a draw
+    pub winner: Option<Player>,
+    /// Moves of both players
+    pub moves: u16,
+    pub left_time: Duration,
+    pub right_time: Duration,
+}
+
+/// The latest finished games, the oldest first
+pub type History = Vec<GameLog, MAX_GAMES>;
+
+impl GameLog {
+    /// The game as it ended, with the player who lost it or `None` for a draw
+    pub fn of(game_state: &GameState, loser: Option<Player>) -> GameLog {
+        let other = |player| match player {
+            Player::Left => Player::Right,
+            Player::Right => Player::Left,
+        };
+        GameLog {
+            first: match game_state.moves % 2 {
+                0 => game_state.turn,
+                _ => other(game_state.turn),
+            },
+            winner: loser.map(other),
+            moves: game_state.moves,
+            left_time: game_state.left_time,
+            right_time: game_state.right_time,
+        }
+    }
+}
+
+/// Adds the game to the history, dropping the oldest one when it is full
+pub fn record(history: &mut History, game_log: GameLog) {
+    if history.is_full() {
+        history.remove(0);
+    }
+    let _ = history.push(game_log);
+}
+
+impl From<&GameLog> for GameRecord {
+    fn from(game_log: &GameLog) -> Self {
+        GameRecord {
+            first: game_log.first.into(),
+            winner: game_log.winner.map(Into::into),
+            moves: game_log.moves,
+            left_ms: game_log.left_time.as_millis() as u32,
+            right_ms: game_log.right_time.as_millis() as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app::{Button, Event, Page, PressType},
+        effect::Effects,
+        menu::GameConfig,
+        testing::app_state,
+    };
+
+    fn game_log(moves: u16) -> GameLog {
+        GameLog {
+            first: Player::Left,
+            winner: None,
+            moves,
+            left_time: Duration::from_secs(10),
+            right_time: Duration::from_secs(20),
+        }
+    }
+
+    #[test]
+    fn the_first_player_is_found_from_the_moves() {
+        let game_config = GameConfig::default();
+        let mut game_state = GameState::new(&game_config, Player::Right);
+        game_state.paused = false;
+        game_state.handle_event(
+            &mut Effects::new(),
+            &game_config,
+            &Event::ButtonPushed(Button::Right, PressType::Single),
+        );
+        assert!(game_state.turn == Player::Left);
+        assert_eq!(game_state.moves, 1);
+
+        let game_log = GameLog::of(&game_state, Some(Player::Left));
+        assert!(game_log.first == Player::Right);
+        assert!(game_log.winner == Some(Player::Right));
+        assert_eq!(game_log.moves, 1);
+    }
+
+    #[test]
+    fn the_oldest_game_makes_room() {
+        let mut history = History::new();
+        for moves in 0..MAX_GAMES as u16 + 2 {
+            record(&mut history, game_log(moves));
+        }
+        assert_eq!(history.len(), MAX_GAMES);
+        assert_eq!(history[0].moves, 2);
+        assert_eq!(history[MAX_GAMES - 1].moves, MAX_GAMES as u16 + 1);
+    }
+
+    #[test]
+    fn flags_and_results_entered_by_hand_are_kept() {
+        let mut state = app_state();
+        let mut game_state = GameState::new(&state.game_config, Player::Left);
+        game_state.paused = false;
+        game_state.left_time = Duration::from_millis(100);
+        state.page = Page::Game(game_state);
+        state.handle_event(
+            &mut Effects::new(),
+            Event::Clock(Duration::from_millis(100)),
+        );
+        assert_eq!(state.history.len(), 1);
+        assert!(state.history[0].winner == Some(Player::Right));
+        assert_eq!(state.history[0].left_time.as_ticks(), 0);
+
+        // A draw, on the result page the handicap brings
+        state.settings.handicap_step = Duration::from_secs(30);
+        state.page = Page::Game(GameState::new(&state.game_config, Player::Right));
+        for event in [
+            Event::ButtonPushed(Button::Right, PressType::Long),
+            Event::ButtonPushed(Button::Right, PressType::Single),
+            Event::ButtonPushed(Button::Right, PressType::Single),
+            Event::ButtonPushed(Button::Control, PressType::Single),
+        ] {
+            state.handle_event(&mut Effects::new(), event);
+        }
+        assert!(matches!(state.page, Page::Welcome));
+        assert_eq!(state.history.len(), 2);
+        assert!(state.history[1].first == Player::Right && state.history[1].winner.is_none());
+    }
+}
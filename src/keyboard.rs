@@ -0,0 +1,149 @@
+//! Keystrokes sent by the player buttons when the clock acts as a USB keyboard.
+
+/// What the player buttons do besides running the clock
+#[derive(Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum KeyboardMode {
+    Off,
+    /// The buttons type their key and run the clock as usual
+    WithClock,
+    /// The buttons only type their key, the clock stays on the welcome page
+    KeysOnly,
+}
+
+impl KeyboardMode {
+    pub const ALL: [KeyboardMode; 3] = [
+        KeyboardMode::Off,
+        KeyboardMode::WithClock,
+        KeyboardMode::KeysOnly,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            KeyboardMode::Off => "Off",
+            KeyboardMode::WithClock => "With clock",
+            KeyboardMode::KeysOnly => "Keys only",
+        }
+    }
+}
+
+pub struct Key {
+    pub label: &'static str,
+    /// Modifier bits of the boot keyboard report
+    pub modifiers: u8,
+    /// HID usage code of the keyboard page
+    pub usage: u8,
+}
+
+const CTRL: u8 = 0x01;
+const SHIFT: u8 = 0x02;
+
+/// Keys the buttons can be set to, settings store the index
+pub const KEYS: [Key; 10] = [
+    key("Space", 0, 0x2c),
+    key("Enter", 0, 0x28),
+    key("Tab", 0, 0x2b),
+    key("Left arrow", 0, 0x50),
+    key("Right arrow", 0, 0x4f),
+    key("Up arrow", 0, 0x52),
+    key("Down arrow", 0, 0x51),
+    key("Ctrl+Space", CTRL, 0x2c),
+    key("Ctrl+Enter", CTRL, 0x28),
+    key("Shift+Space", SHIFT, 0x2c),
+];
+
+const fn key(label: &'static str, modifiers: u8, usage: u8) -> Key {
+    Key {
+        label,
+        modifiers,
+        usage,
+    }
+}
+
+/// Looks up a stored key, unknown indices fall back to the first key
+pub fn get(index: u8) -> &'static Key {
+    KEYS.get(index as usize).unwrap_or(&KEYS[0])
+}
+
+pub const REPORT_SIZE: usize = 8;
+
+/// Boot keyboard input report, modifiers, a reserved byte and up to six keys
+pub fn report(key: Option<&Key>) -> [u8; REPORT_SIZE] {
+    let mut report = [0; REPORT_SIZE];
+    if let Some(key) = key {
+        report[0] = key.modifiers;
+        report[2] = key.usage;
+    }
+    report
+}
+
+/// Report descriptor of the boot keyboard, without the LED output report
+pub const REPORT_DESCRIPTOR: [u8; 43] = [
+    0x05, 0x01, // Usage page (generic desktop)
+    0x09, 0x06, // Usage (keyboard)
+    0xa1, 0x01, // Collection (application)
+    0x05, 0x07, //   Usage page (keyboard)
+    0x19, 0xe0, //   Usage minimum (left control)
+    0x29, 0xe7, //   Usage maximum (right GUI)
+    0x15, 0x00, //   Logical minimum (0)
+    0x25, 0x01, //   Logical maximum (1)
+    0x75, 0x01, //   Report size (1)
+    0x95, 0x08, //   Report count (8)
+    0x81, 0x02, //   Input (data, variable, absolute), the modifiers
+    0x95, 0x01, //   Report count (1)
+    0x75, 0x08, //   Report size (8)
+    0x81, 0x01, //   Input (constant), the reserved byte
+    0x95, 0x06, //   Report count (6)
+    0x75, 0x08, //   Report size (8)
+    0x15, 0x00, //   Logical minimum (0)
+    0x25, 0x65, //   Logical maximum (101)
+    0x19, 0x00, //   Usage minimum (0)
+    0x29, 0x65, //   Usage maximum (101)
+    0x81, 0x00, //   Input (data, array), the keys
+    0xc0, // End collection
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_keystroke_is_a_press_report_and_an_empty_one() {
+        assert_eq!(report(Some(&KEYS[0])), [0, 0, 0x2c, 0, 0, 0, 0, 0]);
+        assert_eq!(report(Some(&KEYS[4])), [0, 0, 0x4f, 0, 0, 0, 0, 0]);
+        assert_eq!(report(None), [0; REPORT_SIZE]);
+    }
+
+    #[test]
+    fn modifiers_go_in_the_first_byte() {
+        let ctrl_enter = KEYS.iter().find(|key| key.label == "Ctrl+Enter").unwrap();
+        assert_eq!(report(Some(ctrl_enter)), [0x01, 0, 0x28, 0, 0, 0, 0, 0]);
+        let shift_space = KEYS.iter().find(|key| key.label == "Shift+Space").unwrap();
+        assert_eq!(report(Some(shift_space)), [0x02, 0, 0x2c, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn unknown_stored_keys_fall_back_to_the_first() {
+        assert_eq!(get(3).label, "Left arrow");
+        assert_eq!(get(KEYS.len() as u8).label, KEYS[0].label);
+        assert_eq!(get(u8::MAX).label, KEYS[0].label);
+    }
+
+    #[test]
+    fn the_descriptor_describes_the_reports_sent() {
+        // Adds up the input items, each sized by the last report size and count
+        let (mut size, mut count, mut bits) = (0, 0, 0);
+        let mut max_usage = 0;
+        for item in REPORT_DESCRIPTOR[..REPORT_DESCRIPTOR.len() - 1].chunks_exact(2) {
+            match item[0] {
+                0x75 => size = item[1] as usize,
+                0x95 => count = item[1] as usize,
+                0x81 => bits += size * count,
+                0x29 => max_usage = item[1],
+                _ => {}
+            }
+        }
+        assert_eq!(bits, REPORT_SIZE * 8);
+        assert_eq!(REPORT_DESCRIPTOR.last(), Some(&0xc0));
+        assert!(KEYS.iter().all(|key| key.usage <= max_usage));
+    }
+}
@@ -0,0 +1,288 @@
+use defmt::info;
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+/// Bitmap of a custom character, one byte per pixel row (lower 5 bits)
+pub type Glyph = [u8; 8];
+
+// PCF8574 backpack pins
+const REGISTER_SELECT: u8 = 0x01;
+const ENABLE: u8 = 0x04;
+const BACKLIGHT: u8 = 0x08;
+
+/// Addresses of the PCF8574 (0x20-0x27) and PCF8574A (0x38-0x3F) backpacks
+const BACKPACK_ADDRESSES: [core::ops::RangeInclusive<u8>; 2] = [0x20..=0x27, 0x38..=0x3f];
+
+// HD44780 instructions
+const CLEAR: u8 = 0x01;
+const ENTRY_MODE_INCREMENT: u8 = 0x06;
+const DISPLAY_CONTROL: u8 = 0x08;
+const DISPLAY_ON: u8 = 0x04;
+const CURSOR_ON: u8 = 0x02;
+const CURSOR_BLINK: u8 = 0x01;
+const FUNCTION_SET_8BIT: u8 = 0x30;
+const FUNCTION_SET_4BIT: u8 = 0x20;
+const TWO_LINES: u8 = 0x08;
+const SET_CGRAM_ADDRESS: u8 = 0x40;
+const SET_DDRAM_ADDRESS: u8 = 0x80;
+
+/// HD44780 character LCD behind a PCF8574 I2C backpack, driven in 4 bit mode.
+///
+/// Every byte is sent as a single I2C transfer with the enable pulses included, so apart from
+/// clearing the display no extra delays are needed between writes.
+pub struct Lcd<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+    backlight: u8,
+    display_control: u8,
+}
+
+impl<I, D> Lcd<I, D>
+where
+    I: I2c,
+    D: DelayNs,
+{
+    pub fn new(i2c: I, delay: D, address: u8) -> Self {
+        Self {
+            i2c,
+            delay,
+            address,
+            backlight: BACKLIGHT,
+            display_control: DISPLAY_ON,
+        }
+    }
+
+    /// Runs the power on initialisation sequence, switching the controller into 4 bit mode.
+    pub async fn init(&mut self) -> Result<(), I::Error> {
+        self.delay.delay_ms(50).await;
+        self.i2c.write(self.address, &[self.backlight]).await?;
+
+        for _ in 0..3 {
+            self.write_nibble(FUNCTION_SET_8BIT, 0).await?;
+            self.delay.delay_ms(5).await;
+        }
+        self.write_nibble(FUNCTION_SET_4BIT, 0).await?;
+
+        self.command(FUNCTION_SET_4BIT | TWO_LINES).await?;
+        self.command(DISPLAY_CONTROL | self.display_control).await?;
+        self.command(ENTRY_MODE_INCREMENT).await?;
+        self.clear().await
+    }
+
+    pub async fn backlight(&mut self, on: bool) -> Result<(), I::Error> {
+        self.backlight = if on { BACKLIGHT } else { 0 };
+        self.i2c.write(self.address, &[self.backlight]).await
+    }
+
+    pub async fn clear(&mut self) -> Result<(), I::Error> {
+        self.command(CLEAR).await?;
+        self.delay.delay_ms(2).await;
+        Ok(())
+    }
+
+    /// Moves the cursor to (row, col), zero based.
+    pub async fn set_cursor(&mut self, row: u8, col: u8) -> Result<(), I::Error> {
+        self.command(SET_DDRAM_ADDRESS | (row * 0x40 + col)).await
+    }
+
+    pub async fn set_cursor_mode(&mut self, on: bool, blink: bool) -> Result<(), I::Error> {
+        let mut display_control = DISPLAY_ON;
+        if on {
+            display_control |= CURSOR_ON;
+        }
+        if blink {
+            display_control |= CURSOR_BLINK;
+        }
+        self.display_control = display_control;
+        self.command(DISPLAY_CONTROL | display_control).await
+    }
+
+    /// Writes raw character codes at the cursor position.
+    pub async fn write_bytes(&mut self, data: &[u8]) -> Result<(), I::Error> {
+        for byte in data {
+            self.send(*byte, REGISTER_SELECT).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores a custom character in CGRAM, it can be displayed with the character code `location`.
+    ///
+    /// The cursor position is lost, it has to be set before writing characters again.
+    pub async fn create_char(&mut self, location: u8, glyph: &Glyph) -> Result<(), I::Error> {
+        self.command(SET_CGRAM_ADDRESS | ((location & 0x07) << 3))
+            .await?;
+        self.write_bytes(glyph).await
+    }
+
+    async fn command(&mut self, data: u8) -> Result<(), I::Error> {
+        self.send(data, 0).await
+    }
+
+    async fn send(&mut self, data: u8, mode: u8) -> Result<(), I::Error> {
+        let high = (data & 0xf0) | mode | self.backlight;
+        let low = ((data << 4) & 0xf0) | mode | self.backlight;
+        self.i2c
+            .write(self.address, &[high | ENABLE, high, low | ENABLE, low])
+            .await
+    }
+
+    async fn write_nibble(&mut self, nibble: u8, mode: u8) -> Result<(), I::Error> {
+        let data = (nibble & 0xf0) | mode | self.backlight;
+        self.i2c.write(self.address, &[data | ENABLE, data]).await
+    }
+}
+
+/// Scans the bus for LCD backpacks, preferring the previously used address if it still answers.
+pub async fn find_address<I: I2c>(i2c: &mut I, preferred: u8) -> Option<u8> {
+    let mut found = None;
+    for address in BACKPACK_ADDRESSES.into_iter().flatten() {
+        // Writing the backlight bit is harmless for the backpack
+        if i2c.write(address, &[BACKLIGHT]).await.is_ok() {
+            info!("I2C device found at {=u8:#x}", address);
+            if found.is_none() || address == preferred {
+                found = Some(address);
+            }
+        }
+    }
+
+    match found {
+        Some(address) => info!("Using LCD at {=u8:#x}", address),
+        None => info!("No LCD found on the I2C bus"),
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::testing::{FakeLcd, LcdOp::*, NoDelay};
+
+    #[test]
+    fn init_switches_to_4_bit_mode() {
+        let fake = FakeLcd::default();
+        let mut lcd = Lcd::new(fake.clone(), NoDelay, 0x27);
+        block_on(lcd.init()).unwrap();
+        assert_eq!(
+            fake.take(),
+            [
+                Backlight(true),
+                Nibble(0x30),
+                Nibble(0x30),
+                Nibble(0x30),
+                Nibble(0x20),
+                // Two lines, display on, cursor moving right, cleared
+                Command(0x28),
+                Command(0x0c),
+                Command(0x06),
+                Command(0x01),
+            ]
+        );
+    }
+
+    #[test]
+    fn bytes_are_sent_as_two_nibbles_with_enable_pulses() {
+        let mut raw = Vec::new();
+        let mut recorder = Recorder(&mut raw);
+        let mut lcd = Lcd::new(&mut recorder, NoDelay, 0x27);
+        block_on(lcd.write_bytes(b"A")).unwrap();
+        // Register select and backlight on, enable high then low for each nibble
+        assert_eq!(raw, [[0x4d, 0x49, 0x1d, 0x19]]);
+    }
+
+    #[test]
+    fn cursor_positions_address_both_rows() {
+        let fake = FakeLcd::default();
+        let mut lcd = Lcd::new(fake.clone(), NoDelay, 0x27);
+        block_on(async {
+            lcd.set_cursor(0, 3).await?;
+            lcd.set_cursor(1, 15).await?;
+            lcd.set_cursor_mode(true, false).await?;
+            lcd.set_cursor_mode(false, true).await
+        })
+        .unwrap();
+        assert_eq!(
+            fake.take(),
+            [Command(0x83), Command(0xcf), Command(0x0e), Command(0x0d)]
+        );
+    }
+
+    #[test]
+    fn custom_characters_go_to_their_cgram_slot() {
+        let fake = FakeLcd::default();
+        let mut lcd = Lcd::new(fake.clone(), NoDelay, 0x27);
+        let glyph = [1, 2, 3, 4, 5, 6, 7, 8];
+        block_on(lcd.create_char(2, &glyph)).unwrap();
+        let mut expected = vec![Command(0x50)];
+        expected.extend(glyph.map(Data));
+        assert_eq!(fake.take(), expected);
+    }
+
+    #[test]
+    fn backlight_is_kept_in_every_transfer() {
+        let mut raw = Vec::new();
+        let mut recorder = Recorder(&mut raw);
+        let mut lcd = Lcd::new(&mut recorder, NoDelay, 0x27);
+        block_on(async {
+            lcd.backlight(false).await?;
+            lcd.write_bytes(b"A").await
+        })
+        .unwrap();
+        assert_eq!(raw, [vec![0x00], vec![0x45, 0x41, 0x15, 0x11]]);
+    }
+
+    #[test]
+    fn backpacks_are_found_preferring_the_saved_address() {
+        let mut bus = Backpacks(&[0x20, 0x27, 0x3f]);
+        assert_eq!(block_on(find_address(&mut bus, 0x27)), Some(0x27));
+        assert_eq!(block_on(find_address(&mut bus, 0x38)), Some(0x20));
+        let mut empty = Backpacks(&[]);
+        assert_eq!(block_on(find_address(&mut empty, 0x27)), None);
+    }
+
+    /// Keeps the raw bytes of the transfers
+    struct Recorder<'a>(&'a mut Vec<Vec<u8>>);
+
+    impl embedded_hal::i2c::ErrorType for Recorder<'_> {
+        type Error = embedded_hal::i2c::ErrorKind;
+    }
+
+    impl I2c for Recorder<'_> {
+        async fn transaction(
+            &mut self,
+            _address: u8,
+            operations: &mut [embedded_hal::i2c::Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            for operation in operations {
+                if let embedded_hal::i2c::Operation::Write(bytes) = operation {
+                    self.0.push(bytes.to_vec());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Bus with backpacks answering at the given addresses
+    struct Backpacks(&'static [u8]);
+
+    impl embedded_hal::i2c::ErrorType for Backpacks {
+        type Error = embedded_hal::i2c::ErrorKind;
+    }
+
+    impl I2c for Backpacks {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            _operations: &mut [embedded_hal::i2c::Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            if self.0.contains(&address) {
+                Ok(())
+            } else {
+                Err(embedded_hal::i2c::ErrorKind::NoAcknowledge(
+                    embedded_hal::i2c::NoAcknowledgeSource::Address,
+                ))
+            }
+        }
+    }
+}
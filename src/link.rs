@@ -0,0 +1,308 @@
+//! Link between the two clocks of a bughouse match, over a serial line between the devices.
+//!
+//! Each clock tells its partner when its game starts, pauses or ends on a flag, and sends its
+//! times on every tick. The partner mirrors start, pause and flag on its own game and shows the
+//! times on the second row. Both clocks have to be set up the same way round, with white on the
+//! same side, so a start on one board runs the same side on the other.
+//!
+//! Messages are ASCII lines: `START,<L|R>`, `PAUSE`, `FLAG,<L|R>` and
+//! `TIMES,<left ms>,<right ms>`. Only changes made on the clock itself are sent, what comes from
+//! the partner isn't echoed back.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::{
+    aux::format_secs,
+    display::Frame,
+    error::Error,
+    protocol::{PageKind, Side, Status},
+};
+
+/// Longest line, a `TIMES` message with two ten digit times
+pub const MAX_LINE: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum LinkMessage {
+    /// The game started or resumed with the time of the side running
+    Start(Side),
+    Pause,
+    /// The side ran out of time, the match is over on both boards
+    Flag(Side),
+    Times {
+        left_ms: u32,
+        right_ms: u32,
+    },
+}
+
+/// Game of the partner board, as reported over the link
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Partner {
+    pub left_ms: u32,
+    pub right_ms: u32,
+    pub flagged: Option<Side>,
+}
+
+impl Partner {
+    /// Shows the partner's times at both ends of the row, and the flag in between
+    pub fn view(&self, frame: &mut Frame, row: usize) -> Result<(), Error> {
+        let secs = |millis: u32| millis.div_ceil(1000) as u64;
+        frame.print(row, 0, format_secs(secs(self.left_ms))?.as_str());
+        frame.print(row, 11, format_secs(secs(self.right_ms))?.as_str());
+        match self.flagged {
+            Some(Side::Left) => frame.print(row, 5, "<flag "),
+            Some(Side::Right) => frame.print(row, 5, " flag>"),
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Messages telling the partner about the change from one state to the next
+pub fn changes(prev: &Status, next: &Status) -> impl Iterator<Item = LinkMessage> {
+    let mut changes: [Option<LinkMessage>; 2] = [None; 2];
+    let was_running = prev.page == PageKind::Game && !prev.paused;
+    match next.page {
+        PageKind::Game if !next.paused && !was_running => {
+            changes[0] = Some(LinkMessage::Start(next.turn))
+        }
+        PageKind::Game if next.paused && was_running => changes[0] = Some(LinkMessage::Pause),
+        PageKind::GameOver if prev.page != PageKind::GameOver => {
+            changes[0] = Some(LinkMessage::Flag(next.turn))
+        }
+        _ => {}
+    }
+    if next.page == PageKind::Game && (prev.left_ms, prev.right_ms) != (next.left_ms, next.right_ms)
+    {
+        changes[1] = Some(LinkMessage::Times {
+            left_ms: next.left_ms,
+            right_ms: next.right_ms,
+        });
+    }
+    changes.into_iter().flatten()
+}
+
+pub fn encode(message: &LinkMessage) -> String<MAX_LINE> {
+    let mut line = String::new();
+    // The longest message fits in the line
+    let _ = match *message {
+        LinkMessage::Start(side) => writeln!(line, "START,{}", side_letter(side)),
+        LinkMessage::Pause => writeln!(line, "PAUSE"),
+        LinkMessage::Flag(side) => writeln!(line, "FLAG,{}", side_letter(side)),
+        LinkMessage::Times { left_ms, right_ms } => {
+            writeln!(line, "TIMES,{},{}", left_ms, right_ms)
+        }
+    };
+    line
+}
+
+fn side_letter(side: Side) -> char {
+    match side {
+        Side::Left => 'L',
+        Side::Right => 'R',
+    }
+}
+
+fn decode(line: &str) -> Option<LinkMessage> {
+    let mut fields = line.split(',');
+    let side = |field: Option<&str>| match field {
+        Some("L") => Some(Side::Left),
+        Some("R") => Some(Side::Right),
+        _ => None,
+    };
+    let message = match fields.next()? {
+        "START" => LinkMessage::Start(side(fields.next())?),
+        "PAUSE" => LinkMessage::Pause,
+        "FLAG" => LinkMessage::Flag(side(fields.next())?),
+        "TIMES" => LinkMessage::Times {
+            left_ms: fields.next()?.parse().ok()?,
+            right_ms: fields.next()?.parse().ok()?,
+        },
+        _ => return None,
+    };
+    fields.next().is_none().then_some(message)
+}
+
+/// Collects the bytes from the partner into messages, lines that don't decode are dropped
+pub struct Decoder {
+    line: String<MAX_LINE>,
+    overflow: bool,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder {
+            line: String::new(),
+            overflow: false,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<LinkMessage> {
+        match byte {
+            b'\n' => {
+                let message = if self.overflow {
+                    None
+                } else {
+                    decode(self.line.trim_end_matches('\r'))
+                };
+                self.line.clear();
+                self.overflow = false;
+                message
+            }
+            _ => {
+                if !byte.is_ascii() || self.line.push(byte as char).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_time::Duration;
+
+    use super::*;
+    use crate::{
+        app::{AppState, Button, Event, Page, PressType},
+        effect::Effects,
+        menu::{GameConfig, IncrementType, TimeControl},
+        testing::app_state,
+    };
+
+    /// One of the clocks, with the bytes it received from its partner
+    struct Board {
+        state: AppState,
+        decoder: Decoder,
+    }
+
+    impl Board {
+        fn new() -> Self {
+            let mut state = app_state();
+            let control = TimeControl {
+                time: Duration::from_secs(5),
+                increment_type: IncrementType::SuddenDeath,
+            };
+            state.game_config = GameConfig {
+                left: control,
+                right: control,
+                ..GameConfig::default()
+            };
+            Board {
+                state,
+                decoder: Decoder::new(),
+            }
+        }
+
+        /// Handles the event like the main loop, returns the lines for the partner
+        fn handle(&mut self, event: Event) -> Vec<u8> {
+            let from_partner = matches!(event, Event::Link(_));
+            let prev = self.state.status();
+            let mut effects = Effects::new();
+            self.state.handle_event(&mut effects, event);
+            if let Some(page) = effects.page_change {
+                self.state.page = page;
+            }
+            let mut lines = Vec::new();
+            if !from_partner {
+                for message in changes(&prev, &self.state.status()) {
+                    lines.extend_from_slice(encode(&message).as_bytes());
+                }
+            }
+            lines
+        }
+
+        /// Takes in the lines of the partner, returns what this clock sends back
+        fn receive(&mut self, bytes: &[u8]) -> Vec<u8> {
+            let mut lines = Vec::new();
+            for byte in bytes {
+                if let Some(message) = self.decoder.push(*byte) {
+                    lines.extend(self.handle(Event::Link(message)));
+                }
+            }
+            lines
+        }
+
+        fn running(&self) -> bool {
+            matches!(self.state.page, Page::Game(ref game) if !game.paused)
+        }
+    }
+
+    fn press(button: Button) -> Event {
+        Event::ButtonPushed(button, PressType::Single)
+    }
+
+    #[test]
+    fn the_partner_follows_the_game() {
+        let (mut a, mut b) = (Board::new(), Board::new());
+        // The first press sets the game up, the second one starts the clock
+        assert!(a.handle(press(Button::Left)).is_empty());
+        let start = a.handle(press(Button::Left));
+        assert_eq!(start, b"START,L\n");
+        assert!(b.receive(&start).is_empty());
+        assert!(b.running());
+        assert_eq!(b.state.status().turn, Side::Left);
+
+        let times = a.handle(Event::Clock(Duration::from_secs(1)));
+        assert_eq!(times, b"TIMES,4000,5000\n");
+        b.receive(&times);
+        let partner = b.state.partner.unwrap();
+        assert_eq!((partner.left_ms, partner.right_ms), (4000, 5000));
+        let frame = b.state.view().unwrap();
+        assert_eq!(&frame.cells[1][..5], b"00:04");
+
+        // Each side reports its own board
+        let times = b.handle(Event::Clock(Duration::from_secs(2)));
+        a.receive(&times);
+        assert_eq!(a.state.partner.unwrap().left_ms, 3000);
+
+        let pause = a.handle(press(Button::Control));
+        assert_eq!(pause, b"PAUSE\n");
+        b.receive(&pause);
+        assert!(!b.running());
+
+        let resume = a.handle(press(Button::Control));
+        assert_eq!(resume, b"START,L\n");
+        b.receive(&resume);
+        assert!(b.running());
+    }
+
+    #[test]
+    fn a_flag_stops_both_boards() {
+        let (mut a, mut b) = (Board::new(), Board::new());
+        a.handle(press(Button::Right));
+        b.receive(&a.handle(press(Button::Right)));
+        let mut sent = Vec::new();
+        while matches!(a.state.page, Page::Game(_)) {
+            sent = a.handle(Event::Clock(Duration::from_secs(1)));
+            b.receive(&sent);
+        }
+        assert_eq!(sent, b"FLAG,R\n");
+        assert!(!b.running());
+        let partner = b.state.partner.unwrap();
+        assert!(partner.flagged == Some(Side::Right));
+        let frame = b.state.view().unwrap();
+        assert_eq!(&frame.cells[1][5..11], b" flag>");
+
+        // The next game clears the flag on the partner
+        a.handle(press(Button::Left));
+        b.receive(&a.handle(press(Button::Left)));
+        assert!(b.state.partner.unwrap().flagged.is_none());
+    }
+
+    #[test]
+    fn garbled_lines_are_dropped() {
+        let mut board = Board::new();
+        board.receive(b"START,X\nTIMES,1,2,3\nSTART,L");
+        assert!(board.state.partner.is_none());
+        let long = [b'9'; MAX_LINE + 1];
+        board.receive(b"TIMES,1,");
+        board.receive(&long);
+        board.receive(b"\nTIMES,1,2\r\n");
+        let partner = board.state.partner.unwrap();
+        assert_eq!((partner.left_ms, partner.right_ms), (1, 2));
+        assert!(matches!(board.state.page, Page::Welcome));
+    }
+}
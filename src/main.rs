@@ -1,49 +1,154 @@
 #![no_std]
 #![no_main]
 
-use defmt::{info, unwrap};
+#[cfg(feature = "bus")]
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::{debug, info, warn};
 use effect::{Buzz, Effects};
 use embassy_executor::Spawner;
-use embassy_futures::join::{join3, join4};
+use embassy_futures::{
+    join::join4,
+    select::{select, Either},
+};
 use embassy_stm32::{
+    adc::{self, Adc},
     bind_interrupts,
     exti::ExtiInput,
+    flash::{Blocking, Flash, FLASH_SIZE},
     gpio::{Level, Output, OutputType, Pull, Speed},
     i2c::{ErrorInterruptHandler, EventInterruptHandler, I2c},
-    peripherals::{I2C1, TIM1},
+    peripherals::{ADC1, I2C1, PA0, TIM1, USART1},
     time::Hertz,
     timer::{
         low_level::CountingMode,
         simple_pwm::{PwmPin, SimplePwm},
     },
+    usart::{self, BufferedUart},
 };
 use embassy_sync::{
     blocking_mutex::raw::ThreadModeRawMutex,
     channel::{Channel, Receiver, Sender},
+    pubsub::PubSubChannel,
     signal::Signal,
 };
-use embassy_time::{Delay, Duration, Timer, WithTimeout};
-use lcd_lcm1602_i2c::{async_lcd::Lcd, Backlight};
+use embassy_time::{Delay, Duration, Instant, Timer, WithTimeout};
 use {defmt_rtt as _, panic_probe as _};
 
-use crate::app::{AppState, Button, Event, Page, PressType};
+use chessclock_protocol as protocol;
+
+use crate::app::{AppState, Button, Event, Page};
+use crate::battery::Battery;
+use crate::display::{Renderer, Screen};
 use crate::error::Error;
+use crate::game::Player;
+#[cfg(feature = "history")]
+use crate::history::History;
+use crate::lcd::Lcd;
+#[cfg(feature = "link")]
+use crate::link::LinkMessage;
 use crate::menu::GameConfig;
+use crate::presets::UserPresets;
+use crate::protocol::Telemetry;
+use crate::resume::{BackupRegisters, Snapshot, POWER_FAIL};
+#[cfg(feature = "bus")]
+use crate::rs485::Transceiver;
+use crate::settings::Settings;
+use crate::storage::{Persist, Storage, PAGE_SIZE};
+#[cfg(feature = "table")]
+use crate::table::TableSnapshot;
+#[cfg(feature = "bus")]
+use crate::tasks::handle_bus;
+#[cfg(feature = "dgt")]
+use crate::tasks::handle_dgt;
+#[cfg(feature = "link")]
+use crate::tasks::handle_link;
+#[cfg(not(any(feature = "dgt", feature = "link", feature = "bus")))]
+use crate::tasks::handle_serial;
+use crate::tasks::{emit_clock, handle_button, receive_event_or_sleep, SleepControl};
 
 mod app;
+#[cfg(feature = "armageddon")]
+mod armageddon;
 mod aux;
+mod battery;
+#[cfg(feature = "dgt")]
+mod dgt;
+mod display;
 mod effect;
 mod error;
 mod game;
+mod handicap;
+#[cfg(feature = "history")]
+mod history;
+// The keys can only be chosen and typed with the USB keyboard
+#[cfg_attr(not(feature = "hid"), allow(dead_code))]
+mod keyboard;
+mod lcd;
+#[cfg(feature = "link")]
+mod link;
+#[cfg(feature = "match")]
+mod match_play;
 mod menu;
+#[cfg(feature = "odds")]
+mod odds;
+#[cfg(not(any(feature = "usb", feature = "link", feature = "bus")))]
+mod power;
+mod presets;
+mod result;
+mod resume;
+#[cfg(feature = "bus")]
+mod rs485;
+mod settings;
+mod storage;
+#[cfg(feature = "table")]
+mod table;
+mod tasks;
+#[cfg(feature = "usb")]
+mod usb;
+
+#[cfg(all(feature = "usb", not(feature = "stm32f103cb")))]
+compile_error!("The USB serial port only fits in 128K of flash, enable the stm32f103cb feature");
+#[cfg(all(feature = "bus", not(feature = "stm32f103cb")))]
+compile_error!("The RS-485 bus only fits in 128K of flash, enable the stm32f103cb feature");
+#[cfg(all(feature = "table", not(feature = "stm32f103cb")))]
+compile_error!("The table timer only fits in 128K of flash, enable the stm32f103cb feature");
+#[cfg(all(feature = "odds", not(feature = "stm32f103cb")))]
+compile_error!("The odds calculator only fits in 128K of flash, enable the stm32f103cb feature");
+#[cfg(all(feature = "match", not(feature = "stm32f103cb")))]
+compile_error!("Matches only fit in 128K of flash, enable the stm32f103cb feature");
+#[cfg(all(feature = "history", not(feature = "stm32f103cb")))]
+compile_error!("The game history only fits in 128K of flash, enable the stm32f103cb feature");
+#[cfg(all(feature = "dgt", feature = "link"))]
+compile_error!("The DGT emulation and the bughouse link both need USART1, enable only one");
+#[cfg(all(feature = "bus", any(feature = "dgt", feature = "link")))]
+compile_error!("The RS-485 bus needs USART1 for itself, it can't go with dgt or link");
 
 bind_interrupts!(struct Irqs {
     I2C1_EV => EventInterruptHandler<I2C1>;
     I2C1_ER => ErrorInterruptHandler<I2C1>;
+    ADC1_2 => adc::InterruptHandler<ADC1>;
+    USART1 => usart::BufferedInterruptHandler<USART1>;
+    #[cfg(feature = "usb")]
+    USB_LP_CAN1_RX0 => embassy_stm32::usb::InterruptHandler<embassy_stm32::peripherals::USB>;
 });
 
 static CLOCK: Signal<ThreadModeRawMutex, bool> = Signal::new();
 static BUZZ: Signal<ThreadModeRawMutex, Buzz> = Signal::new();
+static DISPLAY: Signal<ThreadModeRawMutex, Screen> = Signal::new();
+/// State changes sent over the serial protocol
+static TELEMETRY: PubSubChannel<ThreadModeRawMutex, Telemetry, 8, TELEMETRY_SUBSCRIBERS, 0> =
+    PubSubChannel::new();
+/// Keys typed on the USB keyboard, indices into `KEYS`
+static KEYSTROKES: Channel<ThreadModeRawMutex, u8, 4> = Channel::new();
+/// Messages for the partner clock of a bughouse match
+#[cfg(feature = "link")]
+static LINK: Channel<ThreadModeRawMutex, LinkMessage, 4> = Channel::new();
+/// Address on the RS-485 bus, follows the settings
+#[cfg(feature = "bus")]
+static BUS_ADDRESS: AtomicU8 = AtomicU8::new(1);
+/// Signaled by the display task once a screen has been shown
+static DISPLAY_DONE: Signal<ThreadModeRawMutex, ()> = Signal::new();
 
 pub enum SystemEvent {
     SetClock(bool),
@@ -51,20 +156,55 @@ pub enum SystemEvent {
     Sleep(bool),
 }
 
-const SLEEP_TIME: u64 = 20;
-
-struct Outputs<'a, 'b> {
+const DISPLAY_PROBE_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest wait for the display to switch off before stopping the MCU
+const DISPLAY_OFF_TIMEOUT: Duration = Duration::from_millis(500);
+/// UART, and the USB serial port when enabled
+const TELEMETRY_SUBSCRIBERS: usize = if cfg!(feature = "usb") { 2 } else { 1 };
+/// Interval between battery measurements
+const BATTERY_INTERVAL: Duration = Duration::from_secs(30);
+/// Last pages of the flash, excluded from FLASH in the memory.x written by build.rs
+const STORAGE_PAGES: u32 = 2;
+/// Offset of the storage area from the start of the flash
+const STORAGE_OFFSET: u32 = FLASH_SIZE as u32 - STORAGE_PAGES * PAGE_SIZE;
+
+type LcdRenderer<'a> = Renderer<I2c<'a, embassy_stm32::mode::Async>, Delay>;
+type FlashStorage<'a> = Storage<Flash<'a, Blocking>>;
+
+struct Outputs<'a> {
     left_led: Output<'a>,
     right_led: Output<'a>,
-    lcd: Lcd<'a, I2c<'b, embassy_stm32::mode::Async>, embassy_time::Delay>,
+}
+
+impl Outputs<'_> {
+    /// Updates the LEDs and hands the frame over to the display task, without waiting for the LCD.
+    ///
+    /// A page that fails to draw leaves the previous frame on the display, the clock carries on.
+    async fn show(&mut self, state: &AppState) {
+        match state.view() {
+            Ok(frame) => DISPLAY.signal(Screen::On(frame)),
+            Err(err) => warn!("Failed to draw the page: {}", err),
+        }
+
+        let active_led = state.active_led();
+        self.left_led
+            .set_level((active_led == Some(Player::Left)).into());
+        self.right_led
+            .set_level((active_led == Some(Player::Right)).into());
+    }
 }
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
-    let stm32_config = Default::default();
+    #[allow(unused_mut)]
+    let mut stm32_config = Default::default();
+    #[cfg(feature = "usb")]
+    usb::rcc_config(&mut stm32_config);
     let p = embassy_stm32::init(stm32_config);
 
-    let config = embassy_stm32::i2c::Config::default();
+    let mut config = embassy_stm32::i2c::Config::default();
+    // Fail fast when the display is disconnected
+    config.timeout = Duration::from_millis(50);
     let scl = p.PB6;
     let sda = p.PB7;
 
@@ -78,15 +218,36 @@ async fn main(_spawner: Spawner) {
         Hertz::khz(100),
         config,
     );
-    let mut delay = Delay;
-    let lcd = unwrap!(
-        Lcd::new(&mut i2c, &mut delay)
-            .with_address(0x27)
-            .with_cursor_on(false)
-            .with_rows(2)
-            .init()
-            .await
-    );
+    let mut storage = Storage::new(Flash::new_blocking(p.FLASH), STORAGE_OFFSET, STORAGE_PAGES)
+        .unwrap_or_else(|err| defmt::panic!("Storage unavailable: {}", err));
+    let mut settings = load_or_default::<Settings>(&mut storage);
+    let game_config = load_or_default::<GameConfig>(&mut storage);
+    let user_presets = load_or_default::<UserPresets>(&mut storage);
+    #[cfg(feature = "history")]
+    let history = load_or_default::<History>(&mut storage);
+
+    // The backup registers survive a reset, the flash copy is only written on a power failure
+    let backup = BackupRegisters::new();
+    let flash_snapshot = load_or_default::<Option<Snapshot>>(&mut storage);
+    if flash_snapshot.is_some() {
+        save(&mut storage, &None::<Snapshot>);
+    }
+    let resumable = backup.read().or(flash_snapshot);
+    #[cfg(feature = "table")]
+    let resumable_table = load_or_default::<Option<TableSnapshot>>(&mut storage);
+    #[cfg(feature = "table")]
+    if resumable_table.is_some() {
+        save(&mut storage, &None::<TableSnapshot>);
+    }
+    resume::enable_power_fail_detection();
+
+    if let Some(address) = lcd::find_address(&mut i2c, settings.lcd_address).await {
+        if address != settings.lcd_address {
+            settings.lcd_address = address;
+            save(&mut storage, &settings);
+        }
+    }
+    let lcd = Lcd::new(i2c, Delay, settings.lcd_address);
 
     let left_led = Output::new(p.PC13, Level::Low, Speed::Low);
     let right_led = Output::new(p.PB11, Level::Low, Speed::Low);
@@ -104,72 +265,124 @@ async fn main(_spawner: Spawner) {
     let right_button = ExtiInput::new(p.PB10, p.EXTI10, Pull::Up);
     let control_button = ExtiInput::new(p.PC15, p.EXTI15, Pull::Up);
 
+    let mut uart_config = usart::Config::default();
+    // DGT boards talk at 9600 baud
+    uart_config.baudrate = if cfg!(feature = "dgt") { 9600 } else { 115_200 };
+    let mut uart_tx_buffer = [0; 128];
+    let mut uart_rx_buffer = [0; 64];
+    let uart = BufferedUart::new(
+        p.USART1,
+        Irqs,
+        p.PA10,
+        p.PA9,
+        &mut uart_tx_buffer,
+        &mut uart_rx_buffer,
+        uart_config,
+    )
+    .unwrap_or_else(|_| defmt::panic!("Invalid UART config"));
+    #[cfg(not(feature = "link"))]
+    let telemetry = TELEMETRY
+        .subscriber()
+        .unwrap_or_else(|_| defmt::panic!("No telemetry subscriber left"));
+
+    // Driver and receiver enable of the RS-485 transceiver
+    #[cfg(feature = "bus")]
+    let driver_enable = Output::new(p.PA1, Level::Low, Speed::Low);
+    #[cfg(feature = "bus")]
+    BUS_ADDRESS.store(settings.bus_address, Ordering::Relaxed);
+
+    let adc = Adc::new(p.ADC1);
+    let battery_pin = p.PA0;
+
     let event_channel: Channel<ThreadModeRawMutex, Event, 3> = Channel::new();
     let tx = event_channel.sender();
     let rx = event_channel.receiver();
 
+    #[cfg(feature = "dgt")]
+    let uart_serial = handle_dgt(tx, uart, telemetry);
+    #[cfg(feature = "link")]
+    let uart_serial = handle_link(tx, uart, LINK.receiver());
+    #[cfg(feature = "bus")]
+    let uart_serial = handle_bus(
+        tx,
+        Transceiver::new(uart, driver_enable),
+        telemetry,
+        &BUS_ADDRESS,
+    );
+    #[cfg(not(any(feature = "dgt", feature = "link", feature = "bus")))]
+    let uart_serial = handle_serial(tx, uart, telemetry);
+
+    #[cfg(feature = "usb")]
+    let usb_serial = usb::run(
+        p.USB,
+        Irqs,
+        p.PA12,
+        p.PA11,
+        tx,
+        TELEMETRY
+            .subscriber()
+            .unwrap_or_else(|_| defmt::panic!("No telemetry subscriber left")),
+        KEYSTROKES.receiver(),
+    );
+    #[cfg(not(feature = "usb"))]
+    let usb_serial = core::future::pending::<()>();
+
     let mut outputs = Outputs {
         left_led,
         right_led,
-        lcd,
     };
+    let mut renderer = Renderer::new(lcd);
 
     let _ = join4(
-        main_loop(rx, &mut outputs),
-        emit_clock(tx),
-        join3(
+        main_loop(
+            rx,
+            &mut outputs,
+            &mut storage,
+            backup,
+            AppState {
+                game_config,
+                user_presets,
+                settings,
+                #[cfg(feature = "history")]
+                history,
+                page: Page::Welcome,
+                deferred_config: None,
+                resumable,
+                #[cfg(feature = "table")]
+                resumable_table,
+                battery: None,
+                #[cfg(feature = "link")]
+                partner: None,
+                #[cfg(feature = "match")]
+                match_state: None,
+                #[cfg(feature = "armageddon")]
+                armageddon: None,
+            },
+        ),
+        emit_clock(tx, &CLOCK),
+        join4(
             handle_button(tx, left_button, Button::Left),
             handle_button(tx, right_button, Button::Right),
             handle_button(tx, control_button, Button::Control),
+            uart_serial,
+        ),
+        join4(
+            handle_buzz(&mut buzzer),
+            handle_display(&mut renderer),
+            monitor_battery(tx, adc, battery_pin),
+            usb_serial,
         ),
-        handle_buzz(&mut buzzer),
     )
     .await;
 }
 
-async fn handle_button(
-    tx: Sender<'_, ThreadModeRawMutex, Event, 3>,
-    mut input: ExtiInput<'_>,
-    button: Button,
-) {
-    loop {
-        input.wait_for_low().await;
-        let instant = embassy_time::Instant::now();
-        Timer::after_millis(200).await;
-
-        input.wait_for_high().await;
-        let press_type = if instant.elapsed() > Duration::from_millis(300) {
-            PressType::Long
-        } else {
-            PressType::Single
-        };
-
-        tx.send(Event::ButtonPushed(button, press_type)).await;
-        Timer::after_millis(100).await;
-    }
-}
-
-async fn emit_clock(tx: Sender<'_, ThreadModeRawMutex, Event, 3>) {
-    loop {
-        if CLOCK.wait().await {
-            loop {
-                let duration = Duration::from_millis(1000);
-                let clock = CLOCK.wait().with_timeout(duration).await;
-
-                if let Ok(false) = clock {
-                    break;
-                }
-
-                tx.send(Event::Clock(duration)).await;
-            }
-        }
-    }
-}
-
 async fn main_loop(
     rx: Receiver<'_, ThreadModeRawMutex, Event, 3>,
-    outputs: &mut Outputs<'_, '_>,
-) -> Result<(), Error> {
+    outputs: &mut Outputs<'_>,
+    storage: &mut FlashStorage<'_>,
+    mut backup: BackupRegisters,
+    mut state: AppState,
+) {
     info!("Init");
 
     let test_duration = Duration::from_millis(300);
@@ -185,22 +398,63 @@ async fn main_loop(
     outputs.left_led.set_low();
     outputs.right_led.set_low();
 
-    let init_state = AppState {
-        game_config: GameConfig::default(),
-        page: Page::Init,
-    };
-    let mut state = AppState {
-        game_config: GameConfig::default(),
-        page: Page::Welcome,
-    };
-    state.display_state(&init_state, outputs).await?;
+    let mut saved_game_config = state.game_config.clone();
+    let mut saved_user_presets = state.user_presets.clone();
+    let mut saved_settings = state.settings.clone();
+    #[cfg(feature = "history")]
+    let mut saved_history = state.history.clone();
+    let mut snapshot = None;
+    // Set once the game is saved on a power failure, until the supply recovers
+    let mut saved_on_power_fail = false;
+    // Whether the flash holds a game saved on a power failure the clock lived through
+    let mut game_in_flash = false;
+    #[cfg(feature = "table")]
+    let mut table_in_flash = false;
+    let telemetry = TELEMETRY.immediate_publisher();
+    outputs.show(&state).await;
     loop {
-        let event = receive_event_or_sleep(rx, outputs, &state).await?;
-
-        let prev_state = state.clone();
+        let event = match select(
+            receive_event_or_sleep(rx, outputs, &state),
+            POWER_FAIL.wait(),
+        )
+        .await
+        {
+            Either::First(Ok(event)) => event,
+            Either::First(Err(err)) => {
+                warn!("Failed to sleep or wake up: {}", err);
+                continue;
+            }
+            // The supply bounces around the threshold, once is enough
+            Either::Second(()) if saved_on_power_fail => continue,
+            Either::Second(()) => {
+                warn!("Power failure");
+                saved_on_power_fail = true;
+                let pending = pending_snapshot(&snapshot, &state);
+                // The registers take no time, the flash copy is for a supply without a backup
+                // battery and goes to room reserved for it
+                backup.write(pending.as_ref());
+                if pending.is_some() || game_in_flash {
+                    save(storage, &pending);
+                    game_in_flash = pending.is_some();
+                }
+                #[cfg(feature = "table")]
+                {
+                    let pending = pending_table(&state);
+                    if pending.is_some() || table_in_flash {
+                        save(storage, &pending);
+                        table_in_flash = pending.is_some();
+                    }
+                }
+                continue;
+            }
+        };
 
+        let prev_status = state.status();
+        // Changes made by the partner clock aren't sent back to it
+        #[cfg(feature = "link")]
+        let from_partner = matches!(event, Event::Link(_));
         let mut effects = Effects::new();
-        state.handle_event(&mut effects, event)?;
+        state.handle_event(&mut effects, event);
 
         if let Some(buzz) = effects.buzz {
             info!("Buzz effect");
@@ -215,43 +469,179 @@ async fn main_loop(
             CLOCK.signal(clock);
         }
 
-        state.display_state(&prev_state, outputs).await?;
+        if let Some(key) = effects.key {
+            // Dropped when nobody types them, without the keyboard or while unplugged
+            let _ = KEYSTROKES.try_send(key);
+        }
+
+        let status = state.status();
+        for change in protocol::changes(&prev_status, &status) {
+            telemetry.publish_immediate(change);
+        }
+        #[cfg(feature = "link")]
+        if !from_partner {
+            for message in link::changes(&prev_status, &status) {
+                // The partner misses out while the line is congested, the next tick catches up
+                let _ = LINK.try_send(message);
+            }
+        }
+        if effects.report_state {
+            telemetry.publish_immediate(Telemetry::State(status));
+        }
+        if effects.report_presets {
+            for (i, preset) in state.user_presets.iter().enumerate() {
+                // Names left blank in the menu are sent as a dash
+                let name = protocol::Name::new(&preset.name).or_else(|| protocol::Name::new("-"));
+                if let Some(name) = name {
+                    let config = (&preset.config).into();
+                    telemetry.publish_immediate(Telemetry::Preset(i as u8 + 1, name, config));
+                }
+            }
+        }
+        #[cfg(feature = "history")]
+        if effects.report_games {
+            for (i, game_log) in state.history.iter().enumerate() {
+                telemetry.publish_immediate(Telemetry::Game(i as u8 + 1, game_log.into()));
+            }
+        }
+
+        outputs.show(&state).await;
+
+        let next_snapshot = state.snapshot();
+        if next_snapshot != snapshot {
+            backup.write(next_snapshot.as_ref());
+            snapshot = next_snapshot;
+        }
+
+        // Saved once the menu is left, not on every edit
+        if !matches!(state.page, Page::Menu(_)) {
+            if state.game_config != saved_game_config {
+                save(storage, &state.game_config);
+                saved_game_config = state.game_config.clone();
+            }
+            if state.user_presets != saved_user_presets {
+                save(storage, &state.user_presets);
+                saved_user_presets = state.user_presets.clone();
+            }
+            if state.settings != saved_settings {
+                save(storage, &state.settings);
+                saved_settings = state.settings.clone();
+                #[cfg(feature = "bus")]
+                BUS_ADDRESS.store(state.settings.bus_address, Ordering::Relaxed);
+            }
+            #[cfg(feature = "history")]
+            if state.history != saved_history {
+                save(storage, &state.history);
+                saved_history = state.history.clone();
+            }
+        }
+
+        // Erasing takes too long once the power fails
+        let pending = pending_snapshot(&snapshot, &state);
+        if pending.is_some() {
+            if let Err(err) = storage.reserve(&pending) {
+                warn!("Failed to make room in flash: {}", err);
+            }
+        } else if game_in_flash {
+            // Over since the power failure, it would be offered again at the next boot
+            save(storage, &pending);
+            game_in_flash = false;
+        }
+        #[cfg(feature = "table")]
+        {
+            let pending = pending_table(&state);
+            if pending.is_some() {
+                if let Err(err) = storage.reserve(&pending) {
+                    warn!("Failed to make room in flash: {}", err);
+                }
+            } else if table_in_flash {
+                save(storage, &pending);
+                table_in_flash = false;
+            }
+        }
+        if saved_on_power_fail && !resume::power_failing() {
+            saved_on_power_fail = false;
+        }
     }
 }
 
-async fn receive_event_or_sleep(
-    rx: Receiver<'_, ThreadModeRawMutex, Event, 3>,
-    outputs: &mut Outputs<'_, '_>,
-    state: &AppState,
-) -> Result<Event, Error> {
-    let time_until_sleep = Duration::from_secs(SLEEP_TIME);
-    let mut event;
-    loop {
-        event = rx.receive().with_timeout(time_until_sleep).await;
-        info!("Event received: {}", event);
+/// Game to save on a power failure, the running one or the one offered for resuming
+fn pending_snapshot(snapshot: &Option<Snapshot>, state: &AppState) -> Option<Snapshot> {
+    snapshot.clone().or_else(|| state.resumable.clone())
+}
 
-        // Sleep after 1 minute of inactivity
-        match event {
-            Ok(event) => {
-                return Ok(event);
-            }
-            Err(_) => {
-                outputs.lcd.clear().await?;
-                outputs.lcd.backlight(Backlight::Off).await?;
-                outputs.left_led.set_low();
-                outputs.right_led.set_low();
+/// Table game to save on a power failure, the one played or the one offered for resuming
+#[cfg(feature = "table")]
+fn pending_table(state: &AppState) -> Option<TableSnapshot> {
+    match state.page {
+        Page::Table(ref table) => TableSnapshot::of(table, &state.game_config),
+        _ => state.resumable_table.clone(),
+    }
+}
+
+fn load_or_default<T: Persist + Default>(storage: &mut FlashStorage<'_>) -> T {
+    match storage.load() {
+        Ok(Some(value)) => value,
+        Ok(None) => T::default(),
+        Err(err) => {
+            warn!("Failed to load from flash: {}", err);
+            T::default()
+        }
+    }
+}
+
+fn save<T: Persist>(storage: &mut FlashStorage<'_>, value: &T) {
+    if let Err(err) = storage.save(value) {
+        warn!("Failed to save to flash: {}", err);
+    }
+}
 
-                info!("Sleep");
-                let event = rx.receive().await;
-                info!("Event received: {}", event);
+impl SleepControl for Outputs<'_> {
+    async fn sleep(&mut self) -> Result<(), Error> {
+        DISPLAY_DONE.reset();
+        DISPLAY.signal(Screen::Off);
+        self.left_led.set_low();
+        self.right_led.set_low();
+        // Stopping halts the I2C transfers as well, a lost display has nothing to wait for
+        let _ = DISPLAY_DONE.wait().with_timeout(DISPLAY_OFF_TIMEOUT).await;
+        Ok(())
+    }
 
-                let mut sleep_state = state.clone();
-                sleep_state.page = Page::Init;
+    async fn stop(&mut self) {
+        // STOP halts the USB peripheral and the USART, with them the MCU only sleeps between the
+        // interrupts like the executor always does, waking up to look for an event
+        #[cfg(not(any(feature = "usb", feature = "link", feature = "bus")))]
+        power::stop();
+    }
 
-                state.display_state(&sleep_state, outputs).await?;
-                outputs.lcd.backlight(Backlight::On).await?;
+    async fn wake(&mut self, state: &AppState) -> Result<(), Error> {
+        self.show(state).await;
+        Ok(())
+    }
+}
+
+/// Measures the battery periodically, sending an event when the charge changed noticeably
+async fn monitor_battery(
+    tx: Sender<'_, ThreadModeRawMutex, Event, 3>,
+    mut adc: Adc<'_, ADC1>,
+    mut pin: PA0,
+) {
+    adc.set_sample_time(adc::SampleTime::CYCLES239_5);
+    let mut vrefint = adc.enable_vref();
+    let mut reported: Option<Battery> = None;
+    loop {
+        let raw = adc.read(&mut pin).await;
+        let vrefint_raw = adc.read(&mut vrefint).await;
+        #[cfg(feature = "armageddon")]
+        armageddon::stir(raw ^ vrefint_raw);
+        if let Some(battery) = Battery::from_samples(raw, vrefint_raw) {
+            debug!("Battery: {} mV", battery.millivolts);
+            if battery.differs(reported.as_ref()) {
+                tx.send(Event::Battery(battery)).await;
+                reported = Some(battery);
             }
         }
+        Timer::after(BATTERY_INTERVAL).await;
     }
 }
 
@@ -269,3 +659,45 @@ async fn handle_buzz(pwm: &mut SimplePwm<'_, TIM1>) -> Result<(), Error> {
         buzzer.disable();
     }
 }
+
+/// Shows the latest screen on the LCD.
+///
+/// If the display stops responding, the clock carries on with only the LEDs and the buzzer, and the
+/// LCD is reinitialised periodically until it comes back.
+async fn handle_display(renderer: &mut LcdRenderer<'_>) {
+    let mut display_ok = renderer.reinit().await.is_ok();
+    if !display_ok {
+        warn!("Display not found");
+    }
+    let mut screen = DISPLAY.wait().await;
+    loop {
+        if display_ok {
+            let start = Instant::now();
+            match renderer.show(&screen).await {
+                Ok(()) => {
+                    debug!("Frame rendered in {} us", start.elapsed().as_micros());
+                    DISPLAY_DONE.signal(());
+                    screen = DISPLAY.wait().await;
+                }
+                Err(err) => {
+                    warn!("Display lost: {}", err);
+                    BUZZ.signal(Buzz {
+                        freq: 220,
+                        duration: Duration::from_millis(1000),
+                    });
+                    display_ok = false;
+                }
+            }
+        } else {
+            match select(DISPLAY.wait(), Timer::after(DISPLAY_PROBE_INTERVAL)).await {
+                Either::First(next_screen) => screen = next_screen,
+                Either::Second(()) => {
+                    if renderer.reinit().await.is_ok() {
+                        info!("Display recovered");
+                        display_ok = true;
+                    }
+                }
+            }
+        }
+    }
+}
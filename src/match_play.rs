@@ -0,0 +1,236 @@
+//! Matches of several games between the same two players, with a running score and an Armageddon
+//! game to break a tie.
+//!
+//! The players change seats with the colours after every game, so the time controls of the two
+//! sides swap for every other game, and the score follows the players rather than the sides. The
+//! first player to score more than half of the games wins the match. A match tied after all of its
+//! games goes on to Armageddon games when the tiebreak is on, five minutes for the player who moves
+//! first and four for the other, until one of them is decisive. With the `armageddon` feature the
+//! player moving second has the draw odds instead: a draw counts as their win, so one game does.
+//!
+//! Flags end a game by themselves. Any other result is entered on the result page, opened with a
+//! long press on a player button while the game is paused.
+
+use core::fmt::Write;
+
+use embassy_time::Duration;
+use heapless::String;
+
+use crate::{
+    display::Frame,
+    error::Error,
+    game::Player,
+    lcd::Glyph,
+    menu::{GameConfig, IncrementType, TimeControl},
+    settings::Settings,
+};
+
+/// Time of the player moving first in an Armageddon game, the other one gets a minute less
+pub const ARMAGEDDON_TIME: Duration = Duration::from_secs(5 * 60);
+
+/// How far a match got, kept with its game to carry the match on once the game is resumed
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct MatchProgress {
+    pub game: u8,
+    pub points: [u8; 2],
+    pub first: Player,
+}
+
+#[derive(Clone)]
+pub struct MatchState {
+    /// Game being played or the next one, from 1, the Armageddon games follow the last one
+    pub game: u8,
+    /// Half points of the player who started on the left side and of the other one
+    pub points: [u8; 2],
+    /// Time controls of the game being played
+    pub config: GameConfig,
+    /// Side moving first in the game being played
+    pub first: Player,
+    pub over: bool,
+}
+
+impl MatchState {
+    pub fn new(game_config: &GameConfig) -> MatchState {
+        MatchState {
+            game: 1,
+            points: [0, 0],
+            config: game_config.clone(),
+            first: Player::Left,
+            over: false,
+        }
+    }
+
+    /// Match of a resumed game, played with `config`
+    pub fn resumed(progress: MatchProgress, config: GameConfig) -> MatchState {
+        MatchState {
+            game: progress.game,
+            points: progress.points,
+            config,
+            first: progress.first,
+            over: false,
+        }
+    }
+
+    pub fn progress(&self) -> MatchProgress {
+        MatchProgress {
+            game: self.game,
+            points: self.points,
+            first: self.first,
+        }
+    }
+
+    /// Whether the game being played or the next one is an Armageddon game
+    fn armageddon(&self, settings: &Settings) -> bool {
+        self.game > settings.match_games
+    }
+
+    /// Index into `points` of the player on the side, which changes with every game
+    fn player(&self, side: Player) -> usize {
+        match side {
+            Player::Left => (self.game as usize + 1) % 2,
+            Player::Right => self.game as usize % 2,
+        }
+    }
+
+    /// Sets up the time controls of the next game, `first` to move
+    pub fn start(&mut self, settings: &Settings, game_config: &GameConfig, first: Player) {
+        self.config = game_config.clone();
+        self.first = first;
+        if self.armageddon(settings) {
+            let control = |time| TimeControl {
+                time,
+                increment_type: IncrementType::SuddenDeath,
+            };
+            *self.config.control_mut(first) = control(ARMAGEDDON_TIME);
+            let second = match first {
+                Player::Left => Player::Right,
+                Player::Right => Player::Left,
+            };
+            *self.config.control_mut(second) = control(ARMAGEDDON_TIME - Duration::from_secs(60));
+        } else if self.game.is_multiple_of(2) {
+            core::mem::swap(&mut self.config.left, &mut self.config.right);
+        }
+    }
+
+    /// Scores the game that was played, `None` for a draw, and moves on to the next one
+    pub fn record(&mut self, settings: &Settings, loser: Option<Player>) {
+        if self.over {
+            return;
+        }
+        // The player moving second has the draw odds in the Armageddon game
+        #[cfg(feature = "armageddon")]
+        let loser = match loser {
+            None if self.armageddon(settings) => Some(self.first),
+            loser => loser,
+        };
+        match loser {
+            Some(Player::Left) => self.points[self.player(Player::Right)] += 2,
+            Some(Player::Right) => self.points[self.player(Player::Left)] += 2,
+            None => {
+                self.points[0] += 1;
+                self.points[1] += 1;
+            }
+        }
+        let [one, other] = self.points;
+        let decided = one.max(other) > settings.match_games;
+        self.over = if self.armageddon(settings) {
+            loser.is_some()
+        } else {
+            decided
+                || (self.game == settings.match_games && (one != other || !settings.match_tiebreak))
+        };
+        if !self.over {
+            self.game += 1;
+        }
+    }
+
+    /// Prints the score with the points of the player on the left first, and the game
+    pub fn view(&self, settings: &Settings, frame: &mut Frame, row: usize) -> Result<(), Error> {
+        let mut score: String<16> = String::new();
+        for (i, side) in [Player::Left, Player::Right].into_iter().enumerate() {
+            if i == 1 {
+                write!(&mut score, "-")?;
+            }
+            let points = self.points[self.player(side)];
+            if points >= 2 || points == 0 {
+                write!(&mut score, "{}", points / 2)?;
+            }
+            if points % 2 == 1 {
+                write!(&mut score, "{}", HALF_CHAR)?;
+            }
+        }
+        frame.glyphs = &HALF;
+        let mut text: String<32> = String::new();
+        if self.over {
+            write!(&mut text, "Match over {}", score)?;
+        } else if self.armageddon(settings) {
+            write!(&mut text, "Armageddon {}", score)?;
+        } else {
+            write!(&mut text, "Match {} game {}", score, self.game)?;
+            // Without the prefix once there are half points
+            if text.len() > 16 {
+                text.clear();
+                write!(&mut text, "{} game {}", score, self.game)?;
+            }
+        }
+        frame.print(row, 0, &text);
+        Ok(())
+    }
+}
+
+/// Character code of the one half glyph
+const HALF_CHAR: char = '\0';
+
+static HALF: [Glyph; 1] = [[
+    0b01000, 0b11000, 0b01000, 0b00010, 0b00100, 0b01011, 0b00010, 0b00011,
+]];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Match of two games tied one all, at its Armageddon game with the left player moving first
+    fn tied() -> (Settings, MatchState) {
+        let settings = Settings {
+            match_games: 2,
+            match_tiebreak: true,
+            ..Settings::default()
+        };
+        let config = GameConfig::default();
+        let mut match_state = MatchState::new(&config);
+        for _ in 0..2 {
+            match_state.start(&settings, &config, Player::Left);
+            match_state.record(&settings, Some(Player::Left));
+        }
+        match_state.start(&settings, &config, Player::Left);
+        assert!(match_state.armageddon(&settings) && !match_state.over);
+        (settings, match_state)
+    }
+
+    #[test]
+    fn a_decisive_armageddon_game_ends_the_match() {
+        let (settings, mut match_state) = tied();
+        match_state.record(&settings, Some(Player::Right));
+        assert!(match_state.over);
+        assert_eq!(match_state.points, [4, 2]);
+    }
+
+    #[cfg(feature = "armageddon")]
+    #[test]
+    fn a_drawn_armageddon_game_goes_to_the_player_moving_second() {
+        let (settings, mut match_state) = tied();
+        match_state.record(&settings, None);
+        assert!(match_state.over);
+        assert_eq!(match_state.points, [2, 4]);
+    }
+
+    #[cfg(not(feature = "armageddon"))]
+    #[test]
+    fn a_drawn_armageddon_game_is_played_again() {
+        let (settings, mut match_state) = tied();
+        match_state.record(&settings, None);
+        assert!(!match_state.over);
+        assert_eq!(match_state.points, [3, 3]);
+        assert_eq!(match_state.game, 4);
+    }
+}
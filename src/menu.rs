@@ -1,21 +1,74 @@
+use core::fmt::Write;
+
 use embassy_time::Duration;
-use heapless::Vec;
+use heapless::{String, Vec};
 
+#[cfg(feature = "hid")]
+use crate::keyboard::{self, KeyboardMode, KEYS};
+#[cfg(feature = "armageddon")]
+use crate::match_play::ARMAGEDDON_TIME;
+#[cfg(feature = "odds")]
+use crate::odds::odds;
+#[cfg(feature = "bus")]
+use crate::protocol::MAX_BUS_ADDRESS;
+#[cfg(feature = "match")]
+use crate::settings::MAX_MATCH_GAMES;
+#[cfg(feature = "odds")]
+use crate::settings::MAX_RATING;
 use crate::{
     app::{Button, Event, PressType},
     aux::format_duration,
+    display::{CursorMode, Frame},
     error::Error,
-    Outputs,
+    game::Player,
+    presets::{default_name, NameEditor, UserPreset, UserPresets, MAX_USER_PRESETS},
+    protocol::{self, IncrementKind},
+    settings::Settings,
 };
 
+/// Most players round the table
+pub const MAX_PLAYERS: u8 = 8;
+
 #[derive(Clone, PartialEq, Eq)]
 pub enum MenuItem {
     Preset,
-    LeftTime,
-    RightTime,
-    IncrementType,
-    LeftDelay,
-    RightDelay,
+    #[cfg(feature = "table")]
+    Players,
+    Time(Player),
+    IncrementType(Player),
+    /// Increment or delay, depending on the increment type
+    Delay(Player),
+    HandicapStep,
+    HandicapMin,
+    HandicapMax,
+    #[cfg(feature = "odds")]
+    Rating(Player),
+    /// Weaker player's time in the time odds calculator
+    #[cfg(feature = "odds")]
+    OddsBase,
+    /// Sets the times of the game config to the odds of the ratings
+    #[cfg(feature = "odds")]
+    ApplyOdds,
+    #[cfg(feature = "match")]
+    MatchGames,
+    #[cfg(feature = "match")]
+    MatchTiebreak,
+    /// Opens the page giving out the colours of an Armageddon game
+    #[cfg(feature = "armageddon")]
+    Armageddon,
+    SavePreset,
+    EditPresets,
+    IdleTimeout,
+    PausedTimeout,
+    SleepInGame,
+    #[cfg(feature = "hid")]
+    Keyboard,
+    #[cfg(feature = "hid")]
+    LeftKey,
+    #[cfg(feature = "hid")]
+    RightKey,
+    #[cfg(feature = "bus")]
+    BusAddress,
 }
 
 struct Cursor {
@@ -40,114 +93,230 @@ impl MenuItem {
             MenuItem::Preset => {
                 let _ = columns.push(Cursor::new(0, 1));
             }
-            MenuItem::LeftTime => {
+            #[cfg(feature = "table")]
+            MenuItem::Players => {
+                let _ = columns.push(Cursor::new(0, 1));
+            }
+            MenuItem::Time(_) => {
+                let _ = columns.push(Cursor::new(1, 60));
+                let _ = columns.push(Cursor::new(4, 1));
+            }
+            MenuItem::IncrementType(_) => {
+                let _ = columns.push(Cursor::new(0, 1));
+            }
+            MenuItem::Delay(_) => {
+                let _ = columns.push(Cursor::new(1, 60));
+                let _ = columns.push(Cursor::new(4, 1));
+            }
+            MenuItem::HandicapStep | MenuItem::HandicapMin | MenuItem::HandicapMax => {
                 let _ = columns.push(Cursor::new(1, 60));
                 let _ = columns.push(Cursor::new(4, 1));
             }
-            MenuItem::RightTime => {
+            #[cfg(feature = "odds")]
+            MenuItem::Rating(_) => {
+                let _ = columns.push(Cursor::new(1, 100));
+                let _ = columns.push(Cursor::new(3, 1));
+            }
+            #[cfg(feature = "odds")]
+            MenuItem::OddsBase => {
                 let _ = columns.push(Cursor::new(1, 60));
                 let _ = columns.push(Cursor::new(4, 1));
             }
-            MenuItem::IncrementType => {
+            #[cfg(feature = "odds")]
+            MenuItem::ApplyOdds => {
+                let _ = columns.push(Cursor::new(0, 1));
+            }
+            #[cfg(feature = "match")]
+            MenuItem::MatchGames | MenuItem::MatchTiebreak => {
                 let _ = columns.push(Cursor::new(0, 1));
             }
-            MenuItem::LeftDelay => {
+            #[cfg(feature = "armageddon")]
+            MenuItem::Armageddon => {
+                let _ = columns.push(Cursor::new(0, 1));
+            }
+            MenuItem::SavePreset => {
+                let _ = columns.push(Cursor::new(0, 1));
+            }
+            MenuItem::EditPresets => {
+                let _ = columns.push(Cursor::new(0, 1));
+            }
+            MenuItem::IdleTimeout => {
                 let _ = columns.push(Cursor::new(1, 60));
                 let _ = columns.push(Cursor::new(4, 1));
             }
-            MenuItem::RightDelay => {
+            MenuItem::PausedTimeout => {
                 let _ = columns.push(Cursor::new(1, 60));
                 let _ = columns.push(Cursor::new(4, 1));
             }
+            MenuItem::SleepInGame => {
+                let _ = columns.push(Cursor::new(0, 1));
+            }
+            #[cfg(feature = "hid")]
+            MenuItem::Keyboard | MenuItem::LeftKey | MenuItem::RightKey => {
+                let _ = columns.push(Cursor::new(0, 1));
+            }
+            #[cfg(feature = "bus")]
+            MenuItem::BusAddress => {
+                let _ = columns.push(Cursor::new(1, 1));
+            }
         }
         columns
     }
 
     /// Returns the maximum value for the menu item
-    fn max_val(&self) -> u64 {
+    fn max_val(&self, user_presets: &UserPresets) -> u64 {
         match self {
-            MenuItem::Preset => PRESETS.len() as u64 - 1,
-            MenuItem::LeftTime => 3599,
-            MenuItem::RightTime => 3599,
-            MenuItem::IncrementType => INCREMENT_TYPES.len() as u64 - 1,
-            MenuItem::LeftDelay => 59,
-            MenuItem::RightDelay => 59,
+            MenuItem::Preset => (PRESETS.len() + user_presets.len()) as u64 - 1,
+            #[cfg(feature = "table")]
+            MenuItem::Players => MAX_PLAYERS as u64 - 2,
+            MenuItem::Time(_) => 3599,
+            MenuItem::IncrementType(_) => INCREMENT_TYPES.len() as u64 - 1,
+            MenuItem::Delay(_) => 59,
+            MenuItem::HandicapStep | MenuItem::HandicapMin | MenuItem::HandicapMax => 3599,
+            #[cfg(feature = "odds")]
+            MenuItem::Rating(_) => MAX_RATING as u64,
+            #[cfg(feature = "odds")]
+            MenuItem::OddsBase => 3599,
+            #[cfg(feature = "odds")]
+            MenuItem::ApplyOdds => 0,
+            #[cfg(feature = "match")]
+            MenuItem::MatchGames => MAX_MATCH_GAMES as u64,
+            #[cfg(feature = "match")]
+            MenuItem::MatchTiebreak => 1,
+            #[cfg(feature = "armageddon")]
+            MenuItem::Armageddon => 0,
+            MenuItem::SavePreset => 0,
+            MenuItem::EditPresets => 0,
+            MenuItem::IdleTimeout => 3599,
+            MenuItem::PausedTimeout => 3599,
+            MenuItem::SleepInGame => 1,
+            #[cfg(feature = "hid")]
+            MenuItem::Keyboard => KeyboardMode::ALL.len() as u64 - 1,
+            #[cfg(feature = "hid")]
+            MenuItem::LeftKey | MenuItem::RightKey => KEYS.len() as u64 - 1,
+            #[cfg(feature = "bus")]
+            MenuItem::BusAddress => MAX_BUS_ADDRESS as u64 - 1,
         }
     }
 
-    fn edit(&self, game_config: &mut GameConfig, edit_fn: impl Fn(u64) -> u64) {
+    fn edit(
+        &self,
+        game_config: &mut GameConfig,
+        settings: &mut Settings,
+        user_presets: &UserPresets,
+        edit_fn: impl Fn(u64) -> u64,
+    ) {
         match self {
             MenuItem::Preset => {
-                let idx = PRESETS.iter().enumerate().find_map(|(idx, (_, preset))| {
-                    if preset == game_config {
-                        Some(idx)
-                    } else {
-                        None
-                    }
-                });
+                let idx = presets(user_presets)
+                    .enumerate()
+                    .find_map(|(idx, (_, preset))| {
+                        if preset == game_config {
+                            Some(idx)
+                        } else {
+                            None
+                        }
+                    });
 
-                match idx {
-                    None => *game_config = PRESETS[0].1.clone(),
-                    Some(idx) => *game_config = PRESETS[edit_fn(idx as u64) as usize].1.clone(),
+                let idx = idx.map(|idx| edit_fn(idx as u64) as usize).unwrap_or(0);
+                if let Some((_, preset)) = presets(user_presets).nth(idx) {
+                    *game_config = preset.clone();
                 }
             }
-            MenuItem::LeftTime => {
-                game_config.left_time =
-                    Duration::from_secs(edit_fn(game_config.left_time.as_secs()));
+            // Edited from 0 as the menu counts from there, there are at least two players
+            #[cfg(feature = "table")]
+            MenuItem::Players => {
+                game_config.players = edit_fn(game_config.players as u64 - 2) as u8 + 2;
             }
-            MenuItem::RightTime => {
-                game_config.right_time =
-                    Duration::from_secs(edit_fn(game_config.right_time.as_secs()));
+            MenuItem::Time(player) => {
+                let control = game_config.control_mut(*player);
+                control.time = Duration::from_secs(edit_fn(control.time.as_secs()));
             }
-            MenuItem::IncrementType => {
-                let idx = match game_config.increment_type {
+            MenuItem::IncrementType(player) => {
+                let control = game_config.control_mut(*player);
+                let idx = match control.increment_type {
                     IncrementType::SuddenDeath => 0,
-                    IncrementType::Increment { .. } => 1,
-                    IncrementType::Delay { .. } => 2,
-                    IncrementType::Bronstein { .. } => 3,
+                    IncrementType::Increment(_) => 1,
+                    IncrementType::Delay(_) => 2,
+                    IncrementType::Bronstein(_) => 3,
                 };
-                game_config.increment_type = INCREMENT_TYPES[edit_fn(idx) as usize].clone();
+                control.increment_type = INCREMENT_TYPES[edit_fn(idx) as usize];
             }
-            MenuItem::LeftDelay => match game_config.increment_type {
-                IncrementType::SuddenDeath => {}
-                IncrementType::Increment {
-                    ref mut left_increment,
-                    ..
-                } => {
-                    *left_increment = Duration::from_secs(edit_fn(left_increment.as_secs()));
-                }
-                IncrementType::Delay {
-                    ref mut left_delay, ..
-                } => {
-                    *left_delay = Duration::from_secs(edit_fn(left_delay.as_secs()));
-                }
-                IncrementType::Bronstein {
-                    ref mut left_delay, ..
-                } => {
-                    *left_delay = Duration::from_secs(edit_fn(left_delay.as_secs()));
-                }
-            },
-            MenuItem::RightDelay => match game_config.increment_type {
+            MenuItem::Delay(player) => match game_config.control_mut(*player).increment_type {
                 IncrementType::SuddenDeath => {}
-                IncrementType::Increment {
-                    ref mut right_increment,
-                    ..
-                } => {
-                    *right_increment = Duration::from_secs(edit_fn(right_increment.as_secs()));
-                }
-                IncrementType::Delay {
-                    ref mut right_delay,
-                    ..
-                } => {
-                    *right_delay = Duration::from_secs(edit_fn(right_delay.as_secs()));
-                }
-                IncrementType::Bronstein {
-                    ref mut right_delay,
-                    ..
-                } => {
-                    *right_delay = Duration::from_secs(edit_fn(right_delay.as_secs()));
+                IncrementType::Increment(ref mut duration)
+                | IncrementType::Delay(ref mut duration)
+                | IncrementType::Bronstein(ref mut duration) => {
+                    *duration = Duration::from_secs(edit_fn(duration.as_secs()));
                 }
             },
+            MenuItem::HandicapStep => {
+                settings.handicap_step =
+                    Duration::from_secs(edit_fn(settings.handicap_step.as_secs()));
+            }
+            MenuItem::HandicapMin => {
+                settings.handicap_min =
+                    Duration::from_secs(edit_fn(settings.handicap_min.as_secs()))
+                        .min(settings.handicap_max);
+            }
+            MenuItem::HandicapMax => {
+                settings.handicap_max =
+                    Duration::from_secs(edit_fn(settings.handicap_max.as_secs()))
+                        .max(settings.handicap_min);
+            }
+            #[cfg(feature = "odds")]
+            MenuItem::Rating(Player::Left) => {
+                settings.left_rating = edit_fn(settings.left_rating as u64) as u16;
+            }
+            #[cfg(feature = "odds")]
+            MenuItem::Rating(Player::Right) => {
+                settings.right_rating = edit_fn(settings.right_rating as u64) as u16;
+            }
+            #[cfg(feature = "odds")]
+            MenuItem::OddsBase => {
+                // The odds need some time to share out
+                settings.odds_base =
+                    Duration::from_secs(edit_fn(settings.odds_base.as_secs()).max(1));
+            }
+            #[cfg(feature = "odds")]
+            MenuItem::ApplyOdds => {}
+            #[cfg(feature = "match")]
+            MenuItem::MatchGames => {
+                settings.match_games = edit_fn(settings.match_games as u64) as u8
+            }
+            #[cfg(feature = "match")]
+            MenuItem::MatchTiebreak => {
+                settings.match_tiebreak = edit_fn(settings.match_tiebreak as u64) != 0;
+            }
+            #[cfg(feature = "armageddon")]
+            MenuItem::Armageddon => {}
+            MenuItem::SavePreset => {}
+            MenuItem::EditPresets => {}
+            MenuItem::IdleTimeout => {
+                settings.idle_timeout =
+                    Duration::from_secs(edit_fn(settings.idle_timeout.as_secs()));
+            }
+            MenuItem::PausedTimeout => {
+                settings.paused_timeout =
+                    Duration::from_secs(edit_fn(settings.paused_timeout.as_secs()));
+            }
+            MenuItem::SleepInGame => {
+                settings.sleep_in_game = edit_fn(settings.sleep_in_game as u64) != 0;
+            }
+            #[cfg(feature = "hid")]
+            MenuItem::Keyboard => {
+                let index = edit_fn(settings.keyboard as u64) as usize;
+                settings.keyboard = KeyboardMode::ALL[index];
+            }
+            #[cfg(feature = "hid")]
+            MenuItem::LeftKey => settings.left_key = edit_fn(settings.left_key as u64) as u8,
+            #[cfg(feature = "hid")]
+            MenuItem::RightKey => settings.right_key = edit_fn(settings.right_key as u64) as u8,
+            // Edited from 0 as the menu counts from there, the addresses start at 1
+            #[cfg(feature = "bus")]
+            MenuItem::BusAddress => {
+                settings.bus_address = edit_fn(settings.bus_address as u64 - 1) as u8 + 1;
+            }
         }
     }
 }
@@ -156,72 +325,117 @@ const PRESETS: [(&str, GameConfig); 4] = [
     (
         "Normal",
         GameConfig {
-            left_time: Duration::from_secs(10 * 60),
-            right_time: Duration::from_secs(10 * 60),
-            increment_type: IncrementType::Bronstein {
-                left_delay: Duration::from_secs(15),
-                right_delay: Duration::from_secs(15),
+            left: TimeControl {
+                time: Duration::from_secs(10 * 60),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
+            },
+            right: TimeControl {
+                time: Duration::from_secs(10 * 60),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
             },
+            players: 2,
         },
     ),
     (
         "Right handicap",
         GameConfig {
-            left_time: Duration::from_secs(600),
-            right_time: Duration::from_secs(15),
-            increment_type: IncrementType::Bronstein {
-                left_delay: Duration::from_secs(15),
-                right_delay: Duration::from_secs(15),
+            left: TimeControl {
+                time: Duration::from_secs(600),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
             },
+            right: TimeControl {
+                time: Duration::from_secs(15),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
+            },
+            players: 2,
         },
     ),
     (
         "Left handicap",
         GameConfig {
-            left_time: Duration::from_secs(15),
-            right_time: Duration::from_secs(600),
-            increment_type: IncrementType::Bronstein {
-                left_delay: Duration::from_secs(15),
-                right_delay: Duration::from_secs(15),
+            left: TimeControl {
+                time: Duration::from_secs(15),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
+            },
+            right: TimeControl {
+                time: Duration::from_secs(600),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
             },
+            players: 2,
         },
     ),
     (
         "Blitz",
         GameConfig {
-            left_time: Duration::from_secs(15),
-            right_time: Duration::from_secs(15),
-            increment_type: IncrementType::Bronstein {
-                left_delay: Duration::from_secs(15),
-                right_delay: Duration::from_secs(15),
+            left: TimeControl {
+                time: Duration::from_secs(15),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
             },
+            right: TimeControl {
+                time: Duration::from_secs(15),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
+            },
+            players: 2,
         },
     ),
 ];
 
-const MENU_ITEMS: [MenuItem; 6] = [
+/// Built in presets followed by the ones saved by the user
+fn presets(user_presets: &UserPresets) -> impl Iterator<Item = (&str, &GameConfig)> {
+    PRESETS.iter().map(|(name, preset)| (*name, preset)).chain(
+        user_presets
+            .iter()
+            .map(|preset| (preset.name.as_str(), &preset.config)),
+    )
+}
+
+const MENU_ITEMS: &[MenuItem] = &[
     MenuItem::Preset,
-    MenuItem::LeftTime,
-    MenuItem::RightTime,
-    MenuItem::IncrementType,
-    MenuItem::LeftDelay,
-    MenuItem::RightDelay,
+    #[cfg(feature = "table")]
+    MenuItem::Players,
+    MenuItem::Time(Player::Left),
+    MenuItem::IncrementType(Player::Left),
+    MenuItem::Delay(Player::Left),
+    MenuItem::Time(Player::Right),
+    MenuItem::IncrementType(Player::Right),
+    MenuItem::Delay(Player::Right),
+    MenuItem::HandicapStep,
+    MenuItem::HandicapMin,
+    MenuItem::HandicapMax,
+    #[cfg(feature = "odds")]
+    MenuItem::Rating(Player::Left),
+    #[cfg(feature = "odds")]
+    MenuItem::Rating(Player::Right),
+    #[cfg(feature = "odds")]
+    MenuItem::OddsBase,
+    #[cfg(feature = "odds")]
+    MenuItem::ApplyOdds,
+    #[cfg(feature = "match")]
+    MenuItem::MatchGames,
+    #[cfg(feature = "match")]
+    MenuItem::MatchTiebreak,
+    #[cfg(feature = "armageddon")]
+    MenuItem::Armageddon,
+    MenuItem::SavePreset,
+    MenuItem::EditPresets,
+    MenuItem::IdleTimeout,
+    MenuItem::PausedTimeout,
+    MenuItem::SleepInGame,
+    #[cfg(feature = "hid")]
+    MenuItem::Keyboard,
+    #[cfg(feature = "hid")]
+    MenuItem::LeftKey,
+    #[cfg(feature = "hid")]
+    MenuItem::RightKey,
+    #[cfg(feature = "bus")]
+    MenuItem::BusAddress,
 ];
 
 const INCREMENT_TYPES: [IncrementType; 4] = [
     IncrementType::SuddenDeath,
-    IncrementType::Increment {
-        left_increment: Duration::from_secs(10),
-        right_increment: Duration::from_secs(10),
-    },
-    IncrementType::Delay {
-        left_delay: Duration::from_secs(10),
-        right_delay: Duration::from_secs(10),
-    },
-    IncrementType::Bronstein {
-        left_delay: Duration::from_secs(10),
-        right_delay: Duration::from_secs(10),
-    },
+    IncrementType::Increment(Duration::from_secs(10)),
+    IncrementType::Delay(Duration::from_secs(10)),
+    IncrementType::Bronstein(Duration::from_secs(10)),
 ];
 
 #[derive(Clone, PartialEq, Eq)]
@@ -235,6 +449,31 @@ enum EditState {
     NotEditing,
     Cursor(usize),
     Editing(usize),
+    /// Entering the name of a new user preset, or renaming an existing one
+    Naming(NameEditor, Option<usize>),
+    ChoosePreset(usize),
+    PresetAction(usize, PresetAction),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PresetAction {
+    Rename,
+    Delete,
+    Back,
+}
+
+impl PresetAction {
+    fn next(self) -> PresetAction {
+        match self {
+            PresetAction::Rename => PresetAction::Delete,
+            PresetAction::Delete => PresetAction::Back,
+            PresetAction::Back => PresetAction::Rename,
+        }
+    }
+
+    fn prev(self) -> PresetAction {
+        self.next().next()
+    }
 }
 
 impl MenuState {
@@ -245,12 +484,68 @@ impl MenuState {
         }
     }
 
-    pub fn handle_event(&mut self, game_config: &mut GameConfig, event: &Event) {
-        let mut disabled: Vec<MenuItem, 5> = Vec::new();
-        if matches!(game_config.increment_type, IncrementType::SuddenDeath) {
-            let _ = disabled.push(MenuItem::LeftDelay);
-            let _ = disabled.push(MenuItem::RightDelay);
-        };
+    /// Leaves the saved presets once their list changed under the menu
+    pub fn presets_changed(&mut self) {
+        if self.preset_index().is_some() {
+            self.edit_mode = EditState::NotEditing;
+        }
+    }
+
+    /// Saved preset being chosen, renamed or deleted
+    fn preset_index(&self) -> Option<usize> {
+        match self.edit_mode {
+            EditState::Naming(_, Some(idx))
+            | EditState::ChoosePreset(idx)
+            | EditState::PresetAction(idx, _) => Some(idx),
+            _ => None,
+        }
+    }
+
+    /// Item the menu is on
+    #[cfg(feature = "armageddon")]
+    pub fn item(&self) -> &MenuItem {
+        &MENU_ITEMS[self.item_index]
+    }
+
+    pub fn handle_event(
+        &mut self,
+        game_config: &mut GameConfig,
+        settings: &mut Settings,
+        user_presets: &mut UserPresets,
+        event: &Event,
+    ) {
+        // One more for the match tiebreak
+        let mut disabled: Vec<MenuItem, { 8 + cfg!(feature = "match") as usize }> = Vec::new();
+        for player in [Player::Left, Player::Right] {
+            if game_config.control(player).increment_type == IncrementType::SuddenDeath {
+                let _ = disabled.push(MenuItem::Delay(player));
+            }
+        }
+        // Every player round the table uses the left time control
+        #[cfg(feature = "table")]
+        if game_config.players > 2 {
+            let _ = disabled.push(MenuItem::Time(Player::Right));
+            let _ = disabled.push(MenuItem::IncrementType(Player::Right));
+            let _ = disabled.push(MenuItem::Delay(Player::Right));
+        }
+        if settings.handicap_step.as_ticks() == 0 {
+            let _ = disabled.push(MenuItem::HandicapMin);
+            let _ = disabled.push(MenuItem::HandicapMax);
+        }
+        #[cfg(feature = "match")]
+        if settings.match_games == 0 {
+            let _ = disabled.push(MenuItem::MatchTiebreak);
+        }
+        if user_presets.is_empty() {
+            let _ = disabled.push(MenuItem::EditPresets);
+        }
+        if self
+            .preset_index()
+            .is_some_and(|idx| idx >= user_presets.len())
+        {
+            self.edit_mode = EditState::NotEditing;
+            return;
+        }
         match self.edit_mode {
             EditState::NotEditing => match event {
                 Event::ButtonPushed(Button::Left, _) => loop {
@@ -276,10 +571,27 @@ impl MenuState {
                     }
                 },
                 Event::ButtonPushed(Button::Control, PressType::Single) => {
-                    if MENU_ITEMS[self.item_index].cols().len() > 1 {
-                        self.edit_mode = EditState::Cursor(0)
-                    } else {
-                        self.edit_mode = EditState::Editing(0)
+                    match MENU_ITEMS[self.item_index] {
+                        #[cfg(feature = "odds")]
+                        MenuItem::ApplyOdds => *game_config = odds(settings, game_config),
+                        MenuItem::SavePreset => {
+                            if user_presets.len() < MAX_USER_PRESETS {
+                                let editor = NameEditor::new(&default_name(user_presets));
+                                self.edit_mode = EditState::Naming(editor, None);
+                            }
+                        }
+                        MenuItem::EditPresets => {
+                            if !user_presets.is_empty() {
+                                self.edit_mode = EditState::ChoosePreset(0);
+                            }
+                        }
+                        _ => {
+                            if MENU_ITEMS[self.item_index].cols().len() > 1 {
+                                self.edit_mode = EditState::Cursor(0)
+                            } else {
+                                self.edit_mode = EditState::Editing(0)
+                            }
+                        }
                     }
                 }
                 _ => {}
@@ -303,18 +615,14 @@ impl MenuState {
             },
             EditState::Editing(col) => match event {
                 Event::ButtonPushed(Button::Left, _) => {
-                    MENU_ITEMS[self.item_index].edit(game_config, |x| {
-                        if x > 0 {
-                            x - MENU_ITEMS[self.item_index].cols()[col].multiplier
-                        } else {
-                            x
-                        }
+                    MENU_ITEMS[self.item_index].edit(game_config, settings, user_presets, |x| {
+                        x.saturating_sub(MENU_ITEMS[self.item_index].cols()[col].multiplier)
                     });
                 }
                 Event::ButtonPushed(Button::Right, _) => {
-                    MENU_ITEMS[self.item_index].edit(game_config, |x| {
-                        (x + MENU_ITEMS[self.item_index].cols()[col].multiplier)
-                            .min(MENU_ITEMS[self.item_index].max_val())
+                    let max_val = MENU_ITEMS[self.item_index].max_val(user_presets);
+                    MENU_ITEMS[self.item_index].edit(game_config, settings, user_presets, |x| {
+                        (x + MENU_ITEMS[self.item_index].cols()[col].multiplier).min(max_val)
                     });
                 }
                 Event::ButtonPushed(Button::Control, PressType::Single) => {
@@ -322,110 +630,184 @@ impl MenuState {
                 }
                 _ => {}
             },
-        }
-    }
-
-    pub async fn display_state(
-        &self,
-        prev_state: Option<&Self>,
-        prev_game_config: &GameConfig,
-        game_config: &GameConfig,
-        outputs: &mut Outputs<'_, '_>,
-    ) -> Result<(), Error> {
-        if Some(self) != prev_state || prev_game_config != game_config {
-            match self.edit_mode {
-                EditState::NotEditing => {
-                    outputs.lcd.clear().await?;
-                    outputs.lcd.cursor_on(false).await?;
-                    outputs.lcd.cursor_blink(false).await?;
-
-                    self.print_menu(game_config, outputs).await?;
-                    self.print_value(game_config, outputs).await?;
+            EditState::Naming(ref mut editor, target) => {
+                if let Some(name) = editor.handle_event(event) {
+                    let name = if name.is_empty() {
+                        default_name(user_presets)
+                    } else {
+                        name
+                    };
+                    match target {
+                        None => {
+                            let _ = user_presets.push(UserPreset {
+                                name,
+                                config: game_config.clone(),
+                            });
+                        }
+                        Some(idx) => user_presets[idx].name = name,
+                    }
+                    self.edit_mode = EditState::NotEditing;
+                }
+            }
+            EditState::ChoosePreset(idx) => match event {
+                Event::ButtonPushed(Button::Left, _) => {
+                    let idx = match idx {
+                        0 => user_presets.len() - 1,
+                        _ => idx - 1,
+                    };
+                    self.edit_mode = EditState::ChoosePreset(idx);
                 }
-                EditState::Cursor(col) => {
-                    self.print_value(game_config, outputs).await?;
-
-                    outputs
-                        .lcd
-                        .set_cursor(1, MENU_ITEMS[self.item_index].cols()[col].position)
-                        .await?;
-                    outputs.lcd.cursor_on(true).await?;
-                    outputs.lcd.cursor_blink(false).await?;
+                Event::ButtonPushed(Button::Right, _) => {
+                    self.edit_mode = EditState::ChoosePreset((idx + 1) % user_presets.len());
                 }
-                EditState::Editing(col) => {
-                    self.print_value(game_config, outputs).await?;
-
-                    outputs
-                        .lcd
-                        .set_cursor(1, MENU_ITEMS[self.item_index].cols()[col].position)
-                        .await?;
-                    outputs.lcd.cursor_on(false).await?;
-                    outputs.lcd.cursor_blink(true).await?;
+                Event::ButtonPushed(Button::Control, PressType::Single) => {
+                    self.edit_mode = EditState::PresetAction(idx, PresetAction::Rename);
                 }
-            }
+                _ => {}
+            },
+            EditState::PresetAction(idx, action) => match event {
+                Event::ButtonPushed(Button::Left, _) => {
+                    self.edit_mode = EditState::PresetAction(idx, action.prev());
+                }
+                Event::ButtonPushed(Button::Right, _) => {
+                    self.edit_mode = EditState::PresetAction(idx, action.next());
+                }
+                Event::ButtonPushed(Button::Control, PressType::Single) => match action {
+                    PresetAction::Rename => {
+                        let editor = NameEditor::new(&user_presets[idx].name);
+                        self.edit_mode = EditState::Naming(editor, Some(idx));
+                    }
+                    PresetAction::Delete => {
+                        user_presets.remove(idx);
+                        self.edit_mode = EditState::NotEditing;
+                    }
+                    PresetAction::Back => self.edit_mode = EditState::NotEditing,
+                },
+                _ => {}
+            },
         }
-        Ok(())
     }
 
-    async fn print_menu(
+    pub fn view(
         &self,
         game_config: &GameConfig,
-        outputs: &mut Outputs<'_, '_>,
-    ) -> Result<(), Error> {
-        outputs.lcd.set_cursor(0, 0).await?;
-        match MENU_ITEMS[self.item_index] {
-            MenuItem::Preset => {
-                outputs.lcd.write_str("Preset").await?;
-            }
-            MenuItem::LeftTime => {
-                outputs.lcd.write_str("Left time").await?;
+        settings: &Settings,
+        user_presets: &UserPresets,
+    ) -> Result<Frame, Error> {
+        let mut frame = Frame::new();
+        // Blank until the next event leaves a preset that is gone
+        let name = |idx| {
+            user_presets
+                .get(idx)
+                .map_or("", |preset: &UserPreset| &preset.name)
+        };
+        match self.edit_mode {
+            EditState::Naming(ref editor, _) => {
+                frame.print(0, 0, "Preset name");
+                editor.view(&mut frame);
+                return Ok(frame);
             }
-            MenuItem::RightTime => {
-                outputs.lcd.write_str("Right time").await?;
+            EditState::ChoosePreset(idx) => {
+                frame.print(0, 0, "My presets");
+                frame.print(1, 0, name(idx));
+                return Ok(frame);
             }
-            MenuItem::IncrementType => {
-                outputs.lcd.write_str("Increment type").await?;
+            EditState::PresetAction(idx, action) => {
+                frame.print(0, 0, name(idx));
+                let action = match action {
+                    PresetAction::Rename => "< Rename >",
+                    PresetAction::Delete => "< Delete >",
+                    PresetAction::Back => "< Back >",
+                };
+                frame.print(1, 0, action);
+                return Ok(frame);
             }
-            MenuItem::LeftDelay => match game_config.increment_type {
-                IncrementType::SuddenDeath => {}
-                IncrementType::Increment { .. } => {
-                    outputs.lcd.write_str("Left increment").await?;
-                }
-                IncrementType::Delay { .. } => {
-                    outputs.lcd.write_str("Left delay").await?;
-                }
-                IncrementType::Bronstein { .. } => {
-                    outputs.lcd.write_str("Left delay").await?;
-                }
+            _ => {}
+        }
+
+        self.print_menu(game_config, &mut frame);
+        self.print_value(game_config, settings, user_presets, &mut frame)?;
+
+        let item = &MENU_ITEMS[self.item_index];
+        frame.cursor = match self.edit_mode {
+            EditState::NotEditing => CursorMode::Hidden,
+            EditState::Cursor(col) => CursorMode::Underline {
+                row: 1,
+                col: item.cols()[col].position,
             },
-            MenuItem::RightDelay => match game_config.increment_type {
-                IncrementType::SuddenDeath => {}
-                IncrementType::Increment { .. } => {
-                    outputs.lcd.write_str("Right increment").await?;
-                }
-                IncrementType::Delay { .. } => {
-                    outputs.lcd.write_str("Right delay").await?;
-                }
-                IncrementType::Bronstein { .. } => {
-                    outputs.lcd.write_str("Right delay").await?;
-                }
+            EditState::Editing(col) => CursorMode::Blink {
+                row: 1,
+                col: item.cols()[col].position,
             },
-        }
-        Ok(())
+            _ => CursorMode::Hidden,
+        };
+        Ok(frame)
+    }
+
+    fn print_menu(&self, game_config: &GameConfig, frame: &mut Frame) {
+        let label = match MENU_ITEMS[self.item_index] {
+            MenuItem::Preset => "Preset",
+            #[cfg(feature = "table")]
+            MenuItem::Players => "Players",
+            #[cfg(feature = "table")]
+            MenuItem::Time(Player::Left) if game_config.players > 2 => "Time per player",
+            MenuItem::Time(Player::Left) => "Left time",
+            MenuItem::Time(Player::Right) => "Right time",
+            MenuItem::IncrementType(Player::Left) => "Left mode",
+            MenuItem::IncrementType(Player::Right) => "Right mode",
+            MenuItem::Delay(player) => match (player, game_config.control(player).increment_type) {
+                (_, IncrementType::SuddenDeath) => "",
+                (Player::Left, IncrementType::Increment(_)) => "Left increment",
+                (Player::Left, _) => "Left delay",
+                (Player::Right, IncrementType::Increment(_)) => "Right increment",
+                (Player::Right, _) => "Right delay",
+            },
+            MenuItem::HandicapStep => "Adaptive step",
+            MenuItem::HandicapMin => "Handicap min",
+            MenuItem::HandicapMax => "Handicap max",
+            #[cfg(feature = "odds")]
+            MenuItem::Rating(Player::Left) => "Left rating",
+            #[cfg(feature = "odds")]
+            MenuItem::Rating(Player::Right) => "Right rating",
+            #[cfg(feature = "odds")]
+            MenuItem::OddsBase => "Odds base time",
+            #[cfg(feature = "odds")]
+            MenuItem::ApplyOdds => "Apply odds",
+            #[cfg(feature = "match")]
+            MenuItem::MatchGames => "Match games",
+            #[cfg(feature = "match")]
+            MenuItem::MatchTiebreak => "Match tiebreak",
+            #[cfg(feature = "armageddon")]
+            MenuItem::Armageddon => "Armageddon",
+            MenuItem::SavePreset => "Save preset",
+            MenuItem::EditPresets => "My presets",
+            MenuItem::IdleTimeout => "Sleep after",
+            MenuItem::PausedTimeout => "Sleep if paused",
+            MenuItem::SleepInGame => "Sleep in game",
+            #[cfg(feature = "hid")]
+            MenuItem::Keyboard => "USB keyboard",
+            #[cfg(feature = "hid")]
+            MenuItem::LeftKey => "Left key",
+            #[cfg(feature = "hid")]
+            MenuItem::RightKey => "Right key",
+            #[cfg(feature = "bus")]
+            MenuItem::BusAddress => "Bus address",
+        };
+        frame.print(0, 0, label);
     }
 
-    async fn print_value(
+    fn print_value(
         &self,
         game_config: &GameConfig,
-        outputs: &mut Outputs<'_, '_>,
+        settings: &Settings,
+        user_presets: &UserPresets,
+        frame: &mut Frame,
     ) -> Result<(), Error> {
-        outputs.lcd.set_cursor(1, 0).await?;
         match MENU_ITEMS[self.item_index] {
             MenuItem::Preset => {
-                let preset_name = PRESETS
-                    .into_iter()
+                let preset_name = presets(user_presets)
                     .find_map(|(name, preset)| {
-                        if &preset == game_config {
+                        if preset == game_config {
                             Some(name)
                         } else {
                             None
@@ -433,117 +815,399 @@ impl MenuState {
                     })
                     .unwrap_or("Unknown");
 
-                outputs.lcd.write_str("                ").await?;
-                outputs.lcd.set_cursor(1, 0).await?;
-                outputs.lcd.write_str(preset_name).await?;
-            }
-            MenuItem::LeftTime => {
-                outputs
-                    .lcd
-                    .write_str(&format_duration(game_config.left_time)?)
-                    .await?;
-            }
-            MenuItem::RightTime => {
-                outputs
-                    .lcd
-                    .write_str(&format_duration(game_config.right_time)?)
-                    .await?;
-            }
-            MenuItem::IncrementType => {
-                outputs.lcd.set_cursor(1, 0).await?;
-                outputs.lcd.write_str("                ").await?;
-                outputs.lcd.set_cursor(1, 0).await?;
-                match game_config.increment_type {
-                    IncrementType::SuddenDeath => {
-                        outputs.lcd.write_str("Sudden death").await?;
-                    }
-                    IncrementType::Increment { .. } => {
-                        outputs.lcd.write_str("Increment").await?;
-                    }
-                    IncrementType::Delay { .. } => {
-                        outputs.lcd.write_str("Delay").await?;
-                    }
-                    IncrementType::Bronstein { .. } => {
-                        outputs.lcd.write_str("Bronstein delay").await?;
-                    }
-                }
+                frame.print(1, 0, preset_name);
+            }
+            #[cfg(feature = "table")]
+            MenuItem::Players => {
+                let mut text: String<1> = String::new();
+                write!(&mut text, "{}", game_config.players)?;
+                frame.print(1, 0, &text);
+            }
+            MenuItem::Time(player) => {
+                frame.print(1, 0, &format_duration(game_config.control(player).time)?);
+            }
+            MenuItem::IncrementType(player) => {
+                let name = match game_config.control(player).increment_type {
+                    IncrementType::SuddenDeath => "Sudden death",
+                    IncrementType::Increment(_) => "Increment",
+                    IncrementType::Delay(_) => "Delay",
+                    IncrementType::Bronstein(_) => "Bronstein delay",
+                };
+                frame.print(1, 0, name);
             }
-            MenuItem::LeftDelay => match game_config.increment_type {
+            MenuItem::Delay(player) => match game_config.control(player).increment_type {
                 IncrementType::SuddenDeath => {}
-                IncrementType::Increment { left_increment, .. } => {
-                    outputs
-                        .lcd
-                        .write_str(&format_duration(left_increment)?)
-                        .await?;
-                }
-                IncrementType::Delay { left_delay, .. } => {
-                    outputs.lcd.write_str(&format_duration(left_delay)?).await?;
-                }
-                IncrementType::Bronstein { left_delay, .. } => {
-                    outputs.lcd.write_str(&format_duration(left_delay)?).await?;
+                IncrementType::Increment(duration)
+                | IncrementType::Delay(duration)
+                | IncrementType::Bronstein(duration) => {
+                    frame.print(1, 0, &format_duration(duration)?);
                 }
             },
-            MenuItem::RightDelay => match game_config.increment_type {
-                IncrementType::SuddenDeath => {}
-                IncrementType::Increment {
-                    right_increment, ..
-                } => {
-                    outputs
-                        .lcd
-                        .write_str(&format_duration(right_increment)?)
-                        .await?;
-                }
-                IncrementType::Delay { right_delay, .. } => {
-                    outputs
-                        .lcd
-                        .write_str(&format_duration(right_delay)?)
-                        .await?;
+            MenuItem::HandicapStep => {
+                frame.print(1, 0, &format_duration(settings.handicap_step)?);
+                if settings.handicap_step.as_ticks() == 0 {
+                    frame.print(1, 6, "off");
                 }
-                IncrementType::Bronstein { right_delay, .. } => {
-                    outputs
-                        .lcd
-                        .write_str(&format_duration(right_delay)?)
-                        .await?;
+            }
+            MenuItem::HandicapMin => {
+                frame.print(1, 0, &format_duration(settings.handicap_min)?);
+            }
+            MenuItem::HandicapMax => {
+                frame.print(1, 0, &format_duration(settings.handicap_max)?);
+            }
+            #[cfg(feature = "odds")]
+            MenuItem::Rating(player) => {
+                let rating = match player {
+                    Player::Left => settings.left_rating,
+                    Player::Right => settings.right_rating,
+                };
+                let mut text: String<4> = String::new();
+                write!(&mut text, "{:>4}", rating)?;
+                frame.print(1, 0, &text);
+            }
+            #[cfg(feature = "odds")]
+            MenuItem::OddsBase => {
+                frame.print(1, 0, &format_duration(settings.odds_base)?);
+            }
+            // The times the odds come to, applied with the control button
+            #[cfg(feature = "odds")]
+            MenuItem::ApplyOdds => {
+                let odds = odds(settings, game_config);
+                frame.print(1, 0, &format_duration(odds.left.time)?);
+                frame.print(1, 11, &format_duration(odds.right.time)?);
+            }
+            #[cfg(feature = "match")]
+            MenuItem::MatchGames if settings.match_games == 0 => frame.print(1, 0, "Off"),
+            #[cfg(feature = "match")]
+            MenuItem::MatchGames => {
+                let mut text: String<9> = String::new();
+                write!(&mut text, "Best of {}", settings.match_games)?;
+                frame.print(1, 0, &text);
+            }
+            #[cfg(feature = "match")]
+            MenuItem::MatchTiebreak => {
+                let tiebreak = if settings.match_tiebreak {
+                    "Armageddon"
+                } else {
+                    "None"
+                };
+                frame.print(1, 0, tiebreak);
+            }
+            // The times of white and black, given out with the control button
+            #[cfg(feature = "armageddon")]
+            MenuItem::Armageddon => {
+                frame.print(1, 0, &format_duration(ARMAGEDDON_TIME)?);
+                frame.print(1, 6, "vs");
+                frame.print(
+                    1,
+                    9,
+                    &format_duration(ARMAGEDDON_TIME - Duration::from_secs(60))?,
+                );
+            }
+            MenuItem::SavePreset => {
+                if user_presets.len() >= MAX_USER_PRESETS {
+                    frame.print(1, 0, "No free slot");
                 }
-            },
+            }
+            MenuItem::EditPresets => {
+                let mut text: String<16> = String::new();
+                write!(&mut text, "{} saved", user_presets.len())?;
+                frame.print(1, 0, &text);
+            }
+            MenuItem::IdleTimeout => print_timeout(settings.idle_timeout, frame)?,
+            MenuItem::PausedTimeout => print_timeout(settings.paused_timeout, frame)?,
+            MenuItem::SleepInGame => {
+                frame.print(1, 0, if settings.sleep_in_game { "Yes" } else { "No" });
+            }
+            #[cfg(feature = "hid")]
+            MenuItem::Keyboard => frame.print(1, 0, settings.keyboard.label()),
+            #[cfg(feature = "hid")]
+            MenuItem::LeftKey => frame.print(1, 0, keyboard::get(settings.left_key).label),
+            #[cfg(feature = "hid")]
+            MenuItem::RightKey => frame.print(1, 0, keyboard::get(settings.right_key).label),
+            #[cfg(feature = "bus")]
+            MenuItem::BusAddress => {
+                let mut text: String<2> = String::new();
+                write!(&mut text, "{:>2}", settings.bus_address)?;
+                frame.print(1, 0, &text);
+            }
         }
         Ok(())
     }
 }
 
+fn print_timeout(timeout: Duration, frame: &mut Frame) -> Result<(), Error> {
+    frame.print(1, 0, &format_duration(timeout)?);
+    if timeout.as_ticks() == 0 {
+        frame.print(1, 6, "never");
+    }
+    Ok(())
+}
+
+/// Time control of one player
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub time: Duration,
+    pub increment_type: IncrementType,
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct GameConfig {
-    pub left_time: Duration,
-    pub right_time: Duration,
-    pub increment_type: IncrementType,
+    pub left: TimeControl,
+    pub right: TimeControl,
+    /// More than two players run the table timer, with the left time control for everyone
+    pub players: u8,
+}
+
+impl GameConfig {
+    pub fn control(&self, player: Player) -> &TimeControl {
+        match player {
+            Player::Left => &self.left,
+            Player::Right => &self.right,
+        }
+    }
+
+    pub fn control_mut(&mut self, player: Player) -> &mut TimeControl {
+        match player {
+            Player::Left => &mut self.left,
+            Player::Right => &mut self.right,
+        }
+    }
 }
 
 impl Default for GameConfig {
     fn default() -> Self {
         GameConfig {
-            left_time: Duration::from_secs(600),
-            right_time: Duration::from_secs(15),
-            increment_type: IncrementType::Bronstein {
-                left_delay: Duration::from_secs(15),
-                right_delay: Duration::from_secs(15),
+            left: TimeControl {
+                time: Duration::from_secs(600),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
+            },
+            right: TimeControl {
+                time: Duration::from_secs(15),
+                increment_type: IncrementType::Bronstein(Duration::from_secs(15)),
             },
+            players: 2,
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq)]
+impl From<protocol::Config> for GameConfig {
+    fn from(config: protocol::Config) -> Self {
+        let control = |secs: u32, kind, increment_secs: u32| TimeControl {
+            time: Duration::from_secs(secs as u64),
+            increment_type: IncrementType::new(kind, Duration::from_secs(increment_secs as u64)),
+        };
+        GameConfig {
+            left: control(
+                config.left_secs,
+                config.left_kind,
+                config.left_increment_secs,
+            ),
+            right: control(
+                config.right_secs,
+                config.right_kind,
+                config.right_increment_secs,
+            ),
+            players: 2,
+        }
+    }
+}
+
+impl From<&GameConfig> for protocol::Config {
+    fn from(game_config: &GameConfig) -> Self {
+        let secs = |duration: Duration| duration.as_secs() as u32;
+        let (left, right) = (&game_config.left, &game_config.right);
+        protocol::Config {
+            left_secs: secs(left.time),
+            right_secs: secs(right.time),
+            left_kind: left.increment_type.kind(),
+            right_kind: right.increment_type.kind(),
+            left_increment_secs: secs(left.increment_type.duration()),
+            right_increment_secs: secs(right.increment_type.duration()),
+        }
+    }
+}
+
+/// Increment type of one player, with the seconds added or the delay
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum IncrementType {
     SuddenDeath,
-    Increment {
-        left_increment: Duration,
-        right_increment: Duration,
-    },
-    Delay {
-        left_delay: Duration,
-        right_delay: Duration,
-    },
-    Bronstein {
-        left_delay: Duration,
-        right_delay: Duration,
-    },
+    Increment(Duration),
+    Delay(Duration),
+    Bronstein(Duration),
+}
+
+impl IncrementType {
+    pub fn new(kind: IncrementKind, duration: Duration) -> IncrementType {
+        match kind {
+            IncrementKind::SuddenDeath => IncrementType::SuddenDeath,
+            IncrementKind::Increment => IncrementType::Increment(duration),
+            IncrementKind::Delay => IncrementType::Delay(duration),
+            IncrementKind::Bronstein => IncrementType::Bronstein(duration),
+        }
+    }
+
+    pub fn kind(&self) -> IncrementKind {
+        match self {
+            IncrementType::SuddenDeath => IncrementKind::SuddenDeath,
+            IncrementType::Increment(_) => IncrementKind::Increment,
+            IncrementType::Delay(_) => IncrementKind::Delay,
+            IncrementType::Bronstein(_) => IncrementKind::Bronstein,
+        }
+    }
+
+    /// Seconds added or delay, zero in sudden death
+    pub fn duration(&self) -> Duration {
+        match *self {
+            IncrementType::SuddenDeath => Duration::from_ticks(0),
+            IncrementType::Increment(duration)
+            | IncrementType::Delay(duration)
+            | IncrementType::Bronstein(duration) => duration,
+        }
+    }
+
+    /// Delay at the start of a turn, zero outside the delay modes
+    pub fn turn_delay(&self) -> Duration {
+        match *self {
+            IncrementType::Delay(delay) | IncrementType::Bronstein(delay) => delay,
+            IncrementType::SuddenDeath | IncrementType::Increment(_) => Duration::from_ticks(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::NAME_LEN;
+
+    /// Menu open on an item, with what it edits
+    struct Menu {
+        state: MenuState,
+        game_config: GameConfig,
+        settings: Settings,
+        user_presets: UserPresets,
+    }
+
+    impl Menu {
+        fn on(item: MenuItem, names: &[&str]) -> Self {
+            let mut user_presets = UserPresets::new();
+            for name in names {
+                let _ = user_presets.push(UserPreset {
+                    name: (*name).try_into().unwrap(),
+                    config: GameConfig::default(),
+                });
+            }
+            let mut state = MenuState::new();
+            state.item_index = MENU_ITEMS.iter().position(|i| i == &item).unwrap();
+            Menu {
+                state,
+                game_config: GameConfig::default(),
+                settings: Settings::default(),
+                user_presets,
+            }
+        }
+
+        fn press(&mut self, buttons: &[Button]) {
+            for button in buttons {
+                self.state.handle_event(
+                    &mut self.game_config,
+                    &mut self.settings,
+                    &mut self.user_presets,
+                    &Event::ButtonPushed(*button, PressType::Single),
+                );
+            }
+        }
+
+        fn names(&self) -> std::vec::Vec<&str> {
+            self.user_presets.iter().map(|p| p.name.as_str()).collect()
+        }
+
+        fn row(&self, row: usize) -> std::string::String {
+            let frame = self
+                .state
+                .view(&self.game_config, &self.settings, &self.user_presets)
+                .unwrap();
+            std::string::String::from_utf8_lossy(&frame.cells[row])
+                .trim_end()
+                .into()
+        }
+    }
+
+    #[test]
+    fn a_new_preset_keeps_the_default_name() {
+        let mut menu = Menu::on(MenuItem::SavePreset, &["BLITZ"]);
+        menu.press(&[Button::Control]);
+        assert_eq!(menu.row(1), "PRESET 2");
+        menu.press(&[Button::Control; NAME_LEN]);
+        assert_eq!(menu.names(), ["BLITZ", "PRESET 2"]);
+    }
+
+    #[test]
+    fn an_empty_name_falls_back_to_the_default() {
+        let mut menu = Menu::on(MenuItem::SavePreset, &[]);
+        menu.press(&[Button::Control]);
+        // From the P of the default name back through the space to the arrow
+        menu.press(&[Button::Left; 17]);
+        menu.press(&[Button::Control]);
+        assert_eq!(menu.names(), ["PRESET 1"]);
+    }
+
+    #[test]
+    fn presets_are_renamed() {
+        let mut menu = Menu::on(MenuItem::EditPresets, &["BLITZ"]);
+        menu.press(&[Button::Control]);
+        assert_eq!(menu.row(1), "BLITZ");
+        menu.press(&[Button::Control, Button::Control, Button::Right]);
+        menu.press(&[Button::Control; NAME_LEN]);
+        assert_eq!(menu.names(), ["CLITZ"]);
+    }
+
+    #[test]
+    fn presets_are_deleted() {
+        let mut menu = Menu::on(MenuItem::EditPresets, &["BLITZ", "RAPID", "CLASSIC"]);
+        menu.press(&[Button::Control, Button::Right, Button::Control]);
+        assert_eq!(menu.row(0), "RAPID");
+        menu.press(&[Button::Right, Button::Control]);
+        assert_eq!(menu.names(), ["BLITZ", "CLASSIC"]);
+    }
+
+    #[test]
+    fn going_back_leaves_the_preset_alone() {
+        let mut menu = Menu::on(MenuItem::EditPresets, &["BLITZ"]);
+        menu.press(&[
+            Button::Control,
+            Button::Control,
+            Button::Left,
+            Button::Control,
+        ]);
+        assert_eq!(menu.names(), ["BLITZ"]);
+        assert!(menu.state.edit_mode == EditState::NotEditing);
+    }
+
+    #[test]
+    fn deleting_the_preset_shown_over_the_serial_port_leaves_it() {
+        use crate::{app::Page, effect::Effects, protocol::Command, testing::app_state};
+
+        let mut menu = Menu::on(MenuItem::EditPresets, &["BLITZ"]);
+        menu.press(&[Button::Control, Button::Control]);
+        let mut state = app_state();
+        state.user_presets = menu.user_presets;
+        state.page = Page::Menu(menu.state);
+        let event = Event::Command(Command::DeletePreset(1));
+        state.handle_event(&mut Effects::new(), event);
+        assert!(state.user_presets.is_empty());
+        assert!(matches!(state.page, Page::Menu(ref m) if m.edit_mode == EditState::NotEditing));
+        assert!(state.view().is_ok());
+    }
+
+    #[test]
+    fn a_preset_gone_from_the_list_is_left_on_the_next_press() {
+        let mut menu = Menu::on(MenuItem::EditPresets, &["BLITZ", "RAPID"]);
+        menu.press(&[Button::Control, Button::Right, Button::Control]);
+        menu.user_presets.pop();
+        assert_eq!(menu.row(0), "");
+        menu.press(&[Button::Right]);
+        assert!(menu.state.edit_mode == EditState::NotEditing);
+        assert_eq!(menu.names(), ["BLITZ"]);
+    }
 }
@@ -0,0 +1,99 @@
+//! Time odds from the ratings of the players, so a game between a stronger and a weaker player
+//! starts with an expected score of one half for both.
+//!
+//! The Elo expected score of the stronger player is `1 / (1 + 10^(-D / 400))` for a rating
+//! difference D. Doubling the thinking time is taken to be worth `DOUBLING` rating points, so the
+//! weaker player keeps the base time and the stronger one gets `base / 2^(D / DOUBLING)`, which
+//! cancels the difference out. The power of two is worked out in integers, halving for every
+//! whole doubling and interpolating the rest from a table in thousandths.
+
+use embassy_time::Duration;
+
+use crate::{menu::GameConfig, settings::Settings};
+
+/// Rating points one doubling of the thinking time is worth
+const DOUBLING: u32 = 100;
+
+/// `2^(-i / 10)` in thousandths, for the part of a doubling left after the whole ones
+const FRACTIONS: [u64; 11] = [1000, 933, 871, 812, 758, 707, 660, 616, 574, 536, 500];
+
+/// Time of the stronger player, in whole seconds and at least one
+fn stronger_time(base: Duration, difference: u32) -> Duration {
+    let halvings = (difference / DOUBLING).min(63);
+    let tenths = (difference % DOUBLING) * 10 / DOUBLING;
+    let rest = (difference % DOUBLING) * 10 % DOUBLING;
+    let (high, low) = (FRACTIONS[tenths as usize], FRACTIONS[tenths as usize + 1]);
+    let fraction = high - (high - low) * rest as u64 / DOUBLING as u64;
+    let secs = (base.as_secs() * fraction / 1000) >> halvings;
+    Duration::from_secs(secs.max(1))
+}
+
+/// Game config with the time odds of the ratings in the settings, keeping the increment types.
+/// A base under a second is taken as one, no game starts without time.
+pub fn odds(settings: &Settings, game_config: &GameConfig) -> GameConfig {
+    let mut game_config = game_config.clone();
+    let (left, right) = (settings.left_rating as u32, settings.right_rating as u32);
+    let base = settings.odds_base.max(Duration::from_secs(1));
+    game_config.left.time = base;
+    game_config.right.time = base;
+    if left > right {
+        game_config.left.time = stronger_time(base, left - right);
+    } else {
+        game_config.right.time = stronger_time(base, right - left);
+    }
+    game_config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::MAX_RATING;
+
+    /// Times of the left and right players in seconds
+    fn times(left_rating: u16, right_rating: u16, base_secs: u64) -> (u64, u64) {
+        let settings = Settings {
+            left_rating,
+            right_rating,
+            odds_base: Duration::from_secs(base_secs),
+            ..Settings::default()
+        };
+        let game_config = odds(&settings, &GameConfig::default());
+        (
+            game_config.left.time.as_secs(),
+            game_config.right.time.as_secs(),
+        )
+    }
+
+    #[test]
+    fn equal_ratings_give_both_the_base() {
+        assert_eq!(times(1500, 1500, 600), (600, 600));
+    }
+
+    #[test]
+    fn the_stronger_time_halves_every_hundred_points() {
+        // Half a doubling is the square root of a half
+        assert_eq!(times(1550, 1500, 600), (424, 600));
+        assert_eq!(times(1600, 1500, 600), (300, 600));
+        assert_eq!(times(1650, 1500, 600), (212, 600));
+        assert_eq!(times(1800, 1500, 600), (75, 600));
+    }
+
+    #[test]
+    fn either_player_can_be_the_stronger() {
+        assert_eq!(times(1500, 1600, 600), (600, 300));
+        assert_eq!(times(2000, 1900, 600), (300, 600));
+        assert_eq!(times(1900, 2000, 600), (600, 300));
+    }
+
+    #[test]
+    fn the_stronger_player_keeps_a_second() {
+        assert_eq!(times(2500, 1500, 600), (1, 600));
+        assert_eq!(times(0, MAX_RATING, 3599), (3599, 1));
+    }
+
+    #[test]
+    fn a_base_of_zero_is_taken_as_a_second() {
+        assert_eq!(times(1500, 1500, 0), (1, 1));
+        assert_eq!(times(1600, 1500, 0), (1, 1));
+    }
+}
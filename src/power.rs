@@ -0,0 +1,37 @@
+use embassy_stm32::pac::{pwr::vals::Pdds, DBGMCU, PWR, RCC};
+
+/// Stops the MCU until an EXTI line, such as one of the buttons, wakes it up.
+///
+/// All clocks are halted in STOP mode, so the embassy time driver pauses as well. The MCU wakes up
+/// on the HSI oscillator, the clock configuration from before is restored before returning.
+pub fn stop() {
+    let cr = RCC.cr().read();
+    let cfgr = RCC.cfgr().read();
+
+    // Keep the debug probe connected while stopped, at the cost of a higher consumption
+    let debugging = cortex_m::peripheral::DCB::is_debugger_attached();
+    DBGMCU.cr().modify(|w| w.set_dbg_stop(debugging));
+
+    PWR.cr().modify(|w| {
+        w.set_pdds(Pdds::STOP_MODE);
+        w.set_lpds(true);
+        w.set_cwuf(true);
+    });
+    // Safety: only the sleep bits of the SCB are touched, nothing else uses them
+    let mut scb = unsafe { cortex_m::Peripherals::steal() }.SCB;
+    scb.set_sleepdeep();
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+    scb.clear_sleepdeep();
+
+    if cr.hseon() {
+        RCC.cr().modify(|w| w.set_hseon(true));
+        while !RCC.cr().read().hserdy() {}
+    }
+    if cr.pllon() {
+        RCC.cr().modify(|w| w.set_pllon(true));
+        while !RCC.cr().read().pllrdy() {}
+    }
+    RCC.cfgr().modify(|w| w.set_sw(cfgr.sw()));
+    while RCC.cfgr().read().sws() != cfgr.sw() {}
+}
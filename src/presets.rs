@@ -0,0 +1,148 @@
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+
+use crate::{
+    app::{Button, Event, PressType},
+    display::{CursorMode, Frame},
+    menu::GameConfig,
+    protocol,
+};
+
+pub const MAX_USER_PRESETS: usize = 6;
+pub const NAME_LEN: usize = protocol::NAME_LEN;
+
+/// Character picked to finish the name, shown as an arrow by the LCD
+const END: u8 = 0x7e;
+const CHARSET: &[u8] = b" ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-\x7e";
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct UserPreset {
+    pub name: String<NAME_LEN>,
+    pub config: GameConfig,
+}
+
+/// Presets saved from the menu, listed after the built in ones
+pub type UserPresets = Vec<UserPreset, MAX_USER_PRESETS>;
+
+pub fn default_name(user_presets: &UserPresets) -> String<NAME_LEN> {
+    let mut name = String::new();
+    let _ = write!(&mut name, "PRESET {}", user_presets.len() + 1);
+    name
+}
+
+/// Character picker editing a name with the three buttons.
+///
+/// Left and right step through the characters, control moves to the next position. Picking the
+/// arrow or stepping past the last position finishes the name.
+#[derive(Clone, PartialEq, Eq)]
+pub struct NameEditor {
+    chars: [u8; NAME_LEN],
+    position: usize,
+}
+
+impl NameEditor {
+    pub fn new(name: &str) -> NameEditor {
+        let mut chars = [b' '; NAME_LEN];
+        chars
+            .iter_mut()
+            .zip(name.bytes())
+            .for_each(|(c, byte)| *c = byte);
+        NameEditor { chars, position: 0 }
+    }
+
+    /// Returns the name once it is finished
+    pub fn handle_event(&mut self, event: &Event) -> Option<String<NAME_LEN>> {
+        match event {
+            Event::ButtonPushed(Button::Left, _) => self.step(CHARSET.len() - 1),
+            Event::ButtonPushed(Button::Right, _) => self.step(1),
+            Event::ButtonPushed(Button::Control, PressType::Single) => {
+                if self.chars[self.position] == END || self.position == NAME_LEN - 1 {
+                    return Some(self.name());
+                }
+                self.position += 1;
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn step(&mut self, offset: usize) {
+        let c = &mut self.chars[self.position];
+        let idx = CHARSET.iter().position(|x| x == c).unwrap_or(0);
+        *c = CHARSET[(idx + offset) % CHARSET.len()];
+    }
+
+    fn name(&self) -> String<NAME_LEN> {
+        self.chars
+            .iter()
+            .take_while(|c| **c != END)
+            .map(|c| *c as char)
+            .collect::<String<NAME_LEN>>()
+            .trim_end()
+            .try_into()
+            .unwrap_or_default()
+    }
+
+    pub fn view(&self, frame: &mut Frame) {
+        frame.cells[1][..NAME_LEN].copy_from_slice(&self.chars);
+        frame.cursor = CursorMode::Blink {
+            row: 1,
+            col: self.position as u8,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(editor: &mut NameEditor, button: Button) -> Option<String<NAME_LEN>> {
+        editor.handle_event(&Event::ButtonPushed(button, PressType::Single))
+    }
+
+    #[test]
+    fn the_buttons_step_through_the_characters() {
+        let mut editor = NameEditor::new("");
+        press(&mut editor, Button::Right);
+        press(&mut editor, Button::Right);
+        assert_eq!(editor.chars[0], b'B');
+        press(&mut editor, Button::Left);
+        press(&mut editor, Button::Left);
+        assert_eq!(editor.chars[0], b' ');
+        // Round to the arrow at the end
+        press(&mut editor, Button::Left);
+        assert_eq!(editor.chars[0], END);
+    }
+
+    #[test]
+    fn the_arrow_finishes_the_name() {
+        let mut editor = NameEditor::new("AB");
+        assert_eq!(press(&mut editor, Button::Control), None);
+        assert_eq!(press(&mut editor, Button::Control), None);
+        press(&mut editor, Button::Left);
+        assert_eq!(press(&mut editor, Button::Control).unwrap(), "AB");
+    }
+
+    #[test]
+    fn stepping_past_the_last_position_finishes_the_name() {
+        let mut editor = NameEditor::new("X");
+        for _ in 0..NAME_LEN - 1 {
+            assert_eq!(press(&mut editor, Button::Control), None);
+        }
+        assert_eq!(press(&mut editor, Button::Control).unwrap(), "X");
+    }
+
+    #[test]
+    fn default_names_count_on_from_the_presets() {
+        let mut user_presets = UserPresets::new();
+        assert_eq!(default_name(&user_presets), "PRESET 1");
+        let preset = UserPreset {
+            name: default_name(&user_presets),
+            config: GameConfig::default(),
+        };
+        let _ = user_presets.push(preset.clone());
+        let _ = user_presets.push(preset);
+        assert_eq!(default_name(&user_presets), "PRESET 3");
+    }
+}
@@ -0,0 +1,108 @@
+//! Result page of a paused game, for the results other than a flag: a loss or a draw entered by
+//! hand, which the match and the adaptive handicap both count.
+
+use crate::{
+    app::{Button, Event, PressType},
+    display::Frame,
+    game::{GameState, Player},
+};
+
+/// Result chosen on the result page
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Choice {
+    /// Back to the paused game
+    Back,
+    Lost(Player),
+    Draw,
+}
+
+impl Choice {
+    fn next(self) -> Choice {
+        match self {
+            Choice::Back => Choice::Lost(Player::Left),
+            Choice::Lost(Player::Left) => Choice::Draw,
+            Choice::Draw => Choice::Lost(Player::Right),
+            Choice::Lost(Player::Right) => Choice::Back,
+        }
+    }
+
+    fn prev(self) -> Choice {
+        self.next().next().next()
+    }
+}
+
+/// Result page of a paused game, for the results other than a flag
+#[derive(Clone)]
+pub struct ResultState {
+    pub game: GameState,
+    choice: Choice,
+}
+
+impl ResultState {
+    pub fn new(game: GameState) -> ResultState {
+        ResultState {
+            game,
+            choice: Choice::Back,
+        }
+    }
+
+    /// Returns the choice once it is confirmed with the control button
+    pub fn handle_event(&mut self, event: &Event) -> Option<Choice> {
+        match event {
+            Event::ButtonPushed(Button::Left, _) => self.choice = self.choice.prev(),
+            Event::ButtonPushed(Button::Right, _) => self.choice = self.choice.next(),
+            Event::ButtonPushed(Button::Control, PressType::Single) => return Some(self.choice),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn view(&self) -> Frame {
+        let mut frame = Frame::new();
+        frame.print(0, 0, "Result");
+        let choice = match self.choice {
+            Choice::Back => "< Back >",
+            Choice::Lost(Player::Left) => "< Left lost >",
+            Choice::Draw => "< Draw >",
+            Choice::Lost(Player::Right) => "< Right lost >",
+        };
+        frame.print(1, 0, choice);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_time::Duration;
+
+    use super::*;
+    use crate::{app::Page, effect::Effects, testing::app_state};
+
+    #[test]
+    fn a_loss_entered_by_hand_adapts_the_handicap() {
+        let mut state = app_state();
+        state.settings.handicap_step = Duration::from_secs(30);
+        state.game_config.left.time = Duration::from_secs(300);
+        state.game_config.right.time = Duration::from_secs(300);
+        state.page = Page::Game(GameState::new(&state.game_config, Player::Left));
+        for event in [
+            Event::ButtonPushed(Button::Left, PressType::Long),
+            Event::ButtonPushed(Button::Right, PressType::Single),
+            Event::ButtonPushed(Button::Control, PressType::Single),
+        ] {
+            state.handle_event(&mut Effects::new(), event);
+        }
+        assert!(matches!(state.page, Page::Welcome));
+        assert_eq!(state.game_config.left.time.as_secs(), 330);
+        assert_eq!(state.game_config.right.time.as_secs(), 270);
+    }
+
+    #[test]
+    fn without_the_handicap_a_paused_game_has_no_result_page() {
+        let mut state = app_state();
+        state.page = Page::Game(GameState::new(&state.game_config, Player::Left));
+        let event = Event::ButtonPushed(Button::Left, PressType::Long);
+        state.handle_event(&mut Effects::new(), event);
+        assert!(matches!(state.page, Page::Game(_)));
+    }
+}
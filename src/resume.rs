@@ -0,0 +1,343 @@
+#[cfg(target_os = "none")]
+use embassy_stm32::{
+    interrupt,
+    interrupt::InterruptExt,
+    pac::{BKP, EXTI, PWR, RCC},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Duration;
+
+#[cfg(feature = "match")]
+use crate::match_play::MatchProgress;
+use crate::{
+    game::{GameState, Player},
+    menu::{GameConfig, IncrementType},
+    protocol::IncrementKind,
+    storage::crc32,
+};
+
+/// Signaled when the supply voltage drops below the PVD threshold
+pub static POWER_FAIL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+const MAGIC: u16 = 0xc10c;
+/// Number of 16 bit backup registers used, all ten of the medium density parts
+pub const WORDS: usize = 10;
+/// EXTI line of the power voltage detector
+#[cfg(target_os = "none")]
+const PVD_EXTI_LINE: usize = 16;
+/// PVD threshold of 2.9V
+#[cfg(target_os = "none")]
+const PVD_LEVEL: u8 = 7;
+
+/// State of a running game, enough to resume it after a reset or power loss
+#[derive(Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub turn: Player,
+    pub left_time: Duration,
+    pub right_time: Duration,
+    pub delay: Duration,
+    pub moves: u16,
+    /// Increments the game was played with, the menu may change them before it is resumed
+    pub left_increment: IncrementType,
+    pub right_increment: IncrementType,
+    /// Where the match stood, for a game of a match
+    #[cfg(feature = "match")]
+    pub match_progress: Option<MatchProgress>,
+}
+
+impl Snapshot {
+    pub fn of(game_state: &GameState, game_config: &GameConfig) -> Snapshot {
+        Snapshot {
+            turn: game_state.turn,
+            left_time: game_state.left_time,
+            right_time: game_state.right_time,
+            delay: game_state.delay,
+            moves: game_state.moves,
+            left_increment: game_config.left.increment_type,
+            right_increment: game_config.right.increment_type,
+            #[cfg(feature = "match")]
+            match_progress: None,
+        }
+    }
+
+    /// Puts the increments of the game back into the config it is resumed with
+    pub fn restore_increments(&self, game_config: &mut GameConfig) {
+        game_config.left.increment_type = self.left_increment;
+        game_config.right.increment_type = self.right_increment;
+    }
+
+    /// The resumed game starts paused
+    pub fn to_game_state(&self) -> GameState {
+        GameState {
+            turn: self.turn,
+            left_time: self.left_time,
+            right_time: self.right_time,
+            paused: true,
+            delay: self.delay,
+            moves: self.moves,
+        }
+    }
+
+    /// Magic, the times in milliseconds, the delay in milliseconds, the turn with the increment
+    /// types and the moves, the increments in seconds, the match and a checksum
+    pub fn to_words(&self) -> [u16; WORDS] {
+        let mut words = [0; WORDS];
+        words[0] = MAGIC;
+        for (i, duration) in [self.left_time, self.right_time].iter().enumerate() {
+            let millis = duration.as_millis() as u32;
+            words[1 + i * 2] = millis as u16;
+            words[2 + i * 2] = (millis >> 16) as u16;
+        }
+        // The delay is at most the 59 seconds the menu allows
+        words[5] = self.delay.as_millis().min(u16::MAX as u64) as u16;
+        let turn = player_bit(self.turn);
+        let (left_kind, left_secs) = increment_to_parts(self.left_increment);
+        let (right_kind, right_secs) = increment_to_parts(self.right_increment);
+        // The moves in the 11 bits left, past them the history gets the first player wrong
+        let moves = self.moves.min(0x7ff);
+        words[6] = turn | left_kind << 1 | right_kind << 3 | moves << 5;
+        words[7] = left_secs | right_secs << 8;
+        // The game in 4 bits, zero outside of a match, the player moving first and the points in
+        // 5 bits each. Only a string of drawn Armageddon games goes past them, and stays there.
+        #[cfg(feature = "match")]
+        if let Some(progress) = self.match_progress {
+            let game = progress.game.min(0xf) as u16;
+            let [one, other] = progress.points.map(|points| points.min(0x1f) as u16);
+            words[8] = game | player_bit(progress.first) << 4 | one << 5 | other << 10;
+        }
+        words[9] = checksum(&words[..9]);
+        words
+    }
+
+    pub fn from_words(words: &[u16; WORDS]) -> Option<Snapshot> {
+        if words[0] != MAGIC || words[9] != checksum(&words[..9]) {
+            return None;
+        }
+        let duration = |i: usize| {
+            let millis = words[1 + i * 2] as u64 | (words[2 + i * 2] as u64) << 16;
+            Duration::from_millis(millis)
+        };
+        #[cfg(feature = "match")]
+        let match_progress = match words[8] & 0xf {
+            0 => None,
+            game => Some(MatchProgress {
+                game: game as u8,
+                points: [(words[8] >> 5 & 0x1f) as u8, (words[8] >> 10 & 0x1f) as u8],
+                first: bit_player(words[8] >> 4),
+            }),
+        };
+        Some(Snapshot {
+            turn: bit_player(words[6]),
+            left_time: duration(0),
+            right_time: duration(1),
+            delay: Duration::from_millis(words[5] as u64),
+            moves: words[6] >> 5,
+            left_increment: increment_from_parts(words[6] >> 1 & 3, words[7] & 0xff),
+            right_increment: increment_from_parts(words[6] >> 3 & 3, words[7] >> 8),
+            #[cfg(feature = "match")]
+            match_progress,
+        })
+    }
+}
+
+fn player_bit(player: Player) -> u16 {
+    match player {
+        Player::Left => 0,
+        Player::Right => 1,
+    }
+}
+
+/// Player of the lowest bit
+fn bit_player(bits: u16) -> Player {
+    match bits & 1 {
+        0 => Player::Left,
+        _ => Player::Right,
+    }
+}
+
+/// Increment type as 2 bits and its seconds as a byte, the menu keeps them under a minute
+fn increment_to_parts(increment: IncrementType) -> (u16, u16) {
+    let secs = increment.duration().as_secs().min(0xff);
+    (increment.kind() as u16, secs as u16)
+}
+
+fn increment_from_parts(kind: u16, secs: u16) -> IncrementType {
+    let kinds = [
+        IncrementKind::SuddenDeath,
+        IncrementKind::Increment,
+        IncrementKind::Delay,
+        IncrementKind::Bronstein,
+    ];
+    IncrementType::new(kinds[kind as usize & 3], Duration::from_secs(secs as u64))
+}
+
+fn checksum(words: &[u16]) -> u16 {
+    let crc = words
+        .iter()
+        .fold(0, |crc, word| crc32(&word.to_le_bytes(), crc));
+    crc as u16
+}
+
+/// Backup domain registers, which keep their content through a reset
+#[cfg(target_os = "none")]
+pub struct BackupRegisters(());
+
+#[cfg(target_os = "none")]
+impl BackupRegisters {
+    pub fn new() -> Self {
+        RCC.apb1enr().modify(|w| {
+            w.set_pwren(true);
+            w.set_bkpen(true);
+        });
+        PWR.cr().modify(|w| w.set_dbp(true));
+        BackupRegisters(())
+    }
+
+    pub fn read(&self) -> Option<Snapshot> {
+        let mut words = [0; WORDS];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = BKP.dr(i).read().d();
+        }
+        Snapshot::from_words(&words)
+    }
+
+    pub fn write(&mut self, snapshot: Option<&Snapshot>) {
+        let words = snapshot.map(|s| s.to_words()).unwrap_or([0; WORDS]);
+        for (i, word) in words.iter().enumerate() {
+            BKP.dr(i).write(|w| w.set_d(*word));
+        }
+    }
+}
+
+/// Enables the power voltage detector interrupt, which signals [`POWER_FAIL`]
+#[cfg(target_os = "none")]
+pub fn enable_power_fail_detection() {
+    PWR.cr().modify(|w| {
+        w.set_pls(PVD_LEVEL);
+        w.set_pvde(true);
+    });
+    // PVDO goes high when the voltage falls below the threshold
+    EXTI.rtsr(0).modify(|w| w.set_line(PVD_EXTI_LINE, true));
+    EXTI.imr(0).modify(|w| w.set_line(PVD_EXTI_LINE, true));
+
+    interrupt::PVD.unpend();
+    // Safety: the handler only signals POWER_FAIL
+    unsafe { interrupt::PVD.enable() };
+}
+
+/// Whether the supply is still below the PVD threshold
+#[cfg(target_os = "none")]
+pub fn power_failing() -> bool {
+    PWR.csr().read().pvdo()
+}
+
+#[cfg(target_os = "none")]
+#[interrupt]
+fn PVD() {
+    EXTI.pr(0).write(|w| w.set_line(PVD_EXTI_LINE, true));
+    if power_failing() {
+        POWER_FAIL.signal(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app::{Button, Event, Page, PressType},
+        effect::Effects,
+        testing::app_state,
+    };
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            turn: Player::Right,
+            left_time: Duration::from_millis(5_999_123),
+            right_time: Duration::from_millis(42),
+            delay: Duration::from_millis(59_000),
+            moves: 2047,
+            left_increment: IncrementType::Delay(Duration::from_secs(59)),
+            right_increment: IncrementType::Increment(Duration::from_secs(3)),
+            #[cfg(feature = "match")]
+            match_progress: None,
+        }
+    }
+
+    #[test]
+    fn snapshots_come_back_from_the_words() {
+        let words = snapshot().to_words();
+        assert!(Snapshot::from_words(&words) == Some(snapshot()));
+
+        let sudden_death = Snapshot {
+            turn: Player::Left,
+            left_increment: IncrementType::SuddenDeath,
+            right_increment: IncrementType::Bronstein(Duration::from_secs(15)),
+            moves: 0,
+            ..snapshot()
+        };
+        let words = sudden_death.to_words();
+        assert!(Snapshot::from_words(&words) == Some(sudden_death));
+    }
+
+    #[test]
+    fn damaged_words_are_rejected() {
+        assert!(Snapshot::from_words(&[0; WORDS]).is_none());
+        for i in 0..WORDS {
+            let mut words = snapshot().to_words();
+            words[i] ^= 0x10;
+            assert!(Snapshot::from_words(&words).is_none(), "word {}", i);
+        }
+    }
+
+    #[test]
+    fn resuming_restores_the_increments_of_the_game() {
+        let mut state = app_state();
+        state.resumable = Some(snapshot());
+        let mut effects = Effects::new();
+        state.handle_event(
+            &mut effects,
+            Event::ButtonPushed(Button::Control, PressType::Single),
+        );
+
+        let Page::Game(ref game_state) = state.page else {
+            panic!("The game wasn't resumed");
+        };
+        assert!(game_state.turn == Player::Right);
+        assert_eq!(game_state.left_time, Duration::from_millis(5_999_123));
+        assert!(game_state.paused);
+        assert!(state.game_config.left.increment_type == snapshot().left_increment);
+        assert!(state.game_config.right.increment_type == snapshot().right_increment);
+        assert!(state.resumable.is_none());
+    }
+
+    #[cfg(feature = "match")]
+    #[test]
+    fn resuming_a_game_of_a_match_carries_the_match_on() {
+        use crate::{match_play::MatchProgress, settings::MAX_MATCH_GAMES};
+
+        let progress = MatchProgress {
+            game: MAX_MATCH_GAMES + 1,
+            points: [9, 11],
+            first: Player::Right,
+        };
+        let in_match = Snapshot {
+            match_progress: Some(progress),
+            ..snapshot()
+        };
+        let words = in_match.to_words();
+        assert!(Snapshot::from_words(&words) == Some(in_match.clone()));
+
+        let mut state = app_state();
+        let menu_config = state.game_config.clone();
+        state.resumable = Some(in_match);
+        state.handle_event(
+            &mut Effects::new(),
+            Event::ButtonPushed(Button::Control, PressType::Single),
+        );
+        assert!(matches!(state.page, Page::Game(_)));
+        let match_state = state.match_state.as_ref().unwrap();
+        assert!(match_state.progress() == progress && !match_state.over);
+        assert!(match_state.config.left.increment_type == snapshot().left_increment);
+        assert!(state.game_config == menu_config);
+    }
+}
@@ -0,0 +1,46 @@
+//! RS-485 transceiver on a serial port, driving the bus only while sending.
+
+use embassy_stm32::gpio::Output;
+use embedded_io_async::{ErrorType, Read, Write};
+
+/// Serial port behind a half duplex transceiver whose driver enable follows the sending.
+///
+/// The receiver enable is wired to the same pin, so the clock doesn't hear itself.
+pub struct Transceiver<'d, P> {
+    port: P,
+    driver_enable: Output<'d>,
+}
+
+impl<'d, P> Transceiver<'d, P> {
+    pub fn new(port: P, mut driver_enable: Output<'d>) -> Self {
+        driver_enable.set_low();
+        Transceiver {
+            port,
+            driver_enable,
+        }
+    }
+}
+
+impl<P: ErrorType> ErrorType for Transceiver<'_, P> {
+    type Error = P::Error;
+}
+
+impl<P: Read> Read for Transceiver<'_, P> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.port.read(buf).await
+    }
+}
+
+impl<P: Write> Write for Transceiver<'_, P> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.driver_enable.set_high();
+        self.port.write(buf).await
+    }
+
+    /// Waits until the last byte left the port, then releases the bus
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        let result = self.port.flush().await;
+        self.driver_enable.set_low();
+        result
+    }
+}
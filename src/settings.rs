@@ -0,0 +1,81 @@
+use embassy_time::Duration;
+
+use crate::{app::Button, keyboard::KeyboardMode};
+
+/// Highest rating of the time odds calculator
+pub const MAX_RATING: u16 = 3000;
+/// Most games in a match
+pub const MAX_MATCH_GAMES: u8 = 9;
+
+/// Device settings, independent of the game being played
+#[derive(Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// I2C address of the LCD backpack, detected at boot
+    pub lcd_address: u8,
+    /// Inactivity before going to sleep, zero never sleeps
+    pub idle_timeout: Duration,
+    /// Inactivity before going to sleep while a game is paused, zero never sleeps
+    pub paused_timeout: Duration,
+    /// Whether a game in progress may go to sleep at all
+    pub sleep_in_game: bool,
+    /// What the player buttons type on the USB keyboard
+    pub keyboard: KeyboardMode,
+    /// Keys typed by the left and right buttons, indices into `KEYS`
+    pub left_key: u8,
+    pub right_key: u8,
+    /// Address of the clock on a shared RS-485 bus, from 1 to `MAX_BUS_ADDRESS`
+    pub bus_address: u8,
+    /// Time moved from the winner to the loser after each game, zero keeps the times as set
+    pub handicap_step: Duration,
+    /// Bounds of the times the adaptive handicap moves
+    pub handicap_min: Duration,
+    pub handicap_max: Duration,
+    /// Ratings of the left and right players for the time odds calculator
+    pub left_rating: u16,
+    pub right_rating: u16,
+    /// Time of the weaker player in the time odds calculator
+    pub odds_base: Duration,
+    /// Games in a match, zero plays single games
+    pub match_games: u8,
+    /// Whether a tied match goes on to Armageddon games
+    pub match_tiebreak: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            lcd_address: 0x27,
+            idle_timeout: Duration::from_secs(20),
+            paused_timeout: Duration::from_secs(10 * 60),
+            sleep_in_game: true,
+            keyboard: KeyboardMode::Off,
+            left_key: 0,
+            right_key: 0,
+            bus_address: 1,
+            handicap_step: Duration::from_ticks(0),
+            handicap_min: Duration::from_secs(15),
+            handicap_max: Duration::from_secs(10 * 60),
+            left_rating: 1500,
+            right_rating: 1500,
+            odds_base: Duration::from_secs(10 * 60),
+            match_games: 0,
+            match_tiebreak: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Key typed by the player button, `None` for the control button
+    pub fn key(&self, button: Button) -> Option<u8> {
+        match button {
+            Button::Left => Some(self.left_key),
+            Button::Right => Some(self.right_key),
+            Button::Control => None,
+        }
+    }
+}
+
+/// Returns the time until sleep, `None` when zero is configured
+pub fn timeout(duration: Duration) -> Option<Duration> {
+    (duration.as_ticks() != 0).then_some(duration)
+}
@@ -0,0 +1,923 @@
+use embassy_time::Duration;
+use embedded_storage::nor_flash::NorFlash;
+use heapless::Vec;
+
+#[cfg(feature = "table")]
+use crate::table::{TableSnapshot, TableState, MIN_PLAYERS};
+use crate::{
+    error::Error,
+    keyboard::KeyboardMode,
+    menu::{GameConfig, IncrementType, TimeControl, MAX_PLAYERS},
+    presets::{UserPreset, UserPresets},
+    protocol::MAX_BUS_ADDRESS,
+    resume::{Snapshot, WORDS},
+    settings::{Settings, MAX_MATCH_GAMES, MAX_RATING},
+};
+#[cfg(feature = "history")]
+use crate::{
+    game::Player,
+    history::{GameLog, History},
+};
+
+/// Flash page size, the unit of erasing
+pub const PAGE_SIZE: u32 = 1024;
+/// Largest payload a record can hold
+pub const MAX_PAYLOAD: usize = 240;
+
+const MAGIC: u8 = 0xc5;
+const ERASED: u8 = 0xff;
+/// Magic, key, version, payload length and sequence number
+const HEADER_SIZE: usize = 8;
+const CRC_SIZE: usize = 4;
+const MAX_RECORD: usize = HEADER_SIZE + MAX_PAYLOAD + CRC_SIZE;
+const MAX_RECORDS_PER_PAGE: usize = PAGE_SIZE as usize / (HEADER_SIZE + CRC_SIZE) + 1;
+/// Empty record closing the copy to a new page, a page without it was left half copied
+const COMMIT_KEY: u8 = 0;
+
+/// A value that can be stored in flash, identified by its key.
+///
+/// The version is stored with the record, records written with another version are ignored.
+pub trait Persist: Sized {
+    const KEY: u8;
+    const VERSION: u8;
+
+    fn encode(&self, buf: &mut Vec<u8, MAX_PAYLOAD>);
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+#[derive(Clone, Copy)]
+struct Header {
+    key: u8,
+    version: u8,
+    len: u8,
+    seq: u32,
+}
+
+impl Header {
+    fn record_size(&self) -> u32 {
+        record_size(self.len as usize)
+    }
+}
+
+/// Location of a valid record
+#[derive(Clone, Copy)]
+struct RecordRef {
+    header: Header,
+    page: u32,
+    offset: u32,
+}
+
+/// Key-value store with versioned, CRC checked records in a reserved area of the flash.
+///
+/// Records are appended to the current page and the latest record of a key wins. When the page
+/// is full, the latest records are moved to the next page, so erases rotate over all pages of the
+/// area. The move ends with a commit record, until then the previous page stays current: a power
+/// failure in the middle loses the record being written, never the others.
+pub struct Storage<F> {
+    flash: F,
+    /// Offset of the reserved area, from the start of the flash
+    offset: u32,
+    pages: u32,
+    current_page: u32,
+    /// Offset of the first free byte in the current page
+    write_offset: u32,
+    next_seq: u32,
+    /// Bit for each page holding a commit record, the other pages are ignored
+    committed: u32,
+}
+
+impl<F> Storage<F>
+where
+    F: NorFlash,
+    Error: From<F::Error>,
+{
+    /// Finds the page with the latest records in the area of `pages` flash pages at `offset`.
+    pub fn new(flash: F, offset: u32, pages: u32) -> Result<Self, Error> {
+        let mut storage = Self {
+            flash,
+            offset,
+            pages,
+            current_page: 0,
+            write_offset: 0,
+            next_seq: 0,
+            committed: 0,
+        };
+
+        // The latest committed page is current. Without any, the area is fresh or was written
+        // before the commit records, and the latest page is taken as it is.
+        let mut latest_seq = None;
+        let mut current: Option<(bool, u32)> = None;
+        for page in 0..pages {
+            let (_, records) = storage.scan_page(page)?;
+            let committed = records.iter().any(|r| r.header.key == COMMIT_KEY);
+            if committed {
+                storage.committed |= 1 << page;
+            }
+            for record in records.iter() {
+                let seq = record.header.seq;
+                if latest_seq.is_none_or(|latest| seq >= latest) {
+                    latest_seq = Some(seq);
+                }
+                if current.is_none_or(|latest| (committed, seq) >= latest) {
+                    current = Some((committed, seq));
+                    storage.current_page = page;
+                }
+            }
+        }
+        storage.next_seq = latest_seq.map(|seq| seq + 1).unwrap_or(0);
+        storage.write_offset = storage.scan_page(storage.current_page)?.0;
+        if !storage.is_committed(storage.current_page) {
+            storage.write(COMMIT_KEY, 0, &[])?;
+            storage.committed |= 1 << storage.current_page;
+        }
+        Ok(storage)
+    }
+
+    /// Reads the latest stored value, returns `None` if there is none or it can't be decoded.
+    pub fn load<T: Persist>(&mut self) -> Result<Option<T>, Error> {
+        let Some(record) = self.find_latest(T::KEY)? else {
+            return Ok(None);
+        };
+        if record.header.version != T::VERSION {
+            return Ok(None);
+        }
+        let mut buf = [0; MAX_RECORD];
+        let payload = self.read_payload(&record, &mut buf)?;
+        Ok(T::decode(payload))
+    }
+
+    pub fn save<T: Persist>(&mut self, value: &T) -> Result<(), Error> {
+        let mut payload = Vec::new();
+        value.encode(&mut payload);
+        self.write(T::KEY, T::VERSION, &payload)
+    }
+
+    /// Moves to the next page now if `value` wouldn't fit in the current one, so saving it later
+    /// only writes and never erases.
+    pub fn reserve<T: Persist>(&mut self, value: &T) -> Result<(), Error> {
+        let mut payload = Vec::new();
+        value.encode(&mut payload);
+        if self.write_offset + record_size(payload.len()) > PAGE_SIZE {
+            self.move_to_next_page((COMMIT_KEY, 0, &[]))?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, key: u8, version: u8, payload: &[u8]) -> Result<(), Error> {
+        if self.write_offset + record_size(payload.len()) > PAGE_SIZE {
+            return self.move_to_next_page((key, version, payload));
+        }
+        self.append(key, version, payload)
+    }
+
+    /// Writes a record at the end of the current page, which must have room for it
+    fn append(&mut self, key: u8, version: u8, payload: &[u8]) -> Result<(), Error> {
+        let header = Header {
+            key,
+            version,
+            len: payload.len() as u8,
+            seq: self.next_seq,
+        };
+
+        let mut record: Vec<u8, MAX_RECORD> = Vec::new();
+        let _ = record.extend_from_slice(&[header.key, header.version, header.len, MAGIC]);
+        let _ = record.extend_from_slice(&header.seq.to_le_bytes());
+        let _ = record.extend_from_slice(payload);
+        let _ = record.extend_from_slice(&crc32(&record, 0).to_le_bytes());
+        let _ = record.resize(header.record_size() as usize, ERASED);
+
+        // The magic byte goes last, so a torn write is never taken as a complete record
+        let address = self.page_offset(self.current_page) + self.write_offset;
+        self.flash.write(address + 4, &record[4..])?;
+        self.flash.write(address, &record[..4])?;
+
+        self.write_offset += header.record_size();
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// Erases the next page, copies the latest record of every other key to it followed by the
+    /// `pending` record, and commits it.
+    fn move_to_next_page(&mut self, pending: (u8, u8, &[u8])) -> Result<(), Error> {
+        let (pending_key, pending_version, pending_payload) = pending;
+        let (_, records) = self.scan_page(self.current_page)?;
+
+        let page = (self.current_page + 1) % self.pages;
+        let start = self.page_offset(page);
+        self.committed &= !(1 << page);
+        self.flash.erase(start, start + PAGE_SIZE)?;
+        let prev_page = core::mem::replace(&mut self.current_page, page);
+        let prev_write_offset = core::mem::replace(&mut self.write_offset, 0);
+
+        let result = self.copy_records(&records, pending_key).and_then(|_| {
+            if pending_key != COMMIT_KEY {
+                self.append(pending_key, pending_version, pending_payload)?;
+            }
+            self.append(COMMIT_KEY, 0, &[])
+        });
+        match result {
+            Ok(()) => self.committed |= 1 << page,
+            // Carry on with the previous page, the next write erases this one again
+            Err(_) => {
+                self.current_page = prev_page;
+                self.write_offset = prev_write_offset;
+            }
+        }
+        result
+    }
+
+    /// Appends the latest of `records` of every key but `skip_key` and the commit records
+    fn copy_records(&mut self, records: &[RecordRef], skip_key: u8) -> Result<(), Error> {
+        for record in records.iter() {
+            let key = record.header.key;
+            let is_latest = records
+                .iter()
+                .all(|other| other.header.key != key || other.header.seq <= record.header.seq);
+            if key != skip_key && key != COMMIT_KEY && is_latest {
+                let mut buf = [0; MAX_RECORD];
+                let payload = self.read_payload(record, &mut buf)?;
+                let mut copy: Vec<u8, MAX_PAYLOAD> = Vec::new();
+                let _ = copy.extend_from_slice(payload);
+                self.append(key, record.header.version, &copy)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn find_latest(&mut self, key: u8) -> Result<Option<RecordRef>, Error> {
+        let mut latest: Option<RecordRef> = None;
+        let committed = self.committed;
+        for page in (0..self.pages).filter(|page| committed & (1 << page) != 0) {
+            let (_, records) = self.scan_page(page)?;
+            for record in records.into_iter().filter(|r| r.header.key == key) {
+                if latest.is_none_or(|latest| record.header.seq > latest.header.seq) {
+                    latest = Some(record);
+                }
+            }
+        }
+        Ok(latest)
+    }
+
+    /// Returns the end of the written area and the valid records of a page.
+    ///
+    /// A damaged record ends the written area, the rest of the page is not used until it is
+    /// erased.
+    fn scan_page(
+        &mut self,
+        page: u32,
+    ) -> Result<(u32, Vec<RecordRef, MAX_RECORDS_PER_PAGE>), Error> {
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset + (HEADER_SIZE as u32) <= PAGE_SIZE {
+            let address = self.page_offset(page) + offset;
+            let mut bytes = [0; HEADER_SIZE];
+            self.flash.read(address, &mut bytes)?;
+            if bytes.iter().all(|byte| *byte == ERASED) {
+                break;
+            }
+            if bytes[3] != MAGIC || bytes[2] as usize > MAX_PAYLOAD {
+                return Ok((PAGE_SIZE, records));
+            }
+            let header = Header {
+                key: bytes[0],
+                version: bytes[1],
+                len: bytes[2],
+                seq: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            };
+            if offset + header.record_size() > PAGE_SIZE {
+                return Ok((PAGE_SIZE, records));
+            }
+
+            let record = RecordRef {
+                header,
+                page,
+                offset,
+            };
+            let mut buf = [0; MAX_RECORD];
+            if self.read_payload(&record, &mut buf).is_ok() {
+                let _ = records.push(record);
+            }
+            offset += header.record_size();
+        }
+        Ok((offset, records))
+    }
+
+    /// Reads the payload of a record, checking its CRC
+    fn read_payload<'a>(
+        &mut self,
+        record: &RecordRef,
+        buf: &'a mut [u8; MAX_RECORD],
+    ) -> Result<&'a [u8], Error> {
+        let len = HEADER_SIZE + record.header.len as usize;
+        let address = self.page_offset(record.page) + record.offset;
+        self.flash.read(address, &mut buf[..len + CRC_SIZE])?;
+
+        let stored_crc = u32::from_le_bytes([buf[len], buf[len + 1], buf[len + 2], buf[len + 3]]);
+        if crc32(&buf[..len], 0) != stored_crc {
+            return Err(Error::CorruptRecord);
+        }
+        Ok(&buf[HEADER_SIZE..len])
+    }
+
+    fn is_committed(&self, page: u32) -> bool {
+        self.committed & (1 << page) != 0
+    }
+
+    fn page_offset(&self, page: u32) -> u32 {
+        self.offset + page * PAGE_SIZE
+    }
+}
+
+fn record_size(len: usize) -> u32 {
+    align(HEADER_SIZE + len + CRC_SIZE) as u32
+}
+
+fn align(size: usize) -> usize {
+    size.div_ceil(4) * 4
+}
+
+/// CRC-32 (IEEE), pass the previous result as `crc` to continue a checksum
+pub fn crc32(data: &[u8], crc: u32) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+impl Persist for Settings {
+    const KEY: u8 = 1;
+    const VERSION: u8 = 8;
+
+    fn encode(&self, buf: &mut Vec<u8, MAX_PAYLOAD>) {
+        let _ = buf.push(self.lcd_address);
+        push_duration(buf, self.idle_timeout);
+        push_duration(buf, self.paused_timeout);
+        let _ = buf.push(self.sleep_in_game as u8);
+        let _ = buf.push(self.keyboard as u8);
+        let _ = buf.push(self.left_key);
+        let _ = buf.push(self.right_key);
+        let _ = buf.push(self.bus_address);
+        push_duration(buf, self.handicap_step);
+        push_duration(buf, self.handicap_min);
+        push_duration(buf, self.handicap_max);
+        let _ = buf.extend_from_slice(&self.left_rating.to_le_bytes());
+        let _ = buf.extend_from_slice(&self.right_rating.to_le_bytes());
+        push_duration(buf, self.odds_base);
+        let _ = buf.push(self.match_games);
+        let _ = buf.push(self.match_tiebreak as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        Some(Settings {
+            lcd_address: reader.byte()?,
+            idle_timeout: reader.duration()?,
+            paused_timeout: reader.duration()?,
+            sleep_in_game: reader.byte()? != 0,
+            keyboard: *KeyboardMode::ALL.get(reader.byte()? as usize)?,
+            left_key: reader.byte()?,
+            right_key: reader.byte()?,
+            bus_address: Some(reader.byte()?)
+                .filter(|address| (1..=MAX_BUS_ADDRESS).contains(address))?,
+            handicap_step: reader.duration()?,
+            handicap_min: reader.duration()?,
+            handicap_max: reader.duration()?,
+            // Held to the range of the menu, which only has room for four digits
+            left_rating: reader.word()?.min(MAX_RATING),
+            right_rating: reader.word()?.min(MAX_RATING),
+            odds_base: reader.duration()?,
+            // Zero plays single games, so only the top is held to the menu
+            match_games: reader.byte()?.min(MAX_MATCH_GAMES),
+            match_tiebreak: reader.byte()? != 0,
+        })
+    }
+}
+
+impl Persist for GameConfig {
+    const KEY: u8 = 2;
+    const VERSION: u8 = 3;
+
+    fn encode(&self, buf: &mut Vec<u8, MAX_PAYLOAD>) {
+        push_time_control(buf, &self.left);
+        push_time_control(buf, &self.right);
+        let _ = buf.push(self.players);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Reader(bytes).game_config()
+    }
+}
+
+impl Persist for UserPresets {
+    const KEY: u8 = 3;
+    const VERSION: u8 = 3;
+
+    fn encode(&self, buf: &mut Vec<u8, MAX_PAYLOAD>) {
+        let _ = buf.push(self.len() as u8);
+        for preset in self {
+            let _ = buf.push(preset.name.len() as u8);
+            let _ = buf.extend_from_slice(preset.name.as_bytes());
+            preset.config.encode(buf);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let mut presets = UserPresets::new();
+        for _ in 0..reader.byte()? {
+            let len = reader.byte()? as usize;
+            let name = core::str::from_utf8(reader.bytes(len)?).ok()?;
+            let preset = UserPreset {
+                name: name.try_into().ok()?,
+                config: reader.game_config()?,
+            };
+            presets.push(preset).ok()?;
+        }
+        Some(presets)
+    }
+}
+
+/// Game saved on a power failure, `None` once it has been resumed or discarded
+impl Persist for Option<Snapshot> {
+    const KEY: u8 = 4;
+    const VERSION: u8 = 3;
+
+    fn encode(&self, buf: &mut Vec<u8, MAX_PAYLOAD>) {
+        if let Some(snapshot) = self {
+            for word in snapshot.to_words() {
+                let _ = buf.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() {
+            return Some(None);
+        }
+        if bytes.len() != WORDS * 2 {
+            return None;
+        }
+        let mut words = [0; WORDS];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Snapshot::from_words(&words).map(Some)
+    }
+}
+
+/// Table game saved on a power failure, `None` once it has been resumed or discarded
+#[cfg(feature = "table")]
+impl Persist for Option<TableSnapshot> {
+    const KEY: u8 = 5;
+    const VERSION: u8 = 1;
+
+    fn encode(&self, buf: &mut Vec<u8, MAX_PAYLOAD>) {
+        if let Some(snapshot) = self {
+            let table = &snapshot.table;
+            let _ = buf.push(table.players);
+            let _ = buf.push(table.turn);
+            push_time_control(buf, &snapshot.control);
+            push_duration(buf, table.delay);
+            for time in &table.times[..table.players as usize] {
+                push_duration(buf, *time);
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.is_empty() {
+            return Some(None);
+        }
+        let mut reader = Reader(bytes);
+        let players = reader.byte()?;
+        let turn = reader.byte()?;
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players) || turn >= players {
+            return None;
+        }
+        let control = reader.time_control()?;
+        let delay = reader.duration()?;
+        // The seats left empty keep the time of the control, like in a new game
+        let mut times = [control.time; MAX_PLAYERS as usize];
+        for time in &mut times[..players as usize] {
+            *time = reader.duration()?;
+        }
+        let table = TableState {
+            players,
+            turn,
+            times,
+            paused: true,
+            delay,
+            flagged: None,
+        };
+        Some(Some(TableSnapshot { table, control }))
+    }
+}
+
+/// Finished games, each with its players in a byte: the first one in the lowest bit, then none for a
+/// draw or the winner plus one
+#[cfg(feature = "history")]
+impl Persist for History {
+    const KEY: u8 = 6;
+    const VERSION: u8 = 1;
+
+    fn encode(&self, buf: &mut Vec<u8, MAX_PAYLOAD>) {
+        let bits = |player| match player {
+            Player::Left => 0,
+            Player::Right => 1,
+        };
+        for game_log in self {
+            let winner = game_log.winner.map_or(0, |winner| bits(winner) + 1);
+            let _ = buf.push(bits(game_log.first) | winner << 1);
+            let _ = buf.extend_from_slice(&game_log.moves.to_le_bytes());
+            push_duration(buf, game_log.left_time);
+            push_duration(buf, game_log.right_time);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let player = |bit| match bit {
+            0 => Player::Left,
+            _ => Player::Right,
+        };
+        let mut reader = Reader(bytes);
+        let mut history = History::new();
+        while let Some(players) = reader.byte() {
+            let game_log = GameLog {
+                first: player(players & 1),
+                winner: match players >> 1 {
+                    0 => None,
+                    winner => Some(player(winner - 1)),
+                },
+                moves: reader.word()?,
+                left_time: reader.duration()?,
+                right_time: reader.duration()?,
+            };
+            history.push(game_log).ok()?;
+        }
+        Some(history)
+    }
+}
+
+fn push_time_control(buf: &mut Vec<u8, MAX_PAYLOAD>, control: &TimeControl) {
+    let kind = match control.increment_type {
+        IncrementType::SuddenDeath => 0,
+        IncrementType::Increment(_) => 1,
+        IncrementType::Delay(_) => 2,
+        IncrementType::Bronstein(_) => 3,
+    };
+    push_duration(buf, control.time);
+    let _ = buf.push(kind);
+    push_duration(buf, control.increment_type.duration());
+}
+
+/// Durations are stored as little endian milliseconds
+fn push_duration(buf: &mut Vec<u8, MAX_PAYLOAD>, duration: Duration) {
+    let _ = buf.extend_from_slice(&(duration.as_millis() as u32).to_le_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let (byte, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(*byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let (bytes, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn word(&mut self) -> Option<u16> {
+        let (bytes, rest) = self.0.split_first_chunk::<2>()?;
+        self.0 = rest;
+        Some(u16::from_le_bytes(*bytes))
+    }
+
+    fn duration(&mut self) -> Option<Duration> {
+        let (bytes, rest) = self.0.split_first_chunk::<4>()?;
+        self.0 = rest;
+        Some(Duration::from_millis(u32::from_le_bytes(*bytes) as u64))
+    }
+
+    fn time_control(&mut self) -> Option<TimeControl> {
+        let time = self.duration()?;
+        let kind = self.byte()?;
+        let duration = self.duration()?;
+        let increment_type = match kind {
+            0 => IncrementType::SuddenDeath,
+            1 => IncrementType::Increment(duration),
+            2 => IncrementType::Delay(duration),
+            3 => IncrementType::Bronstein(duration),
+            _ => return None,
+        };
+        Some(TimeControl {
+            time,
+            increment_type,
+        })
+    }
+
+    fn game_config(&mut self) -> Option<GameConfig> {
+        let left = self.time_control()?;
+        let right = self.time_control()?;
+        let players = self.byte()?;
+        // Two for a chess game, more for the table
+        if !(2..=MAX_PLAYERS).contains(&players) {
+            return None;
+        }
+        Some(GameConfig {
+            left,
+            right,
+            players,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeFlash;
+
+    const PAGES: u32 = 2;
+
+    /// Value of 32 bytes, so a few dozen fill a page
+    #[derive(Debug, PartialEq)]
+    struct Value<const KEY: u8>(u32);
+
+    impl<const KEY: u8> Persist for Value<KEY> {
+        const KEY: u8 = KEY;
+        const VERSION: u8 = 1;
+
+        fn encode(&self, buf: &mut Vec<u8, MAX_PAYLOAD>) {
+            let _ = buf.extend_from_slice(&self.0.to_le_bytes());
+            let _ = buf.resize(32, 0);
+        }
+
+        fn decode(bytes: &[u8]) -> Option<Self> {
+            let (value, _) = bytes.split_first_chunk::<4>()?;
+            Some(Value(u32::from_le_bytes(*value)))
+        }
+    }
+
+    const VALUE_SIZE: u32 = (HEADER_SIZE + 32 + CRC_SIZE) as u32;
+
+    fn open(flash: &FakeFlash) -> Storage<FakeFlash> {
+        Storage::new(flash.clone(), 0, PAGES).unwrap()
+    }
+
+    fn load<const KEY: u8>(storage: &mut Storage<FakeFlash>) -> Option<u32> {
+        storage.load::<Value<KEY>>().unwrap().map(|value| value.0)
+    }
+
+    /// Saves the first value until the page is full, the next save moves to the other page
+    fn fill_page(storage: &mut Storage<FakeFlash>) -> u32 {
+        let mut value = 0;
+        while storage.write_offset + VALUE_SIZE <= PAGE_SIZE {
+            value += 1;
+            storage.save(&Value::<1>(value)).unwrap();
+        }
+        value
+    }
+
+    #[test]
+    fn values_are_read_back_after_a_restart() {
+        let flash = FakeFlash::new(PAGES);
+        let mut storage = open(&flash);
+        assert_eq!(load::<1>(&mut storage), None);
+        storage.save(&Value::<1>(7)).unwrap();
+        storage.save(&Value::<2>(8)).unwrap();
+        storage.save(&Value::<1>(9)).unwrap();
+
+        let mut storage = open(&flash);
+        assert_eq!(load::<1>(&mut storage), Some(9));
+        assert_eq!(load::<2>(&mut storage), Some(8));
+        assert_eq!(load::<3>(&mut storage), None);
+    }
+
+    #[test]
+    fn records_of_another_version_are_ignored() {
+        let flash = FakeFlash::new(PAGES);
+        let mut storage = open(&flash);
+        storage.write(1, 2, &[0; 32]).unwrap();
+        assert_eq!(load::<1>(&mut storage), None);
+    }
+
+    #[test]
+    fn moving_pages_keeps_every_key() {
+        let flash = FakeFlash::new(PAGES);
+        let mut storage = open(&flash);
+        storage.save(&Value::<2>(20)).unwrap();
+        storage.save(&Value::<3>(30)).unwrap();
+        for value in 0..200 {
+            storage.save(&Value::<1>(value)).unwrap();
+        }
+
+        let mut storage = open(&flash);
+        assert_eq!(load::<1>(&mut storage), Some(199));
+        assert_eq!(load::<2>(&mut storage), Some(20));
+        assert_eq!(load::<3>(&mut storage), Some(30));
+    }
+
+    #[test]
+    fn a_torn_record_leaves_the_previous_value() {
+        // The payload and the magic byte are written separately
+        for operations in 0..2 {
+            let flash = FakeFlash::new(PAGES);
+            let mut storage = open(&flash);
+            storage.save(&Value::<1>(1)).unwrap();
+            flash.fail_after(operations);
+            assert!(storage.save(&Value::<1>(2)).is_err());
+            flash.restore();
+
+            let mut storage = open(&flash);
+            assert_eq!(load::<1>(&mut storage), Some(1));
+            storage.save(&Value::<1>(3)).unwrap();
+            assert_eq!(load::<1>(&mut open(&flash)), Some(3));
+        }
+    }
+
+    #[test]
+    fn a_corrupt_record_is_skipped() {
+        let flash = FakeFlash::new(PAGES);
+        let mut storage = open(&flash);
+        storage.save(&Value::<1>(1)).unwrap();
+        storage.save(&Value::<1>(2)).unwrap();
+        flash.corrupt(storage.write_offset - VALUE_SIZE + HEADER_SIZE as u32);
+
+        let mut storage = open(&flash);
+        assert_eq!(load::<1>(&mut storage), Some(1));
+    }
+
+    #[test]
+    fn a_torn_page_move_keeps_the_previous_page() {
+        // The erase, two copies, the new record and the commit record, two writes to a record
+        const OPERATIONS: usize = 1 + 2 * 2 + 2 + 2;
+        for operations in 0..=OPERATIONS {
+            let flash = FakeFlash::new(PAGES);
+            let mut storage = open(&flash);
+            storage.save(&Value::<2>(20)).unwrap();
+            storage.save(&Value::<3>(30)).unwrap();
+            let last = fill_page(&mut storage);
+            flash.fail_after(operations);
+            let saved = storage.save(&Value::<1>(100)).is_ok();
+            assert_eq!(saved, operations == OPERATIONS);
+            flash.restore();
+
+            let mut storage = open(&flash);
+            let expected = if saved { 100 } else { last };
+            assert_eq!(load::<1>(&mut storage), Some(expected));
+            assert_eq!(load::<2>(&mut storage), Some(20));
+            assert_eq!(load::<3>(&mut storage), Some(30));
+
+            // The next moves start from the right page
+            for value in 0..100 {
+                storage.save(&Value::<1>(value)).unwrap();
+            }
+            let mut storage = open(&flash);
+            assert_eq!(load::<2>(&mut storage), Some(20));
+            assert_eq!(load::<3>(&mut storage), Some(30));
+        }
+    }
+
+    #[test]
+    fn saving_into_reserved_room_only_writes() {
+        let flash = FakeFlash::new(PAGES);
+        let mut storage = open(&flash);
+        fill_page(&mut storage);
+        storage.reserve(&Value::<2>(0)).unwrap();
+        // The record and its magic byte, no erase
+        flash.fail_after(2);
+        storage.save(&Value::<2>(5)).unwrap();
+        flash.restore();
+        assert_eq!(load::<2>(&mut open(&flash)), Some(5));
+    }
+
+    #[test]
+    fn pages_written_before_the_commit_records_are_taken_over() {
+        let flash = FakeFlash::new(PAGES);
+        let mut storage = Storage {
+            flash: flash.clone(),
+            offset: 0,
+            pages: PAGES,
+            current_page: 0,
+            write_offset: 0,
+            next_seq: 0,
+            committed: 0,
+        };
+        let mut payload = Vec::new();
+        Value::<1>(1).encode(&mut payload);
+        storage.append(1, 1, &payload).unwrap();
+
+        let mut storage = open(&flash);
+        assert_eq!(load::<1>(&mut storage), Some(1));
+        storage.save(&Value::<1>(2)).unwrap();
+        assert_eq!(load::<1>(&mut open(&flash)), Some(2));
+    }
+
+    #[test]
+    fn ratings_are_held_to_the_range_of_the_menu() {
+        let settings = Settings {
+            left_rating: 4000,
+            right_rating: 1200,
+            ..Settings::default()
+        };
+        let mut buf = Vec::new();
+        settings.encode(&mut buf);
+        let settings = Settings::decode(&buf).unwrap();
+        assert_eq!(settings.left_rating, MAX_RATING);
+        assert_eq!(settings.right_rating, 1200);
+    }
+
+    #[test]
+    fn match_games_are_held_to_the_range_of_the_menu() {
+        for (stored, loaded) in [
+            (0, 0),
+            (MAX_MATCH_GAMES, MAX_MATCH_GAMES),
+            (200, MAX_MATCH_GAMES),
+        ] {
+            let settings = Settings {
+                match_games: stored,
+                ..Settings::default()
+            };
+            let mut buf = Vec::new();
+            settings.encode(&mut buf);
+            assert_eq!(Settings::decode(&buf).unwrap().match_games, loaded);
+        }
+    }
+
+    #[cfg(feature = "history")]
+    #[test]
+    fn finished_games_are_read_back() {
+        use crate::history::record;
+
+        let mut history = History::new();
+        for (first, winner) in [
+            (Player::Left, None),
+            (Player::Left, Some(Player::Right)),
+            (Player::Right, Some(Player::Left)),
+        ] {
+            let game_log = GameLog {
+                first,
+                winner,
+                moves: 300,
+                left_time: Duration::from_millis(5_999_000),
+                right_time: Duration::from_millis(0),
+            };
+            record(&mut history, game_log);
+        }
+        let flash = FakeFlash::new(PAGES);
+        open(&flash).save(&history).unwrap();
+        assert!(open(&flash).load::<History>().unwrap() == Some(history));
+    }
+
+    #[cfg(feature = "table")]
+    #[test]
+    fn table_games_are_read_back() {
+        use crate::{menu::GameConfig, table::TableState};
+
+        let mut config = GameConfig {
+            players: 5,
+            ..GameConfig::default()
+        };
+        config.left.increment_type = IncrementType::Delay(Duration::from_secs(3));
+        let mut table = TableState::new(&config);
+        table.turn = 3;
+        table.times[4] = Duration::from_millis(1234);
+        table.delay = Duration::from_millis(1500);
+        let snapshot = TableSnapshot::of(&table, &config);
+        assert!(snapshot.is_some());
+
+        let flash = FakeFlash::new(PAGES);
+        let mut storage = open(&flash);
+        storage.save(&snapshot).unwrap();
+        let loaded = open(&flash).load::<Option<TableSnapshot>>().unwrap();
+        assert!(loaded == Some(snapshot));
+
+        storage.save(&None::<TableSnapshot>).unwrap();
+        let loaded = open(&flash).load::<Option<TableSnapshot>>().unwrap();
+        assert!(loaded == Some(None));
+
+        // A player to move who isn't at the table
+        let mut payload = Vec::new();
+        TableSnapshot::of(&table, &config).encode(&mut payload);
+        payload[1] = 5;
+        assert!(Option::<TableSnapshot>::decode(&payload).is_none());
+
+        // Fewer players than a table game has, or more than the menu offers
+        for players in [2, MAX_PLAYERS + 1] {
+            payload[0] = players;
+            payload[1] = 0;
+            assert!(Option::<TableSnapshot>::decode(&payload).is_none());
+        }
+    }
+}
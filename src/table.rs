@@ -0,0 +1,266 @@
+//! Timer for board games with three to eight players taking turns round the table.
+//!
+//! Every player has their own time bank and plays the left time control of the game config, with
+//! its increment or delay applying to each of their turns. The menu only edits the two controls of
+//! a chess game, and board games give everyone the same time, so the seats share the left one
+//! rather than taking controls nobody can set. Either player button ends the turn of the player to
+//! move and hands over to the next one round the table.
+//!
+//! A table game is saved to flash on a power failure and offered for resuming like a chess game.
+//! Eight time banks don't fit in the backup registers, so unlike a chess game it doesn't come back
+//! after a reset.
+
+use core::fmt::Write;
+
+use embassy_time::Duration;
+use heapless::String;
+
+use crate::{
+    app::{Button, Event, Page, PressType},
+    aux::{format_secs, CeilTime},
+    display::Frame,
+    effect::Effects,
+    error::Error,
+    menu::{GameConfig, IncrementType, MenuState, TimeControl, MAX_PLAYERS},
+};
+
+/// Fewest players round the table, two play a chess game
+pub const MIN_PLAYERS: u8 = 3;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct TableState {
+    pub players: u8,
+    /// Player to move, counting from 0
+    pub turn: u8,
+    pub times: [Duration; MAX_PLAYERS as usize],
+    pub paused: bool,
+    pub delay: Duration,
+    /// Player who ran out of time, which ends the game
+    pub flagged: Option<u8>,
+}
+
+/// Table game to resume, with the control it was played with
+#[derive(Clone, PartialEq, Eq)]
+pub struct TableSnapshot {
+    pub table: TableState,
+    pub control: TimeControl,
+}
+
+impl TableSnapshot {
+    /// The game on the page, unless it is over
+    pub fn of(table: &TableState, game_config: &GameConfig) -> Option<TableSnapshot> {
+        table.flagged.is_none().then(|| TableSnapshot {
+            table: table.clone(),
+            control: game_config.left,
+        })
+    }
+
+    /// Puts the control of the game back into the config it is resumed with
+    pub fn restore_control(&self, game_config: &mut GameConfig) {
+        game_config.left = self.control;
+    }
+
+    /// The resumed game starts paused
+    pub fn to_table_state(&self) -> TableState {
+        TableState {
+            paused: true,
+            ..self.table.clone()
+        }
+    }
+}
+
+impl TableState {
+    pub fn new(game_config: &GameConfig) -> TableState {
+        TableState {
+            players: game_config.players.clamp(MIN_PLAYERS, MAX_PLAYERS),
+            turn: 0,
+            times: [game_config.left.time; MAX_PLAYERS as usize],
+            paused: true,
+            delay: game_config.left.increment_type.turn_delay(),
+            flagged: None,
+        }
+    }
+
+    /// Player after the one to move, round the table
+    pub fn next(&self) -> u8 {
+        (self.turn + 1) % self.players
+    }
+
+    pub fn handle_event(&mut self, effects: &mut Effects, game_config: &GameConfig, event: &Event) {
+        if self.flagged.is_some() {
+            match event {
+                Event::ButtonPushed(Button::Left | Button::Right, _) => {
+                    *self = TableState::new(game_config)
+                }
+                Event::ButtonPushed(Button::Control, PressType::Single) => {
+                    effects.page_change(Page::Menu(MenuState::new()))
+                }
+                _ => {}
+            }
+            return;
+        }
+        match event {
+            Event::ButtonPushed(Button::Left | Button::Right, _) => {
+                if self.paused {
+                    self.paused = false;
+                    effects.set_clock(true);
+                } else {
+                    let time = &mut self.times[self.turn as usize];
+                    match game_config.left.increment_type {
+                        IncrementType::Increment(increment) => *time += increment,
+                        IncrementType::Bronstein(delay) => *time += delay - self.delay,
+                        IncrementType::SuddenDeath | IncrementType::Delay(_) => {}
+                    }
+                    self.turn = self.next();
+                    self.delay = game_config.left.increment_type.turn_delay();
+                    effects.buzz(220, Duration::from_millis(50));
+                }
+            }
+            Event::ButtonPushed(Button::Control, PressType::Single) => {
+                self.paused = !self.paused;
+                effects.set_clock(!self.paused);
+            }
+            Event::Clock(duration) if !self.paused => {
+                let zero = Duration::from_ticks(0);
+                let in_delay = self.delay.as_ticks() != 0;
+                self.delay = self.delay.checked_sub(*duration).unwrap_or(zero);
+                // The time only runs during a simple delay once the delay is used up
+                if in_delay && matches!(game_config.left.increment_type, IncrementType::Delay(_)) {
+                    return;
+                }
+                let time = &mut self.times[self.turn as usize];
+                *time = time.checked_sub(*duration).unwrap_or(zero);
+                if time.as_ticks() == 0 {
+                    self.flagged = Some(self.turn);
+                    effects.buzz(440, Duration::from_millis(500));
+                    effects.set_clock(false);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The player to move and their time on the first row, the next player on the second
+    pub fn view(&self) -> Result<Frame, Error> {
+        let mut frame = Frame::new();
+        let mut text: String<16> = String::new();
+        if let Some(player) = self.flagged {
+            write!(&mut text, "Player {}", player + 1)?;
+            frame.print(0, 0, &text);
+            frame.print(1, 0, "timeout :(");
+            return Ok(frame);
+        }
+        write!(&mut text, "Player {}", self.turn + 1)?;
+        frame.print(0, 0, &text);
+        let time = self.times[self.turn as usize].ceil_secs();
+        frame.print(0, 11, format_secs(time)?.as_str());
+
+        text.clear();
+        if self.paused {
+            write!(&mut text, "paused")?;
+        } else {
+            write!(&mut text, "next {}", self.next() + 1)?;
+        }
+        frame.print(1, 0, &text);
+        let next_time = self.times[self.next() as usize].ceil_secs();
+        frame.print(1, 11, format_secs(next_time)?.as_str());
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app::AppState, testing::app_state};
+
+    fn config(players: u8) -> GameConfig {
+        GameConfig {
+            left: TimeControl {
+                time: Duration::from_secs(60),
+                increment_type: IncrementType::Increment(Duration::from_secs(2)),
+            },
+            players,
+            ..GameConfig::default()
+        }
+    }
+
+    fn press(state: &mut TableState, config: &GameConfig, button: Button) {
+        let mut effects = Effects::new();
+        state.handle_event(
+            &mut effects,
+            config,
+            &Event::ButtonPushed(button, PressType::Single),
+        );
+    }
+
+    fn tick(state: &mut TableState, config: &GameConfig, secs: u64) {
+        let mut effects = Effects::new();
+        state.handle_event(
+            &mut effects,
+            config,
+            &Event::Clock(Duration::from_secs(secs)),
+        );
+    }
+
+    #[test]
+    fn the_turn_goes_round_the_table() {
+        let config = config(3);
+        let mut table = TableState::new(&config);
+        press(&mut table, &config, Button::Left);
+        assert!(!table.paused);
+        for turn in [1, 2, 0, 1] {
+            tick(&mut table, &config, 5);
+            // Either button passes the turn on
+            press(&mut table, &config, Button::Right);
+            assert_eq!(table.turn, turn);
+        }
+        assert_eq!(table.times[0], Duration::from_secs(54));
+        assert_eq!(table.times[2], Duration::from_secs(57));
+    }
+
+    #[test]
+    fn the_game_ends_when_a_player_runs_out_of_time() {
+        let config = config(4);
+        let mut table = TableState::new(&config);
+        press(&mut table, &config, Button::Left);
+        press(&mut table, &config, Button::Left);
+        tick(&mut table, &config, 61);
+        assert_eq!(table.flagged, Some(1));
+        assert!(TableSnapshot::of(&table, &config).is_none());
+        let frame = table.view().unwrap();
+        assert_eq!(&frame.cells[0][..8], b"Player 2");
+    }
+
+    fn resumed(mut state: AppState) -> AppState {
+        let mut effects = Effects::new();
+        state.handle_event(
+            &mut effects,
+            Event::ButtonPushed(Button::Control, PressType::Single),
+        );
+        state
+    }
+
+    #[test]
+    fn resuming_restores_the_table_and_its_control() {
+        let played = config(6);
+        let mut table = TableState::new(&played);
+        table.turn = 4;
+        table.paused = false;
+        table.times[4] = Duration::from_secs(10);
+
+        let mut state = app_state();
+        state.resumable_table = TableSnapshot::of(&table, &played);
+        assert_eq!(&state.view().unwrap().cells[0][2..14], b"Resume game?");
+        let state = resumed(state);
+
+        let Page::Table(ref resumed) = state.page else {
+            panic!("The table game wasn't resumed");
+        };
+        assert_eq!(resumed.players, 6);
+        assert_eq!(resumed.turn, 4);
+        assert_eq!(resumed.times[4], Duration::from_secs(10));
+        assert!(resumed.paused);
+        assert!(state.game_config.left == played.left);
+        assert!(state.resumable_table.is_none());
+    }
+}
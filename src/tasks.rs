@@ -0,0 +1,773 @@
+#[cfg(feature = "bus")]
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use defmt::{info, warn};
+use embassy_futures::select::{select, Either};
+#[cfg(any(feature = "usb", not(feature = "link")))]
+use embassy_sync::pubsub::Subscriber;
+use embassy_sync::{
+    blocking_mutex::raw::RawMutex,
+    channel::{Receiver, Sender},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer, WithTimeout};
+use embedded_hal_async::digital::Wait;
+use embedded_io_async::{Read, Write};
+
+#[cfg(feature = "link")]
+use crate::link::{self, LinkMessage};
+#[cfg(any(feature = "usb", not(feature = "link")))]
+use crate::protocol::Telemetry;
+#[cfg(any(feature = "usb", not(any(feature = "dgt", feature = "link"))))]
+use crate::protocol::{self, LineBuffer};
+#[cfg(feature = "bus")]
+use crate::protocol::{Address, Command};
+use crate::{
+    app::{AppState, Button, Event, PressType},
+    error::Error,
+};
+#[cfg(feature = "dgt")]
+use crate::{
+    dgt::{self, ClockCommand, HostCommand},
+    protocol::{Command, PageKind, Status},
+};
+
+/// Time the button is ignored after being pushed down
+pub const DEBOUNCE_TIME: Duration = Duration::from_millis(200);
+/// Time the button is ignored after being released
+pub const RELEASE_TIME: Duration = Duration::from_millis(100);
+/// Presses held longer than this are long presses
+pub const LONG_PRESS_TIME: Duration = Duration::from_millis(300);
+/// Interval of the clock ticks while the clock is running
+pub const TICK: Duration = Duration::from_millis(1000);
+/// Time to stay awake after a wake up, long enough for a button press to come through
+pub const WAKE_TIME: Duration = Duration::from_millis(1000);
+
+/// Hardware side effects of going to sleep and waking up.
+///
+/// Kept behind a trait so the sleep logic can be driven with simulated outputs.
+pub trait SleepControl {
+    async fn sleep(&mut self) -> Result<(), Error>;
+    /// Enters the low power state, returns once something woke the MCU up. Builds with a
+    /// peripheral that must keep running return at once, the caller then waits for an event.
+    async fn stop(&mut self);
+    async fn wake(&mut self, state: &AppState) -> Result<(), Error>;
+}
+
+pub fn classify_press(held: Duration) -> PressType {
+    if held > LONG_PRESS_TIME {
+        PressType::Long
+    } else {
+        PressType::Single
+    }
+}
+
+pub async fn handle_button<M: RawMutex, const N: usize>(
+    tx: Sender<'_, M, Event, N>,
+    mut input: impl Wait,
+    button: Button,
+) {
+    loop {
+        let _ = input.wait_for_low().await;
+        let instant = Instant::now();
+        Timer::after(DEBOUNCE_TIME).await;
+
+        let _ = input.wait_for_high().await;
+        let press_type = classify_press(instant.elapsed());
+        info!("Button pushed: {} {}", button, press_type);
+
+        tx.send(Event::ButtonPushed(button, press_type)).await;
+        Timer::after(RELEASE_TIME).await;
+    }
+}
+
+/// Turns the commands received on a serial port into events and sends the telemetry back
+#[cfg(any(
+    feature = "usb",
+    not(any(feature = "dgt", feature = "link", feature = "bus"))
+))]
+pub async fn handle_serial<
+    M: RawMutex,
+    const N: usize,
+    const CAP: usize,
+    const SUBS: usize,
+    const PUBS: usize,
+>(
+    tx: Sender<'_, M, Event, N>,
+    mut port: impl Read + Write,
+    mut telemetry: Subscriber<'_, M, Telemetry, CAP, SUBS, PUBS>,
+) {
+    let mut lines = LineBuffer::new();
+    let mut buf = [0; 16];
+    loop {
+        match select(port.read(&mut buf), telemetry.next_message_pure()).await {
+            Either::First(Ok(len)) => {
+                for byte in &buf[..len] {
+                    match lines.push(*byte) {
+                        Some(Ok(command)) => {
+                            send_line(&mut port, &Telemetry::Ok).await;
+                            tx.send(Event::Command(command)).await;
+                        }
+                        Some(Err(err)) => send_line(&mut port, &Telemetry::Error(err)).await,
+                        None => {}
+                    }
+                }
+            }
+            Either::First(Err(_)) => warn!("Serial receive error"),
+            Either::Second(telemetry) => send_line(&mut port, &telemetry).await,
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "usb",
+    not(any(feature = "dgt", feature = "link", feature = "bus"))
+))]
+async fn send_line(port: &mut impl Write, telemetry: &Telemetry) {
+    let line = protocol::encode(telemetry);
+    if port.write_all(line.as_bytes()).await.is_err() {
+        warn!("Serial send error");
+    }
+}
+
+/// Speaks the serial protocol on a shared bus as the clock at the given address.
+///
+/// Only `STATE`, `PRESETS` and `GAMES` get their telemetry sent, as answers, so the bus stays free
+/// for the other clocks.
+#[cfg(feature = "bus")]
+pub async fn handle_bus<
+    M: RawMutex,
+    const N: usize,
+    const CAP: usize,
+    const SUBS: usize,
+    const PUBS: usize,
+>(
+    tx: Sender<'_, M, Event, N>,
+    mut port: impl Read + Write,
+    mut telemetry: Subscriber<'_, M, Telemetry, CAP, SUBS, PUBS>,
+    address: &AtomicU8,
+) {
+    let mut lines = LineBuffer::new();
+    // STATE lines owed, PRESET and GAME lines are sent until the next of them
+    let mut states = 0u8;
+    let mut lists = false;
+    let mut buf = [0; 16];
+    loop {
+        let received = select(port.read(&mut buf), telemetry.next_message_pure()).await;
+        // Read after the wait, the address can change in the menu meanwhile
+        let own = Address::Clock(address.load(Ordering::Relaxed));
+        match received {
+            Either::First(Ok(len)) => {
+                for byte in &buf[..len] {
+                    match lines.push_addressed(*byte) {
+                        Some((Address::All, Ok(command))) => tx.send(Event::Command(command)).await,
+                        Some((to, Ok(command))) if to == own => {
+                            match command {
+                                Command::State => states = states.saturating_add(1),
+                                Command::ListPresets | Command::ListGames => lists = true,
+                                _ => {}
+                            }
+                            send_addressed(&mut port, own, &Telemetry::Ok).await;
+                            tx.send(Event::Command(command)).await;
+                        }
+                        Some((to, Err(err))) if to == own => {
+                            send_addressed(&mut port, own, &Telemetry::Error(err)).await
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Either::First(Err(_)) => warn!("Bus receive error"),
+            Either::Second(Telemetry::State(status)) if states > 0 => {
+                states -= 1;
+                lists = false;
+                send_addressed(&mut port, own, &Telemetry::State(status)).await;
+            }
+            Either::Second(listed @ (Telemetry::Preset(..) | Telemetry::Game(..))) if lists => {
+                send_addressed(&mut port, own, &listed).await
+            }
+            Either::Second(_) => {}
+        }
+    }
+}
+
+/// Sends the line and waits until it is out, so the bus is released right after it
+#[cfg(feature = "bus")]
+async fn send_addressed(port: &mut impl Write, address: Address, telemetry: &Telemetry) {
+    let line = protocol::with_address(address, &protocol::encode(telemetry));
+    if port.write_all(line.as_bytes()).await.is_err() || port.flush().await.is_err() {
+        warn!("Bus send error");
+    }
+}
+
+/// Acts as a DGT board with a DGT3000 clock on a serial port, for software made for those
+#[cfg(feature = "dgt")]
+pub async fn handle_dgt<
+    M: RawMutex,
+    const N: usize,
+    const CAP: usize,
+    const SUBS: usize,
+    const PUBS: usize,
+>(
+    tx: Sender<'_, M, Event, N>,
+    mut port: impl Read + Write,
+    mut telemetry: Subscriber<'_, M, Telemetry, CAP, SUBS, PUBS>,
+) {
+    let mut decoder = dgt::Decoder::new();
+    let mut status: Option<Status> = None;
+    // Time messages are only sent unasked once the host chose an update mode
+    let mut updates = false;
+    let mut buf = [0; 16];
+    // The time message needs the state from the start
+    tx.send(Event::Command(Command::State)).await;
+    loop {
+        match select(port.read(&mut buf), telemetry.next_message_pure()).await {
+            Either::First(Ok(len)) => {
+                for byte in &buf[..len] {
+                    let Some(command) = decoder.push(*byte) else {
+                        continue;
+                    };
+                    match command {
+                        HostCommand::Reset => updates = false,
+                        HostCommand::SendClock => {
+                            if let Some(ref status) = status {
+                                send_bytes(&mut port, &dgt::time_message(status)).await;
+                            }
+                        }
+                        HostCommand::SendBoard => send_bytes(&mut port, &dgt::board_dump()).await,
+                        HostCommand::SendUpdates => updates = true,
+                        HostCommand::SendSerialNumber => {
+                            send_bytes(&mut port, &dgt::serial_number_message()).await
+                        }
+                        HostCommand::SendVersion => {
+                            send_bytes(&mut port, &dgt::version_message()).await
+                        }
+                        HostCommand::Clock(clock_command) => {
+                            send_bytes(&mut port, &dgt::clock_ack(&clock_command)).await;
+                            let command = match clock_command {
+                                ClockCommand::SetAndRun {
+                                    left_secs,
+                                    right_secs,
+                                    running,
+                                } => Command::Set {
+                                    left_secs,
+                                    right_secs,
+                                    running,
+                                },
+                                ClockCommand::Beep { millis } => Command::Beep(millis),
+                                ClockCommand::Version | ClockCommand::Other(_) => continue,
+                            };
+                            tx.send(Event::Command(command)).await;
+                        }
+                    }
+                }
+            }
+            Either::First(Err(_)) => warn!("Serial receive error"),
+            Either::Second(
+                Telemetry::State(next) | Telemetry::Turn(next) | Telemetry::Tick(next),
+            ) => {
+                let prev = status.replace(next);
+                if !updates {
+                    continue;
+                }
+                if let Some(prev) = prev {
+                    let in_game = prev.page == PageKind::Game && next.page == PageKind::Game;
+                    if in_game && prev.turn != next.turn {
+                        send_bytes(&mut port, &dgt::lever_ack(prev.turn)).await;
+                    }
+                    if in_game && prev.paused != next.paused {
+                        send_bytes(&mut port, &dgt::play_pause_ack()).await;
+                    }
+                }
+                send_bytes(&mut port, &dgt::time_message(&next)).await;
+            }
+            Either::Second(_) => {}
+        }
+    }
+}
+
+/// Carries the messages between this clock and its bughouse partner over a serial port
+#[cfg(feature = "link")]
+pub async fn handle_link<M: RawMutex, const N: usize, const LINKS: usize>(
+    tx: Sender<'_, M, Event, N>,
+    mut port: impl Read + Write,
+    outgoing: Receiver<'_, M, LinkMessage, LINKS>,
+) {
+    let mut decoder = link::Decoder::new();
+    let mut buf = [0; 16];
+    loop {
+        match select(port.read(&mut buf), outgoing.receive()).await {
+            Either::First(Ok(len)) => {
+                for byte in &buf[..len] {
+                    if let Some(message) = decoder.push(*byte) {
+                        tx.send(Event::Link(message)).await;
+                    }
+                }
+            }
+            Either::First(Err(_)) => warn!("Link receive error"),
+            Either::Second(message) => {
+                let line = link::encode(&message);
+                if port.write_all(line.as_bytes()).await.is_err() {
+                    warn!("Link send error");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "dgt")]
+async fn send_bytes(port: &mut impl Write, bytes: &[u8]) {
+    if port.write_all(bytes).await.is_err() {
+        warn!("Serial send error");
+    }
+}
+
+pub async fn emit_clock<M: RawMutex, const N: usize>(
+    tx: Sender<'_, M, Event, N>,
+    clock_signal: &Signal<M, bool>,
+) {
+    loop {
+        if clock_signal.wait().await {
+            loop {
+                let clock = clock_signal.wait().with_timeout(TICK).await;
+
+                if let Ok(false) = clock {
+                    break;
+                }
+
+                tx.send(Event::Clock(TICK)).await;
+            }
+        }
+    }
+}
+
+pub async fn receive_event_or_sleep<M: RawMutex, const N: usize>(
+    rx: Receiver<'_, M, Event, N>,
+    sleep_control: &mut impl SleepControl,
+    state: &AppState,
+) -> Result<Event, Error> {
+    let Some(time_until_sleep) = state.sleep_timeout() else {
+        let event = rx.receive().await;
+        info!("Event received: {}", event);
+        return Ok(event);
+    };
+    let mut event;
+    loop {
+        event = rx.receive().with_timeout(time_until_sleep).await;
+        info!("Event received: {}", event);
+
+        // Sleep after some time of inactivity
+        match event {
+            Ok(event) => {
+                return Ok(event);
+            }
+            Err(_) => {
+                sleep_control.sleep().await?;
+
+                info!("Sleep");
+                let event = loop {
+                    sleep_control.stop().await;
+                    // Wake ups without a button press end up here too, such as a bouncing contact
+                    if let Ok(event) = rx.receive().with_timeout(WAKE_TIME).await {
+                        break event;
+                    }
+                };
+                info!("Event received: {}", event);
+
+                sleep_control.wake(state).await?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use embassy_futures::{block_on, join::join};
+    use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, signal::Signal};
+
+    use super::*;
+    use crate::{
+        display::{Renderer, Screen},
+        lcd::Lcd,
+        testing::{advance, app_state, lock_time, run, settle, FakeLcd, FakePin, LcdOp, NoDelay},
+    };
+
+    #[test]
+    fn presses_longer_than_the_long_press_time_are_long() {
+        assert!(matches!(classify_press(LONG_PRESS_TIME), PressType::Single));
+        assert!(matches!(
+            classify_press(LONG_PRESS_TIME + Duration::from_millis(1)),
+            PressType::Long
+        ));
+    }
+
+    #[test]
+    fn button_presses_are_classified_on_release() {
+        let _time = lock_time();
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let level = Cell::new(true);
+        let task = handle_button(channel.sender(), FakePin(&level), Button::Left);
+        let presses = run(task, async {
+            let mut presses = Vec::new();
+            for held in [250, 600] {
+                level.set(false);
+                advance(Duration::from_millis(held)).await;
+                level.set(true);
+                advance(RELEASE_TIME).await;
+                presses.push(channel.try_receive());
+            }
+            presses
+        });
+        assert!(matches!(
+            presses[..],
+            [
+                Ok(Event::ButtonPushed(Button::Left, PressType::Single)),
+                Ok(Event::ButtonPushed(Button::Left, PressType::Long)),
+            ]
+        ));
+    }
+
+    #[test]
+    fn bounces_after_a_press_are_ignored() {
+        let _time = lock_time();
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let level = Cell::new(true);
+        let task = handle_button(channel.sender(), FakePin(&level), Button::Right);
+        let events = run(task, async {
+            level.set(false);
+            advance(Duration::from_millis(20)).await;
+            // Contact bouncing within the debounce time
+            level.set(true);
+            advance(Duration::from_millis(20)).await;
+            level.set(false);
+            advance(Duration::from_millis(200)).await;
+            level.set(true);
+            advance(RELEASE_TIME).await;
+            channel.len()
+        });
+        assert_eq!(events, 1);
+    }
+
+    #[test]
+    fn the_clock_ticks_every_second_while_running() {
+        let _time = lock_time();
+        let channel: Channel<NoopRawMutex, Event, 8> = Channel::new();
+        let clock: Signal<NoopRawMutex, bool> = Signal::new();
+        let task = emit_clock(channel.sender(), &clock);
+        let (running, stopped) = run(task, async {
+            clock.signal(true);
+            settle().await;
+            advance(TICK * 3 + TICK / 2).await;
+            let running = channel.len();
+            clock.signal(false);
+            settle().await;
+            channel.clear();
+            advance(TICK * 3).await;
+            (running, channel.len())
+        });
+        assert_eq!(running, 3);
+        assert_eq!(stopped, 0);
+    }
+
+    #[cfg(any(
+        feature = "usb",
+        not(any(feature = "dgt", feature = "link", feature = "bus"))
+    ))]
+    #[test]
+    fn serial_commands_are_answered_and_sent_on() {
+        use embassy_sync::pubsub::PubSubChannel;
+
+        use crate::{
+            protocol::{Command, Side},
+            testing::FakePort,
+        };
+
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let telemetry: PubSubChannel<NoopRawMutex, Telemetry, 4, 1, 1> = PubSubChannel::new();
+        let port = FakePort::default();
+        let task = handle_serial(
+            channel.sender(),
+            port.clone(),
+            telemetry.subscriber().unwrap(),
+        );
+        run(task, async {
+            // Split across reads like the bytes coming in over USB
+            port.send(b"START L\r\nJU");
+            settle().await;
+            port.send(b"MP\n\nSET 6000 1\n");
+            settle().await;
+            assert_eq!(
+                port.take(),
+                "OK\nERR,unknown command\nERR,invalid argument\n"
+            );
+            assert!(matches!(
+                channel.try_receive(),
+                Ok(Event::Command(Command::Start(Side::Left)))
+            ));
+            assert!(channel.try_receive().is_err());
+        });
+    }
+
+    #[cfg(any(
+        feature = "usb",
+        not(any(feature = "dgt", feature = "link", feature = "bus"))
+    ))]
+    #[test]
+    fn telemetry_is_written_to_the_serial_port() {
+        use embassy_sync::pubsub::PubSubChannel;
+
+        use crate::{protocol::Side, testing::FakePort};
+
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let telemetry: PubSubChannel<NoopRawMutex, Telemetry, 4, 1, 1> = PubSubChannel::new();
+        let port = FakePort::default();
+        let task = handle_serial(
+            channel.sender(),
+            port.clone(),
+            telemetry.subscriber().unwrap(),
+        );
+        run(task, async {
+            let publisher = telemetry.immediate_publisher();
+            publisher.publish_immediate(Telemetry::Flag(Side::Right));
+            publisher.publish_immediate(Telemetry::Result(Side::Left));
+            settle().await;
+            assert_eq!(port.take(), "FLAG,R\nRESULT,L\n");
+        });
+    }
+
+    #[cfg(feature = "bus")]
+    #[test]
+    fn only_lines_for_the_clock_are_answered_on_the_bus() {
+        use core::sync::atomic::AtomicU8;
+
+        use embassy_sync::pubsub::PubSubChannel;
+
+        use crate::{
+            protocol::{Command, PageKind, Side, Status},
+            testing::FakePort,
+        };
+
+        let channel: Channel<NoopRawMutex, Event, 4> = Channel::new();
+        let telemetry: PubSubChannel<NoopRawMutex, Telemetry, 4, 1, 1> = PubSubChannel::new();
+        let port = FakePort::default();
+        let address = AtomicU8::new(3);
+        let task = handle_bus(
+            channel.sender(),
+            port.clone(),
+            telemetry.subscriber().unwrap(),
+            &address,
+        );
+        run(task, async {
+            port.send(b"@3 START L\n@2 PAUSE\n@* PAUSE\n@3 JUMP\n@4 JUMP\nRESUME\n");
+            settle().await;
+            // Broadcasts are carried out without an answer, the other addresses ignored
+            assert_eq!(port.take(), "@3 OK\n@3 ERR,unknown command\n");
+            assert!(matches!(
+                channel.try_receive(),
+                Ok(Event::Command(Command::Start(Side::Left)))
+            ));
+            assert!(matches!(
+                channel.try_receive(),
+                Ok(Event::Command(Command::Pause))
+            ));
+            assert!(channel.try_receive().is_err());
+
+            // Only the state asked for is sent
+            let status = Status {
+                page: PageKind::Game,
+                turn: Side::Left,
+                paused: true,
+                left_ms: 1000,
+                right_ms: 2000,
+                delay_ms: 0,
+            };
+            let publisher = telemetry.immediate_publisher();
+            publisher.publish_immediate(Telemetry::State(status));
+            settle().await;
+            assert_eq!(port.take(), "");
+            port.send(b"@3 STATE\n");
+            settle().await;
+            publisher.publish_immediate(Telemetry::Tick(status));
+            publisher.publish_immediate(Telemetry::State(status));
+            settle().await;
+            assert_eq!(port.take(), "@3 OK\n@3 STATE,GAME,L,1,1000,2000,0\n");
+
+            // A new address set in the menu holds from the next line
+            address.store(4, Ordering::Relaxed);
+            port.send(b"@3 PAUSE\n@4 PAUSE\n");
+            settle().await;
+            assert_eq!(port.take(), "@4 OK\n");
+        });
+    }
+
+    #[derive(Default)]
+    struct FakeSleep {
+        calls: Vec<&'static str>,
+        /// Whether waking up fails, like a lost display
+        failing: bool,
+    }
+
+    impl SleepControl for FakeSleep {
+        async fn sleep(&mut self) -> Result<(), Error> {
+            self.calls.push("sleep");
+            Ok(())
+        }
+
+        async fn stop(&mut self) {
+            self.calls.push("stop");
+        }
+
+        async fn wake(&mut self, _state: &AppState) -> Result<(), Error> {
+            self.calls.push("wake");
+            if self.failing {
+                return Err(Error::I2cError(embedded_hal_async::i2c::ErrorKind::Bus));
+            }
+            Ok(())
+        }
+    }
+
+    /// Switches the display off and redraws the page on waking up, like the clock does
+    struct DisplaySleep(Renderer<FakeLcd, NoDelay>);
+
+    impl SleepControl for DisplaySleep {
+        async fn sleep(&mut self) -> Result<(), Error> {
+            self.0.show(&Screen::Off).await
+        }
+
+        async fn stop(&mut self) {}
+
+        async fn wake(&mut self, state: &AppState) -> Result<(), Error> {
+            self.0.show(&Screen::On(state.view()?)).await
+        }
+    }
+
+    /// Sleeps with nothing happening, then wakes the clock up and sends a press
+    async fn sleep_and_press(channel: &Channel<NoopRawMutex, Event, 3>, idle_timeout: Duration) {
+        advance(idle_timeout + Duration::from_millis(100)).await;
+        // Only wakes the clock up
+        channel
+            .send(Event::ButtonPushed(Button::Left, PressType::Single))
+            .await;
+        settle().await;
+        channel
+            .send(Event::ButtonPushed(Button::Right, PressType::Single))
+            .await;
+    }
+
+    #[test]
+    fn the_clock_sleeps_after_the_idle_timeout() {
+        let _time = lock_time();
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let state = app_state();
+        let idle_timeout = state.settings.idle_timeout;
+        let mut sleep_control = FakeSleep::default();
+        let (event, ()) = block_on(join(
+            receive_event_or_sleep(channel.receiver(), &mut sleep_control, &state),
+            sleep_and_press(&channel, idle_timeout),
+        ));
+        assert!(matches!(
+            event,
+            Ok(Event::ButtonPushed(Button::Right, PressType::Single))
+        ));
+        assert_eq!(sleep_control.calls, ["sleep", "stop", "wake"]);
+    }
+
+    #[test]
+    fn a_failed_wake_up_is_returned() {
+        let _time = lock_time();
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let state = app_state();
+        let idle_timeout = state.settings.idle_timeout;
+        let mut sleep_control = FakeSleep {
+            failing: true,
+            ..FakeSleep::default()
+        };
+        let (event, ()) = block_on(join(
+            receive_event_or_sleep(channel.receiver(), &mut sleep_control, &state),
+            sleep_and_press(&channel, idle_timeout),
+        ));
+        assert!(event.is_err());
+        assert_eq!(sleep_control.calls, ["sleep", "stop", "wake"]);
+        // The press after the one waking the clock up is still there
+        assert_eq!(channel.len(), 1);
+    }
+
+    #[test]
+    fn waking_up_redraws_the_page() {
+        let _time = lock_time();
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let state = app_state();
+        let idle_timeout = state.settings.idle_timeout;
+        let lcd = FakeLcd::default();
+        let mut renderer = Renderer::new(Lcd::new(lcd.clone(), NoDelay, 0x27));
+        block_on(renderer.show(&Screen::On(state.view().unwrap()))).unwrap();
+        lcd.take();
+
+        let mut sleep_control = DisplaySleep(renderer);
+        block_on(join(
+            receive_event_or_sleep(channel.receiver(), &mut sleep_control, &state),
+            sleep_and_press(&channel, idle_timeout),
+        ))
+        .0
+        .unwrap_or_else(|_| panic!("No event"));
+
+        let ops = lcd.take();
+        let off = ops.iter().position(|op| *op == LcdOp::Backlight(false));
+        let on = ops.iter().rposition(|op| *op == LcdOp::Backlight(true));
+        assert!(off.is_some_and(|off| on.is_some_and(|on| off < on)));
+        // The whole page again, the display forgot it while off
+        let text: Vec<u8> = ops[on.unwrap()..]
+            .iter()
+            .filter_map(|op| match op {
+                LcdOp::Data(byte) => Some(*byte),
+                _ => None,
+            })
+            .collect();
+        assert!(text.windows(10).any(|window| window == b"ChessClock"));
+    }
+
+    #[test]
+    fn wake_ups_without_an_event_stop_again() {
+        let _time = lock_time();
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let state = app_state();
+        let idle_timeout = state.settings.idle_timeout;
+        let mut sleep_control = FakeSleep::default();
+        block_on(join(
+            receive_event_or_sleep(channel.receiver(), &mut sleep_control, &state),
+            async {
+                advance(idle_timeout + WAKE_TIME * 2 + Duration::from_millis(100)).await;
+                channel.send(Event::Clock(TICK)).await;
+                settle().await;
+                channel.send(Event::Clock(TICK)).await;
+            },
+        ))
+        .0
+        .unwrap_or_else(|_| panic!("No event"));
+        assert_eq!(
+            sleep_control.calls,
+            ["sleep", "stop", "stop", "stop", "wake"]
+        );
+    }
+
+    #[test]
+    fn events_before_the_timeout_keep_the_clock_awake() {
+        let _time = lock_time();
+        let channel: Channel<NoopRawMutex, Event, 3> = Channel::new();
+        let state = app_state();
+        let idle_timeout = state.settings.idle_timeout;
+        let mut sleep_control = FakeSleep::default();
+        block_on(join(
+            receive_event_or_sleep(channel.receiver(), &mut sleep_control, &state),
+            async {
+                advance(idle_timeout - Duration::from_millis(100)).await;
+                channel.send(Event::Clock(TICK)).await;
+            },
+        ))
+        .0
+        .unwrap_or_else(|_| panic!("No event"));
+        assert!(sleep_control.calls.is_empty());
+    }
+}
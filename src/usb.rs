@@ -0,0 +1,219 @@
+use embassy_futures::join::join3;
+use embassy_stm32::{
+    gpio::{Level, Output, Speed},
+    interrupt::typelevel::Binding,
+    peripherals::{PA11, PA12, USB},
+    rcc::{AHBPrescaler, APBPrescaler, Hse, HseMode, Pll, PllMul, PllPreDiv, PllSource, Sysclk},
+    time::Hertz,
+    usb::InterruptHandler,
+};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::{
+    channel::{Receiver, Sender},
+    pubsub::Subscriber,
+};
+use embassy_time::{Duration, Timer, WithTimeout};
+#[cfg(feature = "hid")]
+use embassy_usb::class::hid::HidWriter;
+use embassy_usb::{
+    class::cdc_acm::{CdcAcmClass, State},
+    driver::{Driver, EndpointError},
+    Builder,
+};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+
+#[cfg(feature = "hid")]
+use crate::keyboard;
+use crate::{app::Event, protocol::Telemetry, tasks::handle_serial};
+
+/// Packet size of the bulk endpoints, the most a full speed device allows
+pub const MAX_PACKET_SIZE: u16 = 64;
+/// Longest wait for the host to pick up a packet, the telemetry is dropped while nobody listens
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Runs the core from the 8MHz crystal at 72MHz, the USB peripheral needs its 48MHz clock
+pub fn rcc_config(config: &mut embassy_stm32::Config) {
+    config.rcc.hse = Some(Hse {
+        freq: Hertz::mhz(8),
+        mode: HseMode::Oscillator,
+    });
+    config.rcc.pll = Some(Pll {
+        src: PllSource::HSE,
+        prediv: PllPreDiv::DIV1,
+        mul: PllMul::MUL9,
+    });
+    config.rcc.sys = Sysclk::PLL1_P;
+    config.rcc.ahb_pre = AHBPrescaler::DIV1;
+    config.rcc.apb1_pre = APBPrescaler::DIV2;
+    config.rcc.apb2_pre = APBPrescaler::DIV1;
+}
+
+/// Runs the USB device with a CDC-ACM serial port speaking the serial protocol, and a keyboard
+/// typing the keystrokes with the `hid` feature
+pub async fn run<
+    M: RawMutex,
+    const N: usize,
+    const CAP: usize,
+    const SUBS: usize,
+    const PUBS: usize,
+    const KEYS: usize,
+>(
+    usb: USB,
+    irq: impl Binding<embassy_stm32::interrupt::typelevel::USB_LP_CAN1_RX0, InterruptHandler<USB>>,
+    mut dp: PA12,
+    dm: PA11,
+    tx: Sender<'_, M, Event, N>,
+    telemetry: Subscriber<'_, M, Telemetry, CAP, SUBS, PUBS>,
+    keystrokes: Receiver<'_, M, u8, KEYS>,
+) {
+    // The Blue Pill has a fixed pull up on D+, pulling it low makes the host see a reconnect
+    {
+        let _dp = Output::new(&mut dp, Level::Low, Speed::Low);
+        Timer::after_millis(10).await;
+    }
+    let driver = embassy_stm32::usb::Driver::new(usb, irq, dp, dm);
+
+    let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("ChessClock");
+    config.product = Some("ChessClock serial");
+    config.max_power = 100;
+    // The serial port and the keyboard make a composite device
+    #[cfg(feature = "hid")]
+    {
+        config.device_class = 0xef;
+        config.device_sub_class = 0x02;
+        config.device_protocol = 0x01;
+        config.composite_with_iads = true;
+    }
+
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+    let mut state = State::new();
+    #[cfg(feature = "hid")]
+    let mut hid_state = embassy_usb::class::hid::State::new();
+    let mut builder = Builder::new(
+        driver,
+        config,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut [],
+        &mut control_buf,
+    );
+    let class = CdcAcmClass::new(&mut builder, &mut state, MAX_PACKET_SIZE);
+
+    #[cfg(feature = "hid")]
+    let keyboard = {
+        let config = embassy_usb::class::hid::Config {
+            report_descriptor: &keyboard::REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 10,
+            max_packet_size: keyboard::REPORT_SIZE as u16,
+        };
+        let writer = HidWriter::new(&mut builder, &mut hid_state, config);
+        type_keys(writer, keystrokes)
+    };
+    #[cfg(not(feature = "hid"))]
+    let keyboard = {
+        let _ = keystrokes;
+        core::future::pending::<()>()
+    };
+
+    let mut device = builder.build();
+
+    join3(
+        device.run(),
+        handle_serial(tx, UsbSerial::new(class), telemetry),
+        keyboard,
+    )
+    .await;
+}
+
+/// Presses and releases the keys received from the app
+#[cfg(feature = "hid")]
+async fn type_keys<'d, D: Driver<'d>, M: RawMutex, const N: usize>(
+    mut writer: HidWriter<'d, D, { keyboard::REPORT_SIZE }>,
+    keystrokes: Receiver<'_, M, u8, N>,
+) {
+    loop {
+        let key = keyboard::get(keystrokes.receive().await);
+        writer.ready().await;
+        for report in [keyboard::report(Some(key)), keyboard::report(None)] {
+            if let Err(err) = writer.write(&report).with_timeout(WRITE_TIMEOUT).await {
+                defmt::warn!("Keystroke dropped: {}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// CDC-ACM class as a byte stream, so it can carry the serial protocol like the UART.
+pub struct UsbSerial<'d, D: Driver<'d>> {
+    class: CdcAcmClass<'d, D>,
+    packet: [u8; MAX_PACKET_SIZE as usize],
+    /// Received bytes not read yet, `start..end` of `packet`
+    start: usize,
+    end: usize,
+}
+
+impl<'d, D: Driver<'d>> UsbSerial<'d, D> {
+    pub fn new(class: CdcAcmClass<'d, D>) -> Self {
+        Self {
+            class,
+            packet: [0; MAX_PACKET_SIZE as usize],
+            start: 0,
+            end: 0,
+        }
+    }
+}
+
+impl<'d, D: Driver<'d>> ErrorType for UsbSerial<'d, D> {
+    type Error = ErrorKind;
+}
+
+impl<'d, D: Driver<'d>> Read for UsbSerial<'d, D> {
+    /// Waits for the host, a disconnected port isn't an error
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        while self.start == self.end {
+            self.class.wait_connection().await;
+            match self.class.read_packet(&mut self.packet).await {
+                Ok(len) => {
+                    self.start = 0;
+                    self.end = len;
+                }
+                Err(EndpointError::Disabled) => {}
+                Err(EndpointError::BufferOverflow) => return Err(ErrorKind::OutOfMemory),
+            }
+        }
+        let len = buf.len().min(self.end - self.start);
+        buf[..len].copy_from_slice(&self.packet[self.start..self.start + len]);
+        self.start += len;
+        Ok(len)
+    }
+}
+
+impl<'d, D: Driver<'d>> Write for UsbSerial<'d, D> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        // Without DTR no terminal has the port open
+        if !self.class.dtr() {
+            return Ok(buf.len());
+        }
+        let len = buf.len().min(MAX_PACKET_SIZE as usize);
+        let sent = self
+            .class
+            .write_packet(&buf[..len])
+            .with_timeout(WRITE_TIMEOUT)
+            .await;
+        if let Ok(Ok(())) = sent {
+            // A full packet needs a zero length packet to end the transfer
+            if len == MAX_PACKET_SIZE as usize {
+                let _ = self
+                    .class
+                    .write_packet(&[])
+                    .with_timeout(WRITE_TIMEOUT)
+                    .await;
+            }
+        }
+        Ok(len)
+    }
+}